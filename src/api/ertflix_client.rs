@@ -1,11 +1,29 @@
 use std::error;
+use crate::api::rate_limit::{RateLimitMetrics, RateLimitSnapshot};
+use crate::api::transport::{ReqwestTransport, Transport, TransportResponse};
 use crate::config;
 use crate::models::ertflix;
-use log::{debug, error, info, trace, warn};
-use reqwest::{Client, RequestBuilder};
+use crate::services::cache::{Cache, Lookup};
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use tracing::{debug, error, field, info, instrument, trace, warn, Span};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::time::Duration;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Ertflix section holding the full movies catalog, listed unless configured otherwise
+pub const MOVIES_SECTION_CODENAME: &str = "oles-oi-tainies-1";
+/// Ertflix section holding the complete TV series, listed unless configured otherwise
+pub const TV_SHOWS_SECTION_CODENAME: &str = "ert-seires-plereis";
+/// Id of the section `get_collections` gathers the tiles of sections without a toplist
+/// codename into, when `ertflix.uncategorized_collection` names it. ERTFLIX's own section
+/// ids are positive, so this one never collides and its collection id stays the same.
+pub const UNCATEGORIZED_SECTION_ID: i32 = -1;
+/// Longest delay between retries, however many there were before
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -22,7 +40,7 @@ pub struct SectionContents {
     pub tiles_ids: Option<Vec<Tile>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Tile {
     pub origin_entity_id: i32,
@@ -31,11 +49,118 @@ pub struct Tile {
     pub year: Option<u32>,
     pub description: Option<String>,
     pub title: Option<String>,
+    #[serde(default)]
+    pub images: Vec<TileImage>,
+    /// Where the tile's trailer can be streamed from, when it has one
+    #[serde(default)]
+    pub trailer_url: Option<String>,
+    /// Genres, sent by ERTFLIX as `genres` or `categories`
+    #[serde(default, alias = "categories")]
+    pub genres: Vec<TileGenre>,
+}
+
+/// A genre of a tile, either a bare name or an object carrying it as `title` or `name`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum TileGenre {
+    Name(String),
+    Titled {
+        #[serde(alias = "name")]
+        title: String,
+    },
+}
+
+impl TileGenre {
+    pub fn name(&self) -> &str {
+        match self {
+            TileGenre::Name(name) | TileGenre::Titled { title: name } => name,
+        }
+    }
+}
+
+/// Artwork attached to a tile; `role` says what it is, e.g. `poster`, `photo`, `background` or `logo`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TileImage {
+    pub role: String,
+    pub url: String,
+}
+
+impl Tile {
+    /// Takes any fields this tile is missing from `other`, typically the section
+    /// listing's copy of the same tile when GetTiles returned fewer fields
+    pub fn fill_missing_from(&mut self, other: &Tile) {
+        if self.title.is_none() && other.title.is_some() {
+            debug!("Tile {} has no title in GetTiles, using the section's", self.id);
+            self.title = other.title.clone();
+        }
+        if self.year.is_none() {
+            self.year = other.year;
+        }
+        if self.description.is_none() {
+            self.description = other.description.clone();
+        }
+        if self.images.is_empty() {
+            self.images = other.images.clone();
+        }
+        if self.trailer_url.is_none() {
+            self.trailer_url = other.trailer_url.clone();
+        }
+        if self.genres.is_empty() {
+            self.genres = other.genres.clone();
+        }
+    }
+
+    /// The tile's genre names, trimmed, without blanks and with duplicates dropped
+    /// regardless of case, in the order ERTFLIX lists them
+    pub fn genre_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for genre in &self.genres {
+            let name = genre.name().trim();
+            if !name.is_empty() && !names.iter().any(|known| known.to_lowercase() == name.to_lowercase()) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
 }
 
-pub struct DefaultErtflixClient {
-    pub client: Client,
-    pub base_url: String,
+pub struct DefaultErtflixClient<Tr: Transport = ReqwestTransport> {
+    pub transport: Tr,
+    /// The primary base URL followed by its fallbacks, in the order they are tried
+    pub base_urls: Vec<String>,
+    /// Index into `base_urls` of the last host that answered
+    active_base_url: AtomicUsize,
+    /// Keep the complete tiles of a truncated GetTiles response instead of failing
+    pub best_effort_tiles: bool,
+    /// Platform ERTFLIX serves content for, sent as `platformCodename`
+    pub platform_codename: String,
+    /// Tag items with the codename of the section they came from
+    pub tag_source_sections: bool,
+    /// Most tile ids requested in one GetTiles call
+    pub tile_batch_size: usize,
+    /// Most GetTiles batches in flight at once
+    pub max_concurrent_requests: usize,
+    /// Keep the tiles of the batches that succeeded when others fail, instead of failing
+    pub partial_tile_batches: bool,
+    /// How often a request failing with a connection error, timeout or 5xx is retried
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub retry_base_delay: Duration,
+    /// Sections whose tiles make up the movies, in order; swapped when the configuration is reloaded
+    pub movies_section_codenames: RwLock<Vec<String>>,
+    /// Sections whose tiles make up the TV shows, in order; swapped when the configuration is reloaded
+    pub tv_shows_section_codenames: RwLock<Vec<String>>,
+    /// Name of the collection of tiles in sections without a toplist codename, which are dropped when unset
+    pub uncategorized_collection: RwLock<Option<String>>,
+    /// Last body and validators of each GET endpoint that sent validators, keyed by path
+    validated_responses: Mutex<HashMap<String, TransportResponse>>,
+    /// Rate limit headers seen on Ertflix responses, reported on `/metrics`
+    rate_limits: RateLimitMetrics,
+    /// Requests that got no answer, reported on `/metrics`
+    failures: Mutex<UpstreamFailures>,
+    /// Where fetched tile details are kept, and for how long
+    tile_cache: Option<(Arc<Cache>, Duration)>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -51,19 +176,95 @@ struct RequestedTile {
     id: String,
 }
 
+/// A list returned by ERTFLIX, accepted both as a bare array and wrapped in a `data` envelope
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ListPayload<T> {
+    Bare(Vec<T>),
+    Enveloped { data: Vec<T> },
+}
+
+impl<T> ListPayload<T> {
+    fn into_items(self) -> Vec<T> {
+        match self {
+            ListPayload::Bare(items) => items,
+            ListPayload::Enveloped { data } => {
+                trace!("Unwrapping enveloped list of {} items", data.len());
+                data
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AcquireContentResponse {
+    #[serde(default)]
+    media_files: Vec<MediaFile>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MediaFile {
+    #[serde(default)]
+    formats: Vec<MediaFormat>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MediaFormat {
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SeriesDetailsResponse {
+    #[serde(default)]
+    episodes: Vec<SeriesEpisode>,
+}
+
+/// An episode as listed in a series' details; ERTFLIX leaves out numbers it doesn't know
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SeriesEpisode {
+    id: String,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    season_number: Option<u32>,
+    #[serde(default)]
+    episode_number: Option<u32>,
+    #[serde(default, alias = "durationSeconds")]
+    duration: Option<u32>,
+}
+
 #[derive(Debug)]
 pub enum Error {
     Request(reqwest::Error),
+    /// ERTFLIX did not answer within `TIMEOUT_SECONDS`
+    Timeout(String),
     Parse(serde_json::Error),
     Custom(String),
+    /// ERTFLIX answered, but has nothing under the requested id
+    NotFound(String),
+    /// ERTFLIX answered with an HTML page instead of JSON, which it serves to addresses
+    /// outside Greece. Holds the `Content-Type` of the answer, if it had one.
+    GeoBlocked(Option<String>),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::Request(e) => write!(f, "Request error: {}", e),
+            Error::Timeout(e) => write!(f, "Timed out: {}", e),
             Error::Parse(e) => write!(f, "Parse error: {}", e),
             Error::Custom(s) => write!(f, "Custom error: {}", s),
+            Error::NotFound(id) => write!(f, "Not found: {}", id),
+            Error::GeoBlocked(content_type) => write!(
+                f,
+                "ERTFLIX answered with an HTML page instead of JSON (Content-Type: {}), most likely because it is only available from Greece",
+                content_type.as_deref().unwrap_or("none"),
+            ),
         }
     }
 }
@@ -73,16 +274,69 @@ impl error::Error for Error {
         match *self {
             Error::Request(ref e) => Some(e),
             Error::Parse(ref e) => Some(e),
-            Error::Custom(_) => None,
+            Error::Timeout(_) | Error::Custom(_) | Error::NotFound(_) | Error::GeoBlocked(_) => None,
         }
     }
 }
 
+impl Error {
+    /// Wraps a failed HTTP request, telling timeouts apart from other failures
+    pub fn from_request(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Error::Timeout(e.to_string())
+        } else {
+            Error::Request(e)
+        }
+    }
+}
+
+/// What `/metrics` reports about Ertflix requests that got no answer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UpstreamFailures {
+    /// Requests Ertflix did not answer in time
+    pub timeouts: u64,
+    /// Requests that failed any other way, e.g. because no connection could be made
+    pub errors: u64,
+}
+
 pub trait ErtflixClient {
     fn new(base_url: &str) -> Self
     where
         Self: Sized;
 
+    fn from_config(config: &config::ErtflixConfig) -> Self
+    where
+        Self: Sized,
+    {
+        Self::new(&config.base_url)
+    }
+
+    /// Rate limits Ertflix announced in its responses so far
+    fn rate_limits(&self) -> RateLimitSnapshot {
+        RateLimitSnapshot::default()
+    }
+
+    /// Requests to Ertflix that timed out or failed without an answer so far
+    fn upstream_failures(&self) -> UpstreamFailures {
+        UpstreamFailures::default()
+    }
+
+    /// Applies the settings of a reloaded `config` that can change while serving,
+    /// returning whether the items the client lists changed
+    fn reload(&self, _config: &config::ErtflixConfig) -> bool {
+        false
+    }
+
+    /// Keeps the details of each fetched tile in `cache` for `ttl`, so `get_tiles` only asks
+    /// Ertflix for the tiles it doesn't have. Clients without a tile cache ignore it.
+    fn with_tile_cache(self, _cache: Arc<Cache>, ttl: Duration) -> Self
+    where
+        Self: Sized,
+    {
+        trace!("Client keeps no tile cache, ignoring the one offered for {}s", ttl.as_secs());
+        self
+    }
+
     async fn get_collections<CollectionCategory>(
         &self,
         filtering_strategy: fn(SectionContents) -> CollectionCategory,
@@ -92,61 +346,126 @@ pub trait ErtflixClient {
 
     async fn get_tv_shows(&self) -> Result<Vec<ertflix::TVShow>, Box<dyn error::Error>>;
 
+    /// Fetches the episodes of a TV show, grouped into seasons by season number
+    async fn get_show_seasons(&self, show_id: String) -> Result<Vec<ertflix::Season>, Box<dyn error::Error>>;
+
+    /// Resolves a tile codename to the URL of its playback manifest
+    async fn get_stream_url(&self, codename: String) -> Result<String, Box<dyn error::Error>>;
+
     async fn get_section_content(
         &self,
         section_codename: String,
     ) -> Result<Vec<SectionContents>, Box<dyn error::Error>>;
 
+    /// Searches ERTFLIX for tiles matching `query`; an empty query matches nothing
+    async fn search(&self, query: String) -> Result<Vec<Tile>, Box<dyn error::Error>>;
+
+    /// Checks that ERTFLIX can be reached and is not failing, without fetching any content
+    async fn ping(&self) -> Result<(), Box<dyn error::Error>>;
+
+    /// Fetches the full details of the given section tiles. Fields GetTiles leaves out
+    /// are taken from the section tiles.
     async fn get_tiles<TileType>(
         &self,
-        ids: Vec<String>,
+        section_tiles: Vec<Tile>,
     ) -> Result<Vec<TileType>, Box<dyn error::Error>> where
         TileType: From<Tile>;
+
+    /// Fetches a single tile, failing with `Error::NotFound` when ERTFLIX returns nothing for the id
+    async fn get_tile<TileType>(&self, id: String) -> Result<TileType, Box<dyn error::Error>>
+    where
+        TileType: From<Tile>,
+    {
+        let tile = Tile { id: id.clone(), ..Default::default() };
+        match self.get_tiles(vec![tile]).await?.into_iter().next() {
+            Some(tile) => Ok(tile),
+            None => {
+                info!("Tile {} was not found", id);
+                Err(Box::new(Error::NotFound(id)))
+            }
+        }
+    }
+
+    /// Fetches a single tile from ERTFLIX itself, bypassing any tile cache, failing with
+    /// `Error::NotFound` when ERTFLIX returns nothing for the id
+    async fn fetch_tile<TileType>(&self, id: String) -> Result<TileType, Box<dyn error::Error>>
+    where
+        TileType: From<Tile>,
+    {
+        self.get_tile(id).await
+    }
 }
 
-impl ErtflixClient for DefaultErtflixClient {
+impl<Tr: Transport + Default> ErtflixClient for DefaultErtflixClient<Tr> {
     fn new(base_url: &str) -> Self {
         info!("Creating new DefaultErtflixClient with base_url: {}", base_url);
 
-        DefaultErtflixClient {
-            client: Client::new(),
-            base_url: base_url.to_string(),
+        DefaultErtflixClient::with_transport(Tr::default(), vec![base_url.to_string()])
+    }
+
+    fn from_config(config: &config::ErtflixConfig) -> Self {
+        Self::from_config_with_transport(config, Tr::default())
+    }
+
+    fn rate_limits(&self) -> RateLimitSnapshot {
+        self.rate_limits.snapshot()
+    }
+
+    fn upstream_failures(&self) -> UpstreamFailures {
+        *self.failures.lock().unwrap()
+    }
+
+    fn reload(&self, config: &config::ErtflixConfig) -> bool {
+        let mut changed = false;
+        for (codenames, reloaded) in [
+            (&self.movies_section_codenames, &config.movies_section_codenames),
+            (&self.tv_shows_section_codenames, &config.tv_shows_section_codenames),
+        ] {
+            let mut codenames = codenames.write().unwrap();
+            if *codenames != *reloaded {
+                info!("Listing sections {:?} instead of {:?}", reloaded, codenames);
+                *codenames = reloaded.clone();
+                changed = true;
+            }
+        }
+        let mut uncategorized = self.uncategorized_collection.write().unwrap();
+        if *uncategorized != config.uncategorized_collection {
+            info!("Collecting uncategorized tiles into {:?} instead of {:?}", config.uncategorized_collection, uncategorized);
+            *uncategorized = config.uncategorized_collection.clone();
+            changed = true;
+        }
+        changed
+    }
+
+    fn with_tile_cache(mut self, cache: Arc<Cache>, ttl: Duration) -> Self {
+        if ttl.is_zero() {
+            debug!("Tile cache disabled");
+        } else {
+            self.tile_cache = Some((cache, ttl));
         }
+        self
     }
 
     async fn get_collections<CollectionCategory>(
         &self,
         filtering_strategy: fn(SectionContents) -> CollectionCategory,
     ) -> Result<Vec<CollectionCategory>, Box<dyn error::Error>> {
-        let url = format!(
-            "https://{base_url}/v1/InsysGoPage/GetPageContent?platformCodename=www&pageCodename=mainpage&limit=100&page=1&$headers=%7B%22X-Api-Date-Format%22:%22iso%22,%22X-Api-Camel-Case%22:true%7D",
-            base_url = self.base_url
+        let path = format!(
+            "/v1/InsysGoPage/GetPageContent?platformCodename={}&pageCodename=mainpage&limit=100&page=1&$headers=%7B%22X-Api-Date-Format%22:%22iso%22,%22X-Api-Camel-Case%22:true%7D",
+            self.platform_codename,
         );
 
         info!("Fetching collections from Ertflix API");
-        debug!("Request URL: {}", url);
+        debug!("Request path: {}", path);
         trace!("Making HTTP GET request to collections endpoint");
-        let response = self.client.get(url).with_ertflix_headers().send().await;
-
-        let response_str = match response {
-            Ok(res) => {
-                debug!("Received response with status: {}", res.status());
-                match res.text().await {
-                    Ok(text) => {
-                        trace!("Response body length: {} bytes", text.len());
-                        text
-                    }
-                    Err(e) => {
-                        error!("Failed to read response text: {}", e);
-                        return Err(Box::new(e));
-                    }
-                }
-            }
+        let response = match self.send_with_retry(Request::Get(&path)).await {
+            Ok(res) => res,
             Err(e) => {
                 error!("HTTP request failed: {}", e);
                 return Err(Box::new(e));
             }
         };
+        let response_str = response.body;
         // Deserialize into the new top-level struct
         let top_level_response: Result<ApiResponse, Box<dyn error::Error>> = match serde_json::from_str::<ApiResponse>(&response_str) {
             Ok(data) => {
@@ -155,19 +474,16 @@ impl ErtflixClient for DefaultErtflixClient {
                 Ok(data)
             }
             Err(e) => {
-                println!("Failed to parse JSON: {:?}", e);
-                error!("Failed to parse JSON response: {}", e);
-                debug!("Response body: {}", response_str);
+                log_parse_error("collections", &response_str, &e);
                 return Err(Box::new(Error::Parse(e)));
             }
         };
 
         // Now you can access the content
-        let api_response_content: Vec<SectionContents> = top_level_response?
+        let (mut api_response_content, uncategorized): (Vec<SectionContents>, Vec<SectionContents>) = top_level_response?
             .section_contents
             .into_iter()
-            .filter(|s| s.toplist_codename.is_some())
-            .filter(|s| {
+            .partition(|s| {
                 let has_toplist = s.toplist_codename.is_some();
                 if has_toplist {
                     trace!("Including section {} with toplist: {:?}", s.section_id, s.toplist_codename);
@@ -175,10 +491,27 @@ impl ErtflixClient for DefaultErtflixClient {
                     trace!("Filtering out section {} (no toplist)", s.section_id);
                 }
                 has_toplist
-            })
-            .collect();
+            });
         debug!("Filtered to {} sections with toplists", api_response_content.len());
 
+        let uncategorized_collection = self.uncategorized_collection.read().unwrap().clone();
+        if let Some(name) = uncategorized_collection {
+            let mut seen = HashSet::new();
+            let tiles: Vec<Tile> = uncategorized
+                .into_iter()
+                .flat_map(|section| section.tiles_ids.unwrap_or_default())
+                .filter(|tile| seen.insert(tile.id.clone()))
+                .collect();
+            if !tiles.is_empty() {
+                debug!("Collecting {} tiles of sections without a toplist into {}", tiles.len(), name);
+                api_response_content.push(SectionContents {
+                    toplist_codename: Some(name),
+                    section_id: UNCATEGORIZED_SECTION_ID,
+                    tiles_ids: Some(tiles),
+                });
+            }
+        }
+
         let collections: Vec<CollectionCategory> = api_response_content
             .into_iter()
             .map(filtering_strategy)
@@ -189,37 +522,21 @@ impl ErtflixClient for DefaultErtflixClient {
 
     async fn get_movies(&self) -> Result<Vec<ertflix::Movie>, Box<dyn error::Error>> {
         info!("Fetching movies from Ertflix");
-        debug!("Getting section content for movies: oles-oi-tainies-1");
-        let section_contents = self
-            .get_section_content("oles-oi-tainies-1".to_string())
-            .await?;
-
-        let movie_section = match section_contents.first() {
-            Some(section) => {
-                debug!("Found movie section with ID: {}", section.section_id);
-                section
-            }
-            None => {
-                warn!("No movie section found in response");
-                return Err(Box::new(Error::Custom("No movie section found".to_string())));
-            }
-        };
-        let movie_ids: Vec<String> = match &movie_section.tiles_ids {
-            Some(tiles) => {
-                info!("Found {} movie tiles", tiles.len());
-                tiles
-            }
-            None => {
-                warn!("No movie tiles found in section");
-                return Err(Box::new(Error::Custom("No tiles found".to_string())));
-            }
+        let codenames = self.movies_section_codenames.read().unwrap().clone();
+        let listed = self.list_section_tiles(&codenames, "movie").await?;
+        if listed.is_empty() {
+            info!("Movie sections have no tiles, returning an empty library");
+            return Ok(Vec::new());
         }
-            .iter()
-            .map(|tile| tile.id.clone())
-            .collect();
-        debug!("Fetching details for {} movies", movie_ids.len());
+        debug!("Fetching details for {} movies", listed.len());
 
-        let movies: Vec<ertflix::Movie> = self.get_tiles(movie_ids).await?;
+        let tiles = listed.iter().map(|(_, tile)| tile.clone()).collect();
+        let mut movies: Vec<ertflix::Movie> = self.get_tiles(tiles).await?;
+        if self.tag_source_sections {
+            for movie in &mut movies {
+                movie.tags.extend(source_section(&listed, &movie.id));
+            }
+        }
         info!("Successfully fetched {} movies", movies.len());
 
         Ok(movies)
@@ -227,192 +544,1229 @@ impl ErtflixClient for DefaultErtflixClient {
 
     async fn get_tv_shows(&self) -> Result<Vec<ertflix::TVShow>, Box<dyn error::Error>> {
         info!("Fetching TV shows from Ertflix");
-        debug!("Getting section content for TV shows: ert-seires-plereis");
-
-        let section_contents = self.get_section_content("ert-seires-plereis".to_string()).await?;
+        let codenames = self.tv_shows_section_codenames.read().unwrap().clone();
+        let listed = self.list_section_tiles(&codenames, "TV show").await?;
+        if listed.is_empty() {
+            info!("TV show sections have no tiles, returning an empty library");
+            return Ok(Vec::new());
+        }
+        debug!("Fetching details for {} TV shows", listed.len());
 
-        let tv_section = match section_contents.first() {
-            Some(section) => {
-                debug!("Found TV shows section with ID: {}", section.section_id);
-                section
+        let tiles = listed.iter().map(|(_, tile)| tile.clone()).collect();
+        let mut shows: Vec<ertflix::TVShow> = self.get_tiles(tiles).await?;
+        if self.tag_source_sections {
+            for show in &mut shows {
+                show.tags.extend(source_section(&listed, &show.id));
             }
-            None => {
-                warn!("No TV shows section found in response");
-                return Err(Box::new(Error::Custom("No TV shows section found".to_string())));
-            }
-        };
-        let tv_ids: Vec<String> = match &tv_section.tiles_ids {
-            Some(tiles) => {
-                info!("Found {} TV show tiles", tiles.len());
-                tiles
+        }
+        info!("Successfully fetched {} TV shows", shows.len());
+        Ok(shows)
+    }
+
+    async fn get_stream_url(&self, codename: String) -> Result<String, Box<dyn error::Error>> {
+        let platform = &self.platform_codename;
+        let encoded_codename = percent_encode(&codename);
+        let path = format!(
+            "/v1/Player/AcquireContent?platformCodename={platform}&codename={encoded_codename}&$headers=%7B%22X-Api-Date-Format%22:%22iso%22,%22X-Api-Camel-Case%22:true%7D",
+        );
+
+        info!("Resolving stream URL for: {}", codename);
+        debug!("Request path: {}", path);
+
+        let res = self.send_with_retry(Request::Get(&path)).await.map_err(|e| {
+            error!("HTTP request failed for stream of {}: {}", codename, e);
+            e
+        })?;
+        if !res.is_success() {
+            warn!("Non-success status code for stream request: {}", res.status);
+        }
+
+        let content: AcquireContentResponse = serde_json::from_str(&res.body).map_err(|e| {
+            error!("Failed to parse stream content JSON: {}", e);
+            debug!("Response body: {}", res.body);
+            Error::Parse(e)
+        })?;
+
+        // Prefer the HLS manifest when ERTFLIX offers several formats
+        let urls: Vec<String> = content
+            .media_files
+            .into_iter()
+            .flat_map(|file| file.formats)
+            .map(|format| format.url)
+            .collect();
+        match urls.iter().find(|url| url.contains(".m3u8")).or(urls.first()) {
+            Some(url) => {
+                debug!("Resolved stream URL for {}: {}", codename, url);
+                Ok(url.clone())
             }
             None => {
-                warn!("No TV show tiles found in section");
-                return Err(Box::new(Error::Custom("No tiles found".to_string())));
+                warn!("No playable media files found for {}", codename);
+                Err(Box::new(Error::NotFound(codename)))
             }
-        }.iter().map(|tile| tile.id.clone()).collect();
-        debug!("Fetching details for {} TV shows", tv_ids.len());
+        }
+    }
 
-        let shows: Vec<ertflix::TVShow> = self.get_tiles(tv_ids).await?;
-        info!("Successfully fetched {} TV shows", shows.len());
-        Ok(shows)
+    async fn get_show_seasons(&self, show_id: String) -> Result<Vec<ertflix::Season>, Box<dyn error::Error>> {
+        let platform = &self.platform_codename;
+        let encoded_show_id = percent_encode(&show_id);
+        let path = format!(
+            "/v1/Tile/GetSeriesDetails?platformCodename={platform}&id={encoded_show_id}&$headers=%7B%22X-Api-Date-Format%22:%22iso%22,%22X-Api-Camel-Case%22:true%7D",
+        );
+
+        info!("Fetching seasons of TV show: {}", show_id);
+        debug!("Request path: {}", path);
+
+        let res = self.send_with_retry(Request::Get(&path)).await.map_err(|e| {
+            error!("HTTP request failed for seasons of {}: {}", show_id, e);
+            e
+        })?;
+        if !res.is_success() {
+            warn!("Non-success status code for seasons request: {}", res.status);
+        }
+
+        let details: SeriesDetailsResponse = serde_json::from_str(&res.body).map_err(|e| {
+            error!("Failed to parse series details JSON: {}", e);
+            debug!("Response body: {}", res.body);
+            Error::Parse(e)
+        })?;
+
+        // Episodes without a season number take it from their title, e.g. "Κύκλος 2", or go
+        // to season 0, which numbering later fills in
+        let mut seasons: BTreeMap<u32, Vec<ertflix::Episode>> = BTreeMap::new();
+        for episode in details.episodes {
+            let title = episode.title.unwrap_or_default();
+            let season_number = episode.season_number.or_else(|| ertflix::season_number_from_title(&title));
+            seasons.entry(season_number.unwrap_or_default()).or_default().push(ertflix::Episode {
+                id: episode.id,
+                title,
+                duration: episode.duration.unwrap_or_default(),
+                episode_number: episode.episode_number,
+            });
+        }
+        debug!("TV show {} has {} seasons", show_id, seasons.len());
+
+        Ok(seasons
+            .into_iter()
+            .map(|(season_number, episodes)| ertflix::Season { season_number, episodes })
+            .collect())
     }
 
     async fn get_section_content(
         &self,
         section_codename: String,
     ) -> Result<Vec<SectionContents>, Box<dyn error::Error>> {
-        let url = format!(
-            "https://{base_url}/v1/InsysGoPage/GetSectionContent?platformCodename=www&sectionCodename={section_codename}&page=1&ignoreLimit=true&limit=1000&$headers=%7B%22X-Api-Date-Format%22:%22iso%22,%22X-Api-Camel-Case%22:true%7D",
-            base_url = self.base_url,
+        let platform = &self.platform_codename;
+        let encoded_codename = percent_encode(&section_codename);
+        let path = format!(
+            "/v1/InsysGoPage/GetSectionContent?platformCodename={platform}&sectionCodename={encoded_codename}&page=1&ignoreLimit=true&limit=1000&$headers=%7B%22X-Api-Date-Format%22:%22iso%22,%22X-Api-Camel-Case%22:true%7D",
         );
-        let response = self.client.get(&url).with_ertflix_headers().send().await;
+        let response = self.send_with_retry(Request::Get(&path)).await;
 
         info!("Fetching section content for: {}", section_codename);
-        debug!("Request URL: {}", url);
+        debug!("Request path: {}", path);
         trace!("Making HTTP GET request to section content endpoint");
 
 
         match response {
             Ok(res) => {
-                let status = res.status();
-                debug!("Received response with status: {}", status);
-                if !status.is_success() {
-                    warn!("Non-success status code: {}", status);
+                if !res.is_success() {
+                    warn!("Non-success status code: {}", res.status);
                 }
 
-                match res.text().await {
-                    Ok(response_str) => {
-                        trace!("Response body length: {} bytes", response_str.len());
-                        match serde_json::from_str(&response_str) {
-                            Ok(section_contents) => {
-                                let contents: Vec<SectionContents> = section_contents;
-                                info!("Successfully fetched {} section contents for {}", contents.len(), section_codename);
-                                Ok(contents)
-                            }
-                            Err(e) => {
-                                error!("Failed to parse section content JSON: {}", e);
-                                debug!("Response body: {}", response_str);
-                                Err(Box::new(Error::Parse(e)))
-                            }
-                        }
+                match serde_json::from_str::<ListPayload<SectionContents>>(&res.body) {
+                    Ok(section_contents) => {
+                        let contents = section_contents.into_items();
+                        info!("Successfully fetched {} section contents for {}", contents.len(), section_codename);
+                        Ok(contents)
                     }
                     Err(e) => {
-                        error!("Failed to read response text: {}", e);
-                        Err(Box::new(Error::Request(e)))
+                        log_parse_error("section content", &res.body, &e);
+                        Err(Box::new(Error::Parse(e)))
                     }
                 }
             }
             Err(e) => {
                 error!("HTTP request failed for section {}: {}", section_codename, e);
-                Err(Box::new(Error::Request(e)))
+                Err(Box::new(e))
             }
         }
     }
 
+    async fn search(&self, query: String) -> Result<Vec<Tile>, Box<dyn error::Error>> {
+        let query = query.trim();
+        if query.is_empty() {
+            debug!("Empty search query, skipping the request");
+            return Ok(Vec::new());
+        }
+
+        let platform = &self.platform_codename;
+        let encoded_query = percent_encode(query);
+        let path = format!(
+            "/v1/Search/GetSearchResults?platformCodename={platform}&query={encoded_query}&page=1&limit=100&$headers=%7B%22X-Api-Date-Format%22:%22iso%22,%22X-Api-Camel-Case%22:true%7D",
+        );
+
+        info!("Searching Ertflix for: {}", query);
+        debug!("Request path: {}", path);
+
+        let res = self.send_with_retry(Request::Get(&path)).await.map_err(|e| {
+            error!("HTTP request failed for search {}: {}", query, e);
+            e
+        })?;
+        if !res.is_success() {
+            warn!("Non-success status code for search request: {}", res.status);
+        }
+
+        let tiles = serde_json::from_str::<ListPayload<Tile>>(&res.body)
+            .map_err(|e| {
+                error!("Failed to parse search results JSON: {}", e);
+                debug!("Response body: {}", res.body);
+                Error::Parse(e)
+            })?
+            .into_items();
+        info!("Search for {} matched {} tiles", query, tiles.len());
+        Ok(tiles)
+    }
+
+    async fn ping(&self) -> Result<(), Box<dyn error::Error>> {
+        debug!("Pinging Ertflix");
+        // Any answer short of a server error means ERTFLIX is up, even a 404 for the root
+        let res = self.send_with_fallback(Request::Get("/")).await?;
+        if res.status >= 500 {
+            warn!("Ertflix answered the ping with status {}", res.status);
+            return Err(Box::new(Error::Custom(format!("Ertflix answered with status {}", res.status))));
+        }
+        Ok(())
+    }
+
     async fn get_tiles<TileType>(
         &self,
-        ids: Vec<String>,
+        section_tiles: Vec<Tile>,
     ) -> Result<Vec<TileType>, Box<dyn error::Error>>
     where
         TileType: From<Tile>,
     {
-        let url = format!(
-            "https://{base_url}/v2/Tile/GetTiles?$headers=%7B%22Content-Type%22:%22application%2Fjson%3Bcharset%3Dutf-8%22,%22X-Api-Date-Format%22:%22iso%22,%22X-Api-Camel-Case%22:true%7D",
-            base_url = self.base_url
-        );
+        if section_tiles.is_empty() {
+            debug!("No tile ids requested, skipping the GetTiles call");
+            return Ok(Vec::new());
+        }
+
+        let mut known = self.cached_tiles(&section_tiles).await;
+        let missing: Vec<Tile> = section_tiles.iter().filter(|tile| !known.contains_key(&tile.id)).cloned().collect();
+        if !known.is_empty() {
+            debug!("{} of {} tiles are cached, fetching the other {}", known.len(), section_tiles.len(), missing.len());
+        }
+
+        let batches: Vec<&[Tile]> = missing.chunks(self.tile_batch_size.max(1)).collect();
+        info!("Fetching tile details for {} items in {} batches", missing.len(), batches.len());
+        let batch_count = batches.len();
+        let results: Vec<Result<Vec<Tile>, Box<dyn error::Error>>> = stream::iter(batches)
+            .map(|batch| self.get_tile_batch(batch))
+            .buffer_unordered(self.max_concurrent_requests.max(1))
+            .collect()
+            .await;
+
+        let mut fetched = Vec::with_capacity(missing.len());
+        let mut first_error = None;
+        for result in results {
+            match result {
+                Ok(batch) => fetched.extend(batch),
+                Err(e) if self.partial_tile_batches => {
+                    warn!("Skipping a failed GetTiles batch: {}", e);
+                    first_error.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(e) = first_error
+            && fetched.is_empty()
+            && known.is_empty()
+        {
+            error!("All {} GetTiles batches failed", batch_count);
+            return Err(e);
+        }
+        self.cache_tiles(&fetched).await;
+        known.extend(fetched.into_iter().map(|tile| (tile.id.clone(), tile)));
+
+        // In the order the tiles were asked for, whatever order the batches came back in
+        let tile_types: Vec<TileType> = section_tiles
+            .iter()
+            .filter_map(|requested| known.get(&requested.id).cloned())
+            .map(|tile| {
+                trace!("Converting tile: {} ({})", tile.title.as_deref().unwrap_or("Unknown"), tile.id);
+                TileType::from(tile)
+            })
+            .collect();
+        info!("Successfully fetched and converted {} tiles", tile_types.len());
+        Ok(tile_types)
+    }
+
+    async fn fetch_tile<TileType>(&self, id: String) -> Result<TileType, Box<dyn error::Error>>
+    where
+        TileType: From<Tile>,
+    {
+        let tile = Tile { id: id.clone(), ..Default::default() };
+        match self.get_tile_batch(&[tile]).await?.into_iter().find(|tile| tile.id == id) {
+            Some(tile) => Ok(TileType::from(tile)),
+            None => {
+                info!("Tile {} was not found", id);
+                Err(Box::new(Error::NotFound(id)))
+            }
+        }
+    }
+}
+
+/// The delay before the next retry, after one that waited `delay`
+fn next_backoff(delay: Duration) -> Duration {
+    delay.saturating_mul(2).min(MAX_BACKOFF)
+}
+
+/// How many bytes of the body are logged on either side of where parsing failed
+const PARSE_ERROR_CONTEXT_BYTES: usize = 100;
+
+/// Logs where a response body failed to parse along with the part of the body around
+/// that spot, instead of the whole body, which can run to megabytes
+fn log_parse_error(what: &str, body: &str, e: &serde_json::Error) {
+    error!(
+        "Failed to parse {} JSON at line {}, column {}: {}; near {:?}",
+        what,
+        e.line(),
+        e.column(),
+        e,
+        parse_error_snippet(body, e.line(), e.column())
+    );
+}
+
+/// The part of `body` around the 1-based `line` and `column` serde_json reports,
+/// `PARSE_ERROR_CONTEXT_BYTES` on either side, cut at character boundaries
+fn parse_error_snippet(body: &str, line: usize, column: usize) -> &str {
+    let line_start: usize = body.split_inclusive('\n').take(line.saturating_sub(1)).map(str::len).sum();
+    let offset = (line_start + column.saturating_sub(1)).min(body.len());
+
+    let mut start = offset.saturating_sub(PARSE_ERROR_CONTEXT_BYTES);
+    while !body.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (offset + PARSE_ERROR_CONTEXT_BYTES).min(body.len());
+    while !body.is_char_boundary(end) {
+        end += 1;
+    }
+    &body[start..end]
+}
+
+/// Parses the complete items at the start of a JSON array that was cut off, either a
+/// bare array or one inside an envelope, stopping at the first incomplete item
+fn complete_array_items<T: serde::de::DeserializeOwned>(body: &str) -> Vec<T> {
+    let Some(start) = body.find('[') else {
+        return Vec::new();
+    };
+
+    let mut items = Vec::new();
+    let mut rest = &body[start + 1..];
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<T>();
+        match stream.next() {
+            Some(Ok(item)) => {
+                items.push(item);
+                rest = &rest[stream.byte_offset()..];
+            }
+            _ => return items,
+        }
+    }
+}
+
+impl<Tr: Transport + Default> DefaultErtflixClient<Tr> {
+    /// Lists the tiles of every section ERTFLIX returns for each of `codenames`, in order
+    /// and without duplicates, along with the codename each was first listed under.
+    /// `kind` names the items in logs.
+    async fn list_section_tiles(&self, codenames: &[String], kind: &str) -> Result<Vec<(String, Tile)>, Box<dyn error::Error>> {
+        let mut listed: Vec<(String, Tile)> = Vec::new();
+        let mut seen = HashSet::new();
+        for codename in codenames {
+            debug!("Getting section content for {}s: {}", kind, codename);
+            let section_contents = self.get_section_content(codename.clone()).await?;
+            if section_contents.is_empty() {
+                warn!("No {} section found in response for {}", kind, codename);
+                return Err(Box::new(Error::Custom(format!("No {} section found", kind))));
+            }
+            if section_contents.iter().all(|section| section.tiles_ids.is_none()) {
+                warn!("No {} tiles found in any section for {}", kind, codename);
+                return Err(Box::new(Error::Custom("No tiles found".to_string())));
+            }
+
+            for section in &section_contents {
+                let Some(tiles) = &section.tiles_ids else {
+                    warn!("{} section {} of {} lists no tiles, skipping it", kind, section.section_id, codename);
+                    continue;
+                };
+                let before = listed.len();
+                listed.extend(
+                    tiles
+                        .iter()
+                        .filter(|tile| seen.insert(tile.id.clone()))
+                        .map(|tile| (codename.clone(), tile.clone())),
+                );
+                info!(
+                    "Found {} {} tiles in section {} of {}, {} of them new",
+                    tiles.len(), kind, section.section_id, codename, listed.len() - before
+                );
+            }
+        }
+        Ok(listed)
+    }
+}
 
-        info!("Fetching tile details for {} items", ids.len());
-        debug!("Request URL: {}", url);
-        trace!("Tile IDs: {:?}", ids);
+/// A request to an Ertflix endpoint, relative to whichever base URL is tried
+#[derive(Clone, Copy)]
+enum Request<'a> {
+    Get(&'a str),
+    Post(&'a str, &'a serde_json::Value),
+}
+
+impl Request<'_> {
+    fn path(&self) -> &str {
+        match self {
+            Request::Get(path) | Request::Post(path, _) => path,
+        }
+    }
+}
+
+impl<Tr: Transport> DefaultErtflixClient<Tr> {
+    /// Creates a client sending its requests through `transport`, trying `base_urls` in order
+    pub fn with_transport(transport: Tr, base_urls: Vec<String>) -> Self {
+        DefaultErtflixClient {
+            transport,
+            base_urls,
+            active_base_url: AtomicUsize::new(0),
+            best_effort_tiles: false,
+            platform_codename: config::DEFAULT_PLATFORM_CODENAME.to_string(),
+            tag_source_sections: false,
+            tile_batch_size: config::DEFAULT_TILE_BATCH_SIZE,
+            max_concurrent_requests: 1,
+            partial_tile_batches: false,
+            max_retries: 0,
+            retry_base_delay: Duration::ZERO,
+            movies_section_codenames: RwLock::new(vec![MOVIES_SECTION_CODENAME.to_string()]),
+            tv_shows_section_codenames: RwLock::new(vec![TV_SHOWS_SECTION_CODENAME.to_string()]),
+            uncategorized_collection: RwLock::new(None),
+            validated_responses: Mutex::new(HashMap::new()),
+            rate_limits: RateLimitMetrics::default(),
+            failures: Mutex::new(UpstreamFailures::default()),
+            tile_cache: None,
+        }
+    }
+
+    /// Creates a client configured from `config`, sending its requests through `transport`
+    pub fn from_config_with_transport(config: &config::ErtflixConfig, transport: Tr) -> Self {
+        info!("Creating new DefaultErtflixClient with base_url: {}", config.base_url);
+        let mut client = Self::with_transport(transport, vec![config.base_url.clone()]);
+        if !config.fallback_base_urls.is_empty() {
+            info!("Configuring {} fallback base URLs: {:?}", config.fallback_base_urls.len(), config.fallback_base_urls);
+            client.base_urls.extend(config.fallback_base_urls.iter().cloned());
+        }
+        client.best_effort_tiles = config.best_effort_tiles;
+        client.platform_codename = config.platform_codename.clone();
+        client.tag_source_sections = config.tag_source_sections;
+        client.tile_batch_size = config.tile_batch_size;
+        client.max_concurrent_requests = config.max_concurrent_requests;
+        client.partial_tile_batches = config.partial_tile_batches;
+        client.max_retries = config.max_retries;
+        client.retry_base_delay = Duration::from_millis(config.retry_base_delay_millis);
+        client.movies_section_codenames = RwLock::new(config.movies_section_codenames.clone());
+        client.tv_shows_section_codenames = RwLock::new(config.tv_shows_section_codenames.clone());
+        client.uncategorized_collection = RwLock::new(config.uncategorized_collection.clone());
+        client
+    }
+
+    /// Fetches the details of one batch of tiles in a single GetTiles request, filling in
+    /// fields GetTiles left out from the section listing
+    async fn get_tile_batch(&self, section_tiles: &[Tile]) -> Result<Vec<Tile>, Box<dyn error::Error>> {
+        let path = "/v2/Tile/GetTiles?$headers=%7B%22Content-Type%22:%22application%2Fjson%3Bcharset%3Dutf-8%22,%22X-Api-Date-Format%22:%22iso%22,%22X-Api-Camel-Case%22:true%7D";
+
+        debug!("Fetching tile details for a batch of {} items", section_tiles.len());
+        debug!("Request path: {}", path);
+        trace!("Tile IDs: {:?}", section_tiles.iter().map(|tile| &tile.id).collect::<Vec<_>>());
 
         let request_body: GetTilesRequestBody = GetTilesRequestBody {
-            platform_codename: "www".to_string(),
-            requested_tiles: ids
+            platform_codename: self.platform_codename.clone(),
+            requested_tiles: section_tiles
                 .iter()
-                .map(|id| {
-                    let id = id.clone();
+                .map(|tile| {
+                    let id = tile.id.clone();
                     RequestedTile { id }
                 })
                 .collect(),
         };
 
         trace!("Request body prepared with {} tiles", request_body.requested_tiles.len());
-        let response = self
-        .client
-        .post(url)
-        .with_ertflix_headers()
-        .json(&serde_json::json!(request_body))
-        .send()
-        .await;
+        let request_body = serde_json::json!(request_body);
+        let response = self.send_with_retry(Request::Post(path, &request_body)).await;
 
         match response {
             Ok(res) => {
-                let status = res.status();
-                debug!("Received tiles response with status: {}", status);
-                if !status.is_success() {
-                    warn!("Non-success status code for tiles request: {}", status);
+                debug!("Received tiles response with status: {}", res.status);
+                if !res.is_success() {
+                    warn!("Non-success status code for tiles request: {}", res.status);
                 }
 
-                match res.text().await {
-                    Ok(response_str) => {
-                        trace!("Tiles response body length: {} bytes", response_str.len());
-                        match serde_json::from_str(&response_str) {
-                            Ok(tiles) => {
-                                let tiles: Vec<Tile> = tiles;
-                                debug!("Successfully parsed {} tiles", tiles.len());
-
-                                let tile_types: Vec<TileType> = tiles.into_iter().map(|tile| {
-                                    trace!("Converting tile: {} ({})", tile.title.as_deref().unwrap_or("Unknown"), tile.id);
-                                    TileType::from(tile)
-                                }).collect();
-
-                                info!("Successfully fetched and converted {} tiles", tile_types.len());
-                                Ok(tile_types)
-                            }
-                            Err(e) => {
-                                error!("Failed to parse tiles JSON: {}", e);
-                                debug!("Response body: {}", response_str);
-                                Err(Box::new(Error::Parse(e)))
-                            }
-                        }
+                trace!("Tiles response body length: {} bytes", res.body.len());
+                let parsed = match serde_json::from_str::<ListPayload<Tile>>(&res.body) {
+                    Ok(tiles) => Ok(tiles.into_items()),
+                    Err(e) if self.best_effort_tiles && e.is_eof() => {
+                        let tiles: Vec<Tile> = complete_array_items(&res.body);
+                        warn!("Tiles response was truncated ({}), keeping {} complete tiles", e, tiles.len());
+                        Ok(tiles)
+                    }
+                    Err(e) => Err(e),
+                };
+                match parsed {
+                    Ok(tiles) => {
+                        debug!("Successfully parsed {} tiles", tiles.len());
+
+                        Ok(tiles
+                            .into_iter()
+                            .map(|mut tile| {
+                                if let Some(section_tile) = section_tiles.iter().find(|section_tile| section_tile.id == tile.id) {
+                                    tile.fill_missing_from(section_tile);
+                                }
+                                tile
+                            })
+                            .collect())
                     }
                     Err(e) => {
-                        error!("Failed to read tiles response text: {}", e);
-                        Err(Box::new(Error::Request(e)))
+                        log_parse_error("tiles", &res.body, &e);
+                        Err(Box::new(Error::Parse(e)))
                     }
                 }
             }
             Err(e) => {
                 error!("HTTP request failed for tiles: {}", e);
-                Err(Box::new(Error::Request(e)))
+                Err(Box::new(e))
+            }
+        }
+    }
+
+
+    /// Sends the request like `send_with_fallback`, retrying connection failures, timeouts
+    /// and 5xx answers up to `max_retries` times. The delay starts at `retry_base_delay`,
+    /// doubles with each retry up to `MAX_BACKOFF` and is jittered. 4xx answers are never
+    /// retried. Once the retries run out, the last answer or error is returned as it is.
+    async fn send_with_retry(&self, request: Request<'_>) -> Result<TransportResponse, Error> {
+        let mut delay = self.retry_base_delay;
+        let mut retries = 0;
+        loop {
+            let response = self.send_with_fallback(request).await;
+            let reason = match &response {
+                Ok(response) if response.status >= 500 => format!("status {}", response.status),
+                Err(Error::Request(e)) if e.is_connect() => e.to_string(),
+                Err(e @ Error::Timeout(_)) => e.to_string(),
+                _ => return response,
+            };
+            if retries >= self.max_retries {
+                warn!("Giving up on {} after {} retries: {}", request.path(), retries, reason);
+                return response;
+            }
+
+            let jittered = delay.mul_f64(rand::random_range(0.5..1.5));
+            retries += 1;
+            warn!("Request to {} failed ({}), retry {} of {} in {}ms", request.path(), reason, retries, self.max_retries, jittered.as_millis());
+            actix_web::rt::time::sleep(jittered).await;
+            delay = next_backoff(delay);
+        }
+    }
+
+    /// Sends the request to the last working base URL, moving on to the
+    /// next configured host whenever a connection cannot be established.
+    #[instrument(
+        name = "ertflix_request",
+        skip_all,
+        fields(path = request.path(), status_code = field::Empty, body_bytes = field::Empty, elapsed_ms = field::Empty)
+    )]
+    async fn send_with_fallback(&self, request: Request<'_>) -> Result<TransportResponse, Error> {
+        let started = Instant::now();
+        let start = self.active_base_url.load(Ordering::Relaxed);
+        let host_count = self.base_urls.len();
+        let mut attempt = 0;
+
+        loop {
+            let index = (start + attempt) % host_count;
+            let base_url = &self.base_urls[index];
+            trace!("Sending request to base URL {}", base_url);
+
+            let response = match request {
+                Request::Get(path) => self.get_validated(&endpoint_url(base_url, path), path).await,
+                Request::Post(path, body) => self.transport.post(&endpoint_url(base_url, path), body).await,
+            };
+
+            match response {
+                Ok(response) => {
+                    let span = Span::current();
+                    span.record("status_code", response.status);
+                    span.record("body_bytes", response.body.len());
+                    span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+                    self.rate_limits.record(response.status, &response.rate_limit);
+                    if index != start {
+                        info!("Switching to fallback base URL {}", base_url);
+                        self.active_base_url.store(index, Ordering::Relaxed);
+                    }
+                    // ERTFLIX answers addresses outside Greece with an HTML page, which would otherwise fail to parse
+                    if (response.is_success() || response.status == 403) && response.is_html() {
+                        warn!(
+                            "{} answered {} with an HTML page (Content-Type: {:?}), ERTFLIX is probably geo-blocking this address",
+                            request.path(),
+                            response.status,
+                            response.content_type
+                        );
+                        self.failures.lock().unwrap().errors += 1;
+                        return Err(Error::GeoBlocked(response.content_type));
+                    }
+                    return Ok(response);
+                }
+                Err(Error::Request(e)) if e.is_connect() && attempt + 1 < host_count => {
+                    self.failures.lock().unwrap().errors += 1;
+                    warn!("Failed to connect to {}: {}, trying next base URL", base_url, e);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let mut failures = self.failures.lock().unwrap();
+                    match e {
+                        Error::Timeout(_) => failures.timeouts += 1,
+                        _ => failures.errors += 1,
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+impl<Tr: Transport> DefaultErtflixClient<Tr> {
+    /// The fresh cached details of any of `tiles`, by id. The lookups run concurrently,
+    /// since each may be a Redis round trip.
+    async fn cached_tiles(&self, tiles: &[Tile]) -> HashMap<String, Tile> {
+        let Some((cache, _)) = &self.tile_cache else {
+            return HashMap::new();
+        };
+        let lookups = tiles.iter().map(|tile| async move {
+            match cache.get::<Tile>(&self.tile_cache_key(&tile.id)).await {
+                Lookup::Fresh(details) => Some((tile.id.clone(), details)),
+                _ => None,
             }
+        });
+        join_all(lookups).await.into_iter().flatten().collect()
+    }
+
+    async fn cache_tiles(&self, tiles: &[Tile]) {
+        if let Some((cache, ttl)) = &self.tile_cache {
+            let stores = tiles.iter().map(|tile| async move {
+                cache.set_with_ttl(&self.tile_cache_key(&tile.id), tile, *ttl).await;
+            });
+            join_all(stores).await;
+        }
+    }
+
+    fn tile_cache_key(&self, id: &str) -> String {
+        format!("tile:{}:{}", self.platform_codename, id)
+    }
+
+    /// GETs `url`, revalidating the body last seen for `path` when ERTFLIX sent validators
+    /// for it, so an unchanged endpoint answers 304 instead of sending the body again
+    async fn get_validated(&self, url: &str, path: &str) -> Result<TransportResponse, Error> {
+        let previous = self.validated_responses.lock().unwrap().get(path).cloned();
+        let Some(previous) = previous else {
+            let response = self.transport.get(url).await?;
+            self.remember_validated(path, &response);
+            return Ok(response);
+        };
+
+        let response = self.transport.get_conditional(url, &previous.validators).await?;
+        if response.is_not_modified() {
+            debug!("{} not modified, reusing the previous response", path);
+            return Ok(TransportResponse { rate_limit: response.rate_limit, ..previous });
         }
+        self.remember_validated(path, &response);
+        Ok(response)
     }
+
+    fn remember_validated(&self, path: &str, response: &TransportResponse) {
+        let mut validated_responses = self.validated_responses.lock().unwrap();
+        if response.is_success() && !response.validators.is_empty() {
+            trace!("Remembering validators {:?} for {}", response.validators, path);
+            validated_responses.insert(path.to_string(), response.clone());
+        } else {
+            validated_responses.remove(path);
+        }
+    }
+}
+
+/// Percent-encodes `value` for use in a URL query string
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
 }
 
+/// Codename of the section the tile `id` was listed in
+fn source_section(listed: &[(String, Tile)], id: &str) -> Option<String> {
+    listed.iter().find(|(_, tile)| tile.id == id).map(|(codename, _)| codename.clone())
+}
 
-trait ErtflixRequestBuilder {
-    fn with_ertflix_headers(self) -> Self;
+/// Joins a base URL and an endpoint path, defaulting to https when the base URL has no scheme
+fn endpoint_url(base_url: &str, path: &str) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    if base_url.starts_with("http://") || base_url.starts_with("https://") {
+        format!("{base_url}{path}")
+    } else {
+        format!("https://{base_url}{path}")
+    }
 }
 
-impl ErtflixRequestBuilder for RequestBuilder {
-    fn with_ertflix_headers(self) -> Self {
-        self.header(
-            "User-Agent",
-            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:142.0) Gecko/20100101 Firefox/142.0",
-        )
-            .header("Accept", "*/*")
-            .header("Accept-Language", "en")
-            .header("Origin", "https://www.ertflix.gr")
-            .header("DNT", "1")
-            .header("Connection", "keep-alive")
-            .header("Sec-Fetch-Dest", "empty")
-            .header("Sec-Fetch-Mode", "cors")
-            .header("Sec-Fetch-Site", "same-site")
-            .header("Pragma", "no-cache")
-            .header("Cache-Control", "no-cache")
-            .header("TE", "trailers")
-            .timeout(Duration::from_secs(config::TIMEOUT_SECONDS))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::transport::Validators;
+
+    const ETAG: &str = "\"section-v1\"";
+
+    /// Serves one section with an ETag and answers 304 whenever that ETag is sent back
+    #[derive(Default)]
+    struct RevalidatingTransport {
+        full_responses: AtomicUsize,
+    }
+
+    impl Transport for RevalidatingTransport {
+        async fn get(&self, _url: &str) -> Result<TransportResponse, Error> {
+            self.full_responses.fetch_add(1, Ordering::Relaxed);
+            Ok(TransportResponse {
+                status: 200,
+                body: r#"[{"toplistCodename":"movies","sectionId":1,"tilesIds":[]}]"#.to_string(),
+                validators: Validators { etag: Some(ETAG.to_string()), last_modified: None },
+                ..Default::default()
+            })
+        }
+
+        async fn get_conditional(&self, url: &str, validators: &Validators) -> Result<TransportResponse, Error> {
+            if validators.etag.as_deref() == Some(ETAG) {
+                return Ok(TransportResponse { status: 304, ..Default::default() });
+            }
+            self.get(url).await
+        }
+
+        async fn post(&self, url: &str, _body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            panic!("unexpected POST to {}", url)
+        }
+    }
+
+    #[actix_web::test]
+    async fn not_modified_section_keeps_the_previous_response() {
+        let client = DefaultErtflixClient::with_transport(RevalidatingTransport::default(), vec!["ertflix.test".to_string()]);
+
+        let first = client.get_section_content("movies".to_string()).await.unwrap();
+        let second = client.get_section_content("movies".to_string()).await.unwrap();
+
+        assert_eq!(client.transport.full_responses.load(Ordering::Relaxed), 1);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].section_id, first[0].section_id);
+        assert_eq!(second[0].toplist_codename.as_deref(), Some("movies"));
+    }
+
+    /// Answers from fixtures: one movie section listing and the GetTiles details of its tile
+    #[derive(Default)]
+    struct FixtureTransport;
+
+    impl Transport for FixtureTransport {
+        async fn get(&self, _url: &str) -> Result<TransportResponse, Error> {
+            let body = r#"[{"toplistCodename":"movies","sectionId":1,"tilesIds":[{"originEntityId":1,"codename":"o-thiasos","id":"movie-1"}]}]"#;
+            Ok(TransportResponse { status: 200, body: body.to_string(), ..Default::default() })
+        }
+
+        async fn post(&self, _url: &str, _body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            let body = r#"[{"originEntityId":1,"codename":"o-thiasos","id":"movie-1","title":"Ο Θίασος","year":1975}]"#;
+            Ok(TransportResponse { status: 200, body: body.to_string(), ..Default::default() })
+        }
+    }
+
+    #[actix_web::test]
+    async fn movies_are_fetched_through_the_injected_transport() {
+        let client = DefaultErtflixClient::with_transport(FixtureTransport, vec!["ertflix.test".to_string()]);
+
+        let movies = client.get_movies().await.unwrap();
+
+        assert_eq!(movies.len(), 1);
+        assert_eq!(movies[0].id, "movie-1");
+        assert_eq!(movies[0].codename, "o-thiasos");
+        assert_eq!(movies[0].title, "Ο Θίασος");
+        assert_eq!(movies[0].year, Some(1975));
+    }
+
+    /// Lists a titled tile in the movies section, whose GetTiles details leave the title out
+    #[derive(Default)]
+    struct UntitledDetails;
+
+    impl Transport for UntitledDetails {
+        async fn get(&self, _url: &str) -> Result<TransportResponse, Error> {
+            let body = r#"[{"toplistCodename":"movies","sectionId":1,"tilesIds":[{"originEntityId":1,"codename":"o-thiasos","id":"movie-1","title":"Ο Θίασος","year":1975}]}]"#;
+            Ok(TransportResponse { status: 200, body: body.to_string(), ..Default::default() })
+        }
+
+        async fn post(&self, _url: &str, _body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            let body = r#"[{"originEntityId":1,"codename":"o-thiasos","id":"movie-1","description":"Μια περιοδεύουσα θεατρική ομάδα"}]"#;
+            Ok(TransportResponse { status: 200, body: body.to_string(), ..Default::default() })
+        }
+    }
+
+    #[actix_web::test]
+    async fn fields_get_tiles_leaves_out_are_kept_from_the_section() {
+        let client = DefaultErtflixClient::with_transport(UntitledDetails, vec!["ertflix.test".to_string()]);
+
+        let movies = client.get_movies().await.unwrap();
+
+        assert_eq!(movies[0].title, "Ο Θίασος");
+        assert_eq!(movies[0].year, Some(1975));
+        assert_eq!(movies[0].description, "Μια περιοδεύουσα θεατρική ομάδα");
+    }
+
+    /// Answers every GetTiles request with the body in `0`
+    #[derive(Default)]
+    struct TilesAnswer(&'static str);
+
+    impl Transport for TilesAnswer {
+        async fn get(&self, url: &str) -> Result<TransportResponse, Error> {
+            panic!("unexpected GET {}", url)
+        }
+
+        async fn post(&self, _url: &str, _body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            Ok(TransportResponse { status: 200, body: self.0.to_string(), ..Default::default() })
+        }
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_tile_is_not_found_while_an_unreadable_answer_is_an_upstream_error() {
+        use actix_web::ResponseError;
+        use crate::error::AppError;
+        let get_tile = async |answer: &'static str| {
+            let client = DefaultErtflixClient::with_transport(TilesAnswer(answer), vec!["ertflix.test".to_string()]);
+            client.get_tile::<Tile>("gone".to_string()).await.unwrap_err()
+        };
+
+        let missing = get_tile("[]").await;
+        assert!(matches!(missing.downcast_ref::<Error>(), Some(Error::NotFound(id)) if id == "gone"));
+        assert_eq!(AppError::from_upstream(missing.as_ref()).status_code(), 404);
+
+        let unreadable = get_tile("not json").await;
+        assert!(matches!(unreadable.downcast_ref::<Error>(), Some(Error::Parse(_))));
+        assert_eq!(AppError::from_upstream(unreadable.as_ref()).status_code(), 502);
+    }
+
+    /// Lists the movie sections in `0` and refuses to fetch tile details
+    #[derive(Default)]
+    struct MovieSections(&'static str);
+
+    impl Transport for MovieSections {
+        async fn get(&self, _url: &str) -> Result<TransportResponse, Error> {
+            Ok(TransportResponse { status: 200, body: self.0.to_string(), ..Default::default() })
+        }
+
+        async fn post(&self, url: &str, _body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            panic!("unexpected POST to {}", url)
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_section_with_an_empty_tile_list_is_an_empty_library() {
+        let client = DefaultErtflixClient::with_transport(
+            MovieSections(r#"[{"toplistCodename":"movies","sectionId":1,"tilesIds":[]}]"#),
+            vec!["ertflix.test".to_string()],
+        );
+
+        assert!(client.get_movies().await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn a_section_without_a_tile_list_or_no_section_at_all_is_an_error() {
+        let movies = async |sections: &'static str| {
+            let client = DefaultErtflixClient::with_transport(MovieSections(sections), vec!["ertflix.test".to_string()]);
+            client.get_movies().await.unwrap_err().to_string()
+        };
+
+        assert!(movies(r#"[{"toplistCodename":"movies","sectionId":1,"tilesIds":null}]"#).await.contains("No tiles found"));
+        assert!(movies("[]").await.contains("No movie section found"));
+    }
+
+    #[actix_web::test]
+    async fn lists_are_read_bare_or_wrapped_in_a_data_envelope() {
+        for sections in [
+            r#"[{"toplistCodename":"movies","sectionId":1,"tilesIds":[]}]"#,
+            r#"{"data":[{"toplistCodename":"movies","sectionId":1,"tilesIds":[]}]}"#,
+        ] {
+            let client = DefaultErtflixClient::with_transport(MovieSections(sections), vec!["ertflix.test".to_string()]);
+            let section = client.get_section_content("movies".to_string()).await.unwrap();
+            assert_eq!(section.len(), 1, "{}", sections);
+            assert_eq!(section[0].section_id, 1, "{}", sections);
+        }
+
+        for tiles in [
+            r#"[{"originEntityId":1,"codename":"tile-codename","id":"tile","title":"Tile"}]"#,
+            r#"{"data":[{"originEntityId":1,"codename":"tile-codename","id":"tile","title":"Tile"}]}"#,
+        ] {
+            let client = DefaultErtflixClient::with_transport(TilesAnswer(tiles), vec!["ertflix.test".to_string()]);
+            let tile = client.get_tile::<Tile>("tile".to_string()).await.unwrap();
+            assert_eq!(tile.title.as_deref(), Some("Tile"), "{}", tiles);
+        }
+    }
+
+    /// A connection error like the one reqwest gives when nothing listens on the host
+    async fn connection_refused() -> reqwest::Error {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        reqwest::get(format!("http://127.0.0.1:{}", port)).await.unwrap_err()
+    }
+
+    /// Refuses connections to `down.test` and serves one section from any other host,
+    /// recording the URLs asked for
+    #[derive(Default)]
+    struct FirstHostDown {
+        requested: Mutex<Vec<String>>,
+    }
+
+    impl Transport for FirstHostDown {
+        async fn get(&self, url: &str) -> Result<TransportResponse, Error> {
+            self.requested.lock().unwrap().push(url.to_string());
+            if url.contains("down.test") {
+                return Err(Error::Request(connection_refused().await));
+            }
+            Ok(TransportResponse {
+                status: 200,
+                body: r#"[{"toplistCodename":"movies","sectionId":1,"tilesIds":[]}]"#.to_string(),
+                ..Default::default()
+            })
+        }
+
+        async fn post(&self, url: &str, _body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            panic!("unexpected POST to {}", url)
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_next_base_url_is_used_once_the_primary_cannot_be_reached() {
+        let client = DefaultErtflixClient::with_transport(
+            FirstHostDown::default(),
+            vec!["http://down.test".to_string(), "http://up.test".to_string()],
+        );
+
+        let sections = client.get_section_content("movies".to_string()).await.unwrap();
+        client.get_section_content("movies".to_string()).await.unwrap();
+
+        assert_eq!(sections[0].toplist_codename.as_deref(), Some("movies"));
+        let hosts: Vec<&str> = client
+            .transport
+            .requested
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|url| if url.contains("down.test") { "down" } else { "up" })
+            .collect();
+        // The working host is remembered, so the second request goes straight to it
+        assert_eq!(hosts, ["down", "up", "up"]);
+    }
+
+    /// Serves a main page with one section with a toplist and two without, sharing a tile
+    #[derive(Default)]
+    struct PageWithUncategorizedSections;
+
+    impl Transport for PageWithUncategorizedSections {
+        async fn get(&self, _url: &str) -> Result<TransportResponse, Error> {
+            let section = |codename: Option<&str>, id: i32, tiles: &[&str]| {
+                serde_json::json!({
+                    "toplistCodename": codename,
+                    "sectionId": id,
+                    "tilesIds": tiles.iter().map(|id| serde_json::json!({ "originEntityId": 1, "codename": id, "id": id })).collect::<Vec<_>>(),
+                })
+            };
+            let body = serde_json::json!({
+                "sectionContents": [
+                    section(None, 7, &["orphan-1", "shared"]),
+                    section(Some("movies"), 1, &["movie-1"]),
+                    section(None, 8, &["shared", "orphan-2"]),
+                ],
+            });
+            Ok(TransportResponse { status: 200, body: body.to_string(), ..Default::default() })
+        }
+
+        async fn post(&self, url: &str, _body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            panic!("unexpected POST to {}", url)
+        }
+    }
+
+    #[actix_web::test]
+    async fn sections_without_a_toplist_are_collected_last_when_configured() {
+        let client = DefaultErtflixClient::with_transport(PageWithUncategorizedSections, vec!["ertflix.test".to_string()]);
+        let listed = |sections: Vec<SectionContents>| {
+            sections
+                .into_iter()
+                .map(|section| {
+                    let tiles: Vec<String> = section.tiles_ids.into_iter().flatten().map(|tile| tile.id).collect();
+                    (section.section_id, section.toplist_codename, tiles)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let dropped = client.get_collections(|section| section).await.unwrap();
+        assert_eq!(listed(dropped), vec![(1, Some("movies".to_string()), vec!["movie-1".to_string()])]);
+
+        *client.uncategorized_collection.write().unwrap() = Some("Other".to_string());
+        let collected = client.get_collections(|section| section).await.unwrap();
+        assert_eq!(
+            listed(collected),
+            vec![
+                (1, Some("movies".to_string()), vec!["movie-1".to_string()]),
+                (
+                    UNCATEGORIZED_SECTION_ID,
+                    Some("Other".to_string()),
+                    vec!["orphan-1".to_string(), "shared".to_string(), "orphan-2".to_string()],
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_errors_are_shown_with_the_body_around_them() {
+        let tile = r#"{"id":"ταινία","title":"Ο Θίασος"}"#;
+        let body = format!("[\n{}\n{},{{\"id\":oops}}\n{}]", vec![tile; 20].join(",\n"), tile, vec![tile; 20].join(",\n"));
+        let e = serde_json::from_str::<Vec<serde_json::Value>>(&body).unwrap_err();
+
+        let snippet = parse_error_snippet(&body, e.line(), e.column());
+
+        assert_eq!(e.line(), 22);
+        assert!(snippet.contains(r#"{"id":oops}"#), "{}", snippet);
+        assert!(snippet.len() <= 2 * PARSE_ERROR_CONTEXT_BYTES + 3, "{}", snippet);
+    }
+
+    /// Collects what a `fmt` subscriber writes
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn client_events_reach_the_tracing_subscriber() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let client = DefaultErtflixClient::with_transport(RevalidatingTransport::default(), vec!["ertflix.test".to_string()]);
+        client.get_section_content("movies".to_string()).await.unwrap();
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("Successfully fetched 1 section contents for movies"), "{}", output);
+    }
+
+    #[actix_web::test]
+    async fn request_spans_record_the_status_and_body_size() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_max_level(tracing::Level::DEBUG)
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let client = DefaultErtflixClient::with_transport(RevalidatingTransport::default(), vec!["ertflix.test".to_string()]);
+        client.get_section_content("movies".to_string()).await.unwrap();
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let closed = output.lines().find(|line| line.contains("ertflix_request{") && line.contains("close")).expect(&output);
+        assert!(closed.contains("status_code=200 body_bytes=58"), "{}", closed);
+    }
+
+    /// Answers GetTiles with the requested tiles in reverse order, recording the ids asked for
+    #[derive(Default)]
+    struct TilesTransport {
+        requested: Mutex<Vec<String>>,
+    }
+
+    impl Transport for TilesTransport {
+        async fn get(&self, url: &str) -> Result<TransportResponse, Error> {
+            panic!("unexpected GET {}", url)
+        }
+
+        async fn post(&self, _url: &str, body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            let ids: Vec<String> = body["requestedTiles"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|tile| tile["id"].as_str().unwrap().to_string())
+                .collect();
+            self.requested.lock().unwrap().extend(ids.iter().cloned());
+            let tiles: Vec<serde_json::Value> = ids
+                .iter()
+                .rev()
+                .map(|id| serde_json::json!({ "originEntityId": 1, "codename": id, "id": id, "title": id }))
+                .collect();
+            Ok(TransportResponse { status: 200, body: serde_json::Value::from(tiles).to_string(), ..Default::default() })
+        }
+    }
+
+    #[actix_web::test]
+    async fn cached_tiles_are_not_fetched_again_and_order_is_kept() {
+        let client = DefaultErtflixClient::with_transport(TilesTransport::default(), vec!["ertflix.test".to_string()])
+            .with_tile_cache(Arc::new(Cache::new(Duration::ZERO)), Duration::from_secs(60));
+        let request = |ids: &[&str]| ids.iter().map(|id| Tile { id: id.to_string(), ..Default::default() }).collect::<Vec<_>>();
+        let ids = |tiles: Vec<Tile>| tiles.into_iter().map(|tile| tile.id).collect::<Vec<_>>();
+
+        let first = client.get_tiles::<Tile>(request(&["a", "b"])).await.unwrap();
+        let second = client.get_tiles::<Tile>(request(&["c", "b", "a"])).await.unwrap();
+
+        assert_eq!(ids(first), ["a", "b"]);
+        assert_eq!(ids(second), ["c", "b", "a"]);
+        assert_eq!(*client.transport.requested.lock().unwrap(), ["a", "b", "c"]);
+    }
+
+    #[actix_web::test]
+    async fn a_truncated_tiles_answer_keeps_its_complete_tiles_only_in_best_effort_mode() {
+        const TRUNCATED: &str = r#"[{"originEntityId":1,"codename":"a","id":"a","title":"A"},{"originEntityId":1,"codename":"b","id":"b","ti"#;
+        let requested = ["a", "b"].map(|id| Tile { id: id.to_string(), ..Default::default() });
+        let mut client = DefaultErtflixClient::with_transport(TilesAnswer(TRUNCATED), vec!["ertflix.test".to_string()]);
+
+        let error = client.get_tile_batch(&requested).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(Error::Parse(_))), "{}", error);
+
+        client.best_effort_tiles = true;
+        let tiles = client.get_tile_batch(&requested).await.unwrap();
+        assert_eq!(tiles.iter().map(|tile| tile.id.as_str()).collect::<Vec<_>>(), ["a"]);
+    }
+
+    /// Serves a movies section of one tile, recording the URLs and POST bodies it receives
+    #[derive(Default)]
+    struct RecordingTransport {
+        urls: Mutex<Vec<String>>,
+        bodies: Mutex<Vec<serde_json::Value>>,
+    }
+
+    impl Transport for RecordingTransport {
+        async fn get(&self, url: &str) -> Result<TransportResponse, Error> {
+            self.urls.lock().unwrap().push(url.to_string());
+            Ok(TransportResponse {
+                status: 200,
+                body: r#"[{"toplistCodename":"movies","sectionId":1,"tilesIds":[{"originEntityId":1,"codename":"a","id":"a"}]}]"#.to_string(),
+                ..Default::default()
+            })
+        }
+
+        async fn post(&self, url: &str, body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            self.urls.lock().unwrap().push(url.to_string());
+            self.bodies.lock().unwrap().push(body.clone());
+            Ok(TransportResponse { status: 200, body: r#"[{"originEntityId":1,"codename":"a","id":"a","title":"A"}]"#.to_string(), ..Default::default() })
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_configured_platform_is_sent_in_urls_and_tile_requests() {
+        let mut client = DefaultErtflixClient::with_transport(RecordingTransport::default(), vec!["ertflix.test".to_string()]);
+        client.platform_codename = "tv".to_string();
+
+        assert_eq!(client.get_movies().await.unwrap().len(), 1);
+
+        let urls = client.transport.urls.lock().unwrap();
+        let section_url = urls.iter().find(|url| url.contains("GetSectionContent")).unwrap();
+        assert!(section_url.contains("platformCodename=tv&"), "{}", section_url);
+        assert!(urls.iter().all(|url| !url.contains("platformCodename=www")), "{:?}", urls);
+        let bodies = client.transport.bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0]["platformCodename"], "tv");
+    }
+
+    #[actix_web::test]
+    async fn ids_and_codenames_cannot_add_parameters_to_the_urls() {
+        let client = DefaultErtflixClient::with_transport(RecordingTransport::default(), vec!["ertflix.test".to_string()]);
+
+        client.get_section_content("x&limit=1".to_string()).await.unwrap();
+        // The recorded answer is no series details or stream, only the URLs matter
+        let _ = client.get_show_seasons("show&id=other".to_string()).await;
+        let _ = client.get_stream_url("movie&codename=other".to_string()).await;
+
+        let urls = client.transport.urls.lock().unwrap();
+        assert!(urls[0].contains("sectionCodename=x%26limit%3D1&"), "{}", urls[0]);
+        assert!(urls[1].contains("id=show%26id%3Dother&"), "{}", urls[1]);
+        assert!(urls[2].contains("codename=movie%26codename%3Dother&"), "{}", urls[2]);
+    }
+
+    /// Answers GETs with the statuses it was given, in order, and one section once they run out
+    #[derive(Default)]
+    struct FlakyTransport {
+        statuses: Mutex<Vec<u16>>,
+        requests: AtomicUsize,
+    }
+
+    impl FlakyTransport {
+        fn new(statuses: &[u16]) -> Self {
+            Self { statuses: Mutex::new(statuses.iter().rev().copied().collect()), requests: AtomicUsize::new(0) }
+        }
+    }
+
+    impl Transport for FlakyTransport {
+        async fn get(&self, _url: &str) -> Result<TransportResponse, Error> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let Some(status) = self.statuses.lock().unwrap().pop() else {
+                return Ok(TransportResponse {
+                    status: 200,
+                    body: r#"[{"toplistCodename":"movies","sectionId":1,"tilesIds":[]}]"#.to_string(),
+                    ..Default::default()
+                });
+            };
+            Ok(TransportResponse { status, ..Default::default() })
+        }
+
+        async fn post(&self, url: &str, _body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            panic!("unexpected POST to {}", url)
+        }
+    }
+
+    #[actix_web::test]
+    async fn server_errors_are_retried_until_the_retries_run_out_but_client_errors_are_not() {
+        let requests = async |statuses: &[u16], max_retries: u32| {
+            let mut client = DefaultErtflixClient::with_transport(FlakyTransport::new(statuses), vec!["ertflix.test".to_string()]);
+            client.max_retries = max_retries;
+            client.retry_base_delay = Duration::from_millis(1);
+            let succeeded = client.get_section_content("movies".to_string()).await.is_ok();
+            (succeeded, client.transport.requests.load(Ordering::SeqCst))
+        };
+
+        assert_eq!(requests(&[503, 502], 2).await, (true, 3));
+        assert_eq!(requests(&[503, 502, 500], 2).await, (false, 3));
+        assert_eq!(requests(&[404], 2).await, (false, 1));
+    }
+
+    #[test]
+    fn the_backoff_doubles_up_to_its_cap() {
+        assert_eq!(next_backoff(Duration::from_millis(250)), Duration::from_millis(500));
+        assert_eq!(next_backoff(Duration::from_secs(20)), MAX_BACKOFF);
+        assert_eq!(next_backoff(MAX_BACKOFF), MAX_BACKOFF);
+        assert_eq!(next_backoff(Duration::MAX), MAX_BACKOFF);
+
+        let mut delay = Duration::from_millis(100);
+        for _ in 0..64 {
+            delay = next_backoff(delay);
+        }
+        assert_eq!(delay, MAX_BACKOFF);
     }
 }
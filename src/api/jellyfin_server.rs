@@ -1,6 +1,6 @@
+use crate::clock::{Clock, SystemClock};
 use crate::config;
-use chrono;
-use chrono::{Datelike, Timelike};
+use crate::models::jellyfin::ItemId;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info, trace};
 use uuid::Uuid;
@@ -15,17 +15,69 @@ pub struct AuthenticationResponse {
 }
 
 impl AuthenticationResponse {
-    pub fn default(emby_authorization_header: EmbyAuthorizationHeader) -> Self {
+    /// The default response, with its login and activity dates taken from `clock`
+    pub fn with_clock(emby_authorization_header: EmbyAuthorizationHeader, clock: &dyn Clock) -> Self {
         info!("Creating default authentication response");
         debug!("Initializing authentication response with default user");
         trace!("Authentication response creation completed");
         Self {
-            user: User::default(),
-            server_id: config::SERVER_ID.into(),
+            user: User::with_clock(clock),
+            server_id: config::identity().server_id.clone(),
             access_token: Uuid::new_v4().to_string(),
-            session_info: SessionInfo::from(emby_authorization_header),
+            session_info: SessionInfo::from_header(emby_authorization_header, clock),
         }
     }
+
+    pub fn access_token(&self) -> &str {
+        &self.access_token
+    }
+
+    pub fn session_info(&self) -> &SessionInfo {
+        &self.session_info
+    }
+
+    /// Signs the session in as a guest named `name` instead of the configured user
+    pub fn with_guest(mut self, name: &str) -> Self {
+        info!("Signing in {} as a guest", name);
+        self.user = User::guest(name);
+        self.session_info.user_id = config::GUEST_USER_ID.into();
+        self.session_info.user_name = name.into();
+        self
+    }
+
+    /// Sets the user's default audio and subtitle track language from an ISO 639-1 code
+    pub fn with_language(mut self, language: &str) -> Self {
+        let track_language = track_language(language);
+        debug!("Using {} for default audio and subtitle tracks", track_language);
+        self.user.configuration.audio_language_preference = track_language.clone();
+        self.user.configuration.subtitle_language_preference = track_language;
+        self
+    }
+}
+
+/// Maps an ISO 639-1 code to the ISO 639-2 code Jellyfin uses for track languages
+fn track_language(language: &str) -> String {
+    match language {
+        "en" => "eng".into(),
+        "el" => "gre".into(),
+        other => other.into(),
+    }
+}
+
+/// Body of the `/Sessions/Playing/*` playback reports
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlaybackProgressInfo {
+    pub item_id: ItemId,
+    pub play_session_id: Option<String>,
+    pub position_ticks: Option<i64>,
+}
+
+impl PlaybackProgressInfo {
+    /// Identifies the stream this report belongs to
+    pub fn stream_key(&self) -> &str {
+        self.play_session_id.as_deref().unwrap_or(self.item_id.as_str())
+    }
 }
 
 #[derive(Serialize)]
@@ -45,17 +97,17 @@ impl Default for SystemInfo {
         info!("Creating default system info response");
         debug!(
             "Setting up system info with server ID: {}",
-            config::SERVER_ID
+            config::identity().server_id
         );
         trace!("System info configured with local address: http://localhost:25860");
 
         let system_info = Self {
             local_address: "http://localhost:25860".into(),
-            server_name: "Ertflix Adapter".into(),
+            server_name: config::identity().server_name.clone(),
             version: "10.8.0".into(),
             product_name: "Jellyfin Server".into(),
             operating_system: "Linux".into(),
-            id: config::SERVER_ID.into(),
+            id: config::identity().server_id.clone(),
             startup_wizard_completed: true,
         };
 
@@ -150,15 +202,22 @@ pub struct Policy {
 
 impl Default for User {
     fn default() -> Self {
+        Self::with_clock(&SystemClock)
+    }
+}
+
+impl User {
+    /// The configured user, last seen at the time `clock` reports
+    pub fn with_clock(clock: &dyn Clock) -> Self {
         info!("Creating default user configuration");
-        debug!("Setting up user with server ID: {}", config::SERVER_ID);
+        debug!("Setting up user with server ID: {}", config::identity().server_id);
         trace!("User configured with administrative privileges");
 
-        let timestamp = create_jellyfin_timestamp();
+        let timestamp = clock.jellyfin_timestamp();
         
         let user = Self {
-            name: "antonis".into(),
-            server_id: config::SERVER_ID.into(),
+            name: config::identity().username.clone(),
+            server_id: config::identity().server_id.clone(),
             id: Uuid::new_v4().to_string(),
             has_password: true,
             has_configured_password: true,
@@ -173,6 +232,18 @@ impl Default for User {
         debug!("Default user configuration completed");
         user
     }
+
+    /// A passwordless user that can browse and play but not manage anything
+    pub fn guest(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            id: config::GUEST_USER_ID.into(),
+            has_password: false,
+            has_configured_password: false,
+            policy: Policy::guest(),
+            ..Self::default()
+        }
+    }
 }
 
 impl Default for Configuration {
@@ -263,6 +334,25 @@ impl Default for Policy {
     }
 }
 
+impl Policy {
+    /// Playback only: no administration, deletion, downloads or control of other sessions
+    pub fn guest() -> Self {
+        Self {
+            is_administrator: false,
+            enable_user_preference_access: false,
+            enable_remote_control_of_other_users: false,
+            enable_shared_device_control: false,
+            enable_live_tv_management: false,
+            enable_content_deletion: false,
+            enable_content_downloading: false,
+            enable_sync_transcoding: false,
+            enable_media_conversion: false,
+            enable_public_sharing: false,
+            sync_play_access: "None".to_string(),
+            ..Self::default()
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -293,7 +383,14 @@ pub struct SessionInfo {
 
 impl Default for SessionInfo {
     fn default() -> Self {
-        let timestamp = create_jellyfin_timestamp();
+        Self::with_clock(&SystemClock)
+    }
+}
+
+impl SessionInfo {
+    /// A session last active at the time `clock` reports
+    pub fn with_clock(clock: &dyn Clock) -> Self {
+        let timestamp = clock.jellyfin_timestamp();
         
         Self {
             play_state: PlayState::default(),
@@ -302,8 +399,8 @@ impl Default for SessionInfo {
             remote_end_point: "".to_string(),
             playable_media_types: vec![],
             id: Uuid::new_v4().into(),
-            user_id: config::USER_ID.into(),
-            user_name: config::USERNAME.into(),
+            user_id: config::identity().user_id.clone(),
+            user_name: config::identity().username.clone(),
             client: "web".to_string(),
             last_activity_date: timestamp.clone(),
             last_playback_check_in: timestamp,
@@ -316,21 +413,27 @@ impl Default for SessionInfo {
             now_playing_queue: vec![],
             now_playing_queue_full_items: vec![],
             has_custom_device_name: false,
-            server_id: config::SERVER_ID.into(),
+            server_id: config::identity().server_id.clone(),
             supported_commands: vec![],
         }
-    }   
+    }
+
+    /// The session of the client that sent `header`
+    pub fn from_header(header: EmbyAuthorizationHeader, clock: &dyn Clock) -> Self {
+        Self {
+            device_name: header.device,
+            device_id: header.device_id,
+            client: header.client,
+            application_version: header.version,
+            id: Uuid::new_v4().to_string(),
+            ..Self::with_clock(clock)
+        }
+    }
 }
 
 impl From<EmbyAuthorizationHeader> for SessionInfo {
     fn from(header: EmbyAuthorizationHeader) -> Self {
-        let mut session_info = SessionInfo::default();
-        session_info.device_name = header.device;
-        session_info.device_id = header.device_id;
-        session_info.client = header.client;
-        session_info.application_version = header.version;
-        session_info.id = Uuid::new_v4().to_string();
-        session_info
+        Self::from_header(header, &SystemClock)
     }
 }
 
@@ -388,16 +491,3 @@ impl FromStr for EmbyAuthorizationHeader {
         Ok(EmbyAuthorizationHeader { version, device, device_id, client })
     }
 }
-
-fn create_jellyfin_timestamp() -> String {
-    format!(
-        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:07}Z",
-        chrono::Utc::now().year(),
-        chrono::Utc::now().month(),
-        chrono::Utc::now().day(),
-        chrono::Utc::now().hour(),
-        chrono::Utc::now().minute(),
-        chrono::Utc::now().second(),
-        chrono::Utc::now().nanosecond() / 100
-    )
-}
\ No newline at end of file
@@ -0,0 +1,138 @@
+//! An `ErtflixClient` serving a small canned library, so handlers can be tested without
+//! the live API or a fake transport.
+
+use std::error;
+use crate::api::ertflix_client::{
+    Error, ErtflixClient, SectionContents, Tile, TileImage, MOVIES_SECTION_CODENAME, TV_SHOWS_SECTION_CODENAME,
+};
+use crate::models::ertflix;
+
+/// Serves two movies and one series of two episodes, listed in the movies and TV shows sections
+pub struct MockErtflixClient {
+    movies: Vec<Tile>,
+    tv_shows: Vec<Tile>,
+}
+
+fn tile(id: &str, title: &str, year: Option<u32>) -> Tile {
+    Tile {
+        origin_entity_id: 1,
+        codename: format!("{}-codename", id),
+        id: id.to_string(),
+        year,
+        description: Some(format!("Description of {}", title)),
+        title: Some(title.to_string()),
+        images: vec![TileImage { role: "poster".into(), url: format!("https://images.mock/{}/poster.jpg", id) }],
+        ..Default::default()
+    }
+}
+
+impl MockErtflixClient {
+    fn sections(&self) -> Vec<SectionContents> {
+        vec![
+            SectionContents {
+                toplist_codename: Some(MOVIES_SECTION_CODENAME.to_string()),
+                section_id: 1,
+                tiles_ids: Some(self.movies.clone()),
+            },
+            SectionContents {
+                toplist_codename: Some(TV_SHOWS_SECTION_CODENAME.to_string()),
+                section_id: 2,
+                tiles_ids: Some(self.tv_shows.clone()),
+            },
+        ]
+    }
+
+    fn all_tiles(&self) -> impl Iterator<Item = &Tile> {
+        self.movies.iter().chain(&self.tv_shows)
+    }
+}
+
+impl ErtflixClient for MockErtflixClient {
+    fn new(_base_url: &str) -> Self {
+        Self {
+            movies: vec![
+                tile("mock-movie-1", "Η Λίμνη", Some(1999)),
+                tile("mock-movie-2", "Ο Φάρος", Some(2012)),
+            ],
+            tv_shows: vec![tile("mock-series-1", "Το Νησί", Some(2010))],
+        }
+    }
+
+    async fn get_collections<CollectionCategory>(
+        &self,
+        filtering_strategy: fn(SectionContents) -> CollectionCategory,
+    ) -> Result<Vec<CollectionCategory>, Box<dyn error::Error>> {
+        Ok(self.sections().into_iter().map(filtering_strategy).collect())
+    }
+
+    async fn get_movies(&self) -> Result<Vec<ertflix::Movie>, Box<dyn error::Error>> {
+        Ok(self.movies.iter().cloned().map(ertflix::Movie::from).collect())
+    }
+
+    async fn get_tv_shows(&self) -> Result<Vec<ertflix::TVShow>, Box<dyn error::Error>> {
+        Ok(self.tv_shows.iter().cloned().map(ertflix::TVShow::from).collect())
+    }
+
+    async fn get_show_seasons(&self, show_id: String) -> Result<Vec<ertflix::Season>, Box<dyn error::Error>> {
+        if !self.tv_shows.iter().any(|show| show.id == show_id) {
+            return Err(Box::new(Error::NotFound(show_id)));
+        }
+        let episode = |number: u32| ertflix::Episode {
+            id: format!("{}-episode-{}", show_id, number),
+            title: format!("Επεισόδιο {}", number),
+            duration: 2700,
+            episode_number: Some(number),
+        };
+        Ok(vec![ertflix::Season { season_number: 1, episodes: vec![episode(1), episode(2)] }])
+    }
+
+    async fn get_stream_url(&self, codename: String) -> Result<String, Box<dyn error::Error>> {
+        if self.all_tiles().any(|tile| tile.codename == codename) {
+            Ok(format!("https://streams.mock/{}/master.m3u8", codename))
+        } else {
+            Err(Box::new(Error::NotFound(codename)))
+        }
+    }
+
+    async fn get_section_content(
+        &self,
+        section_codename: String,
+    ) -> Result<Vec<SectionContents>, Box<dyn error::Error>> {
+        Ok(self
+            .sections()
+            .into_iter()
+            .filter(|section| section.toplist_codename.as_deref() == Some(section_codename.as_str()))
+            .collect())
+    }
+
+    async fn search(&self, query: String) -> Result<Vec<Tile>, Box<dyn error::Error>> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .all_tiles()
+            .filter(|tile| tile.title.as_deref().is_some_and(|title| title.to_lowercase().contains(&query)))
+            .cloned()
+            .collect())
+    }
+
+    async fn ping(&self) -> Result<(), Box<dyn error::Error>> {
+        Ok(())
+    }
+
+    async fn get_tiles<TileType>(
+        &self,
+        section_tiles: Vec<Tile>,
+    ) -> Result<Vec<TileType>, Box<dyn error::Error>>
+    where
+        TileType: From<Tile>,
+    {
+        Ok(section_tiles
+            .iter()
+            .filter_map(|requested| self.all_tiles().find(|tile| tile.id == requested.id))
+            .cloned()
+            .map(TileType::from)
+            .collect())
+    }
+}
@@ -1,2 +1,6 @@
 pub mod ertflix_client;
 pub mod jellyfin_server;
+#[cfg(test)]
+pub mod mock_ertflix_client;
+pub mod rate_limit;
+pub mod transport;
@@ -0,0 +1,136 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, warn};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use crate::clock::{Clock, SystemClock};
+
+const RATE_LIMIT_REMAINING: &str = "x-ratelimit-remaining";
+
+/// Rate limit headers of one Ertflix response. Either may be absent, and values that
+/// can't be read are treated as absent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// `X-RateLimit-Remaining`: requests left in the current window
+    pub remaining: Option<u64>,
+    /// `Retry-After`, given in seconds or as an HTTP date
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimit {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self::with_clock(headers, &SystemClock)
+    }
+
+    /// The rate limit of the headers, counting a `Retry-After` date from the time `clock` reports
+    pub fn with_clock(headers: &HeaderMap, clock: &dyn Clock) -> Self {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+        Self {
+            remaining: header(RATE_LIMIT_REMAINING).and_then(parse_remaining),
+            retry_after: header(RETRY_AFTER.as_str()).and_then(|value| parse_retry_after(value, clock)),
+        }
+    }
+}
+
+fn parse_remaining(value: &str) -> Option<u64> {
+    let remaining = value
+        .parse::<u64>()
+        .ok()
+        .or_else(|| value.parse::<f64>().ok().filter(|remaining| remaining.is_finite() && *remaining >= 0.0).map(|remaining| remaining as u64));
+    if remaining.is_none() {
+        warn!("Ignoring unreadable X-RateLimit-Remaining header: {:?}", value);
+    }
+    remaining
+}
+
+fn parse_retry_after(value: &str, clock: &dyn Clock) -> Option<Duration> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    match chrono::DateTime::parse_from_rfc2822(value) {
+        Ok(date) => Some((date.with_timezone(&chrono::Utc) - clock.now()).to_std().unwrap_or(Duration::ZERO)),
+        Err(_) => {
+            warn!("Ignoring unreadable Retry-After header: {:?}", value);
+            None
+        }
+    }
+}
+
+/// What `/metrics` reports about the rate limits Ertflix announced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitSnapshot {
+    /// `X-RateLimit-Remaining` of the last response that sent it
+    pub remaining: Option<u64>,
+    /// `Retry-After` of the last response, zero once a response comes without one
+    pub retry_after_seconds: u64,
+    /// Responses that asked to retry later, with a 429 status or a `Retry-After` header
+    pub throttled_responses: u64,
+}
+
+/// Gauges and counters fed by the rate limit headers of every Ertflix response
+#[derive(Debug, Default)]
+pub struct RateLimitMetrics {
+    snapshot: Mutex<RateLimitSnapshot>,
+}
+
+impl RateLimitMetrics {
+    pub fn record(&self, status: u16, rate_limit: &RateLimit) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        if let Some(remaining) = rate_limit.remaining {
+            snapshot.remaining = Some(remaining);
+        }
+        snapshot.retry_after_seconds = rate_limit.retry_after.map_or(0, |retry_after| retry_after.as_secs());
+        if status == 429 || rate_limit.retry_after.is_some() {
+            snapshot.throttled_responses += 1;
+            debug!("Ertflix asked to retry in {}s (status {})", snapshot.retry_after_seconds, status);
+        }
+    }
+
+    pub fn snapshot(&self) -> RateLimitSnapshot {
+        *self.snapshot.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use crate::clock::FixedClock;
+
+    #[test]
+    fn rate_limit_headers_update_the_metrics() {
+        let metrics = RateLimitMetrics::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from_static("42"));
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("30"));
+
+        metrics.record(429, &RateLimit::from_headers(&headers));
+        assert_eq!(
+            metrics.snapshot(),
+            RateLimitSnapshot { remaining: Some(42), retry_after_seconds: 30, throttled_responses: 1 }
+        );
+
+        // Absent or unreadable headers keep the last remaining count and clear Retry-After
+        let mut headers = HeaderMap::new();
+        headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from_static("lots"));
+        metrics.record(200, &RateLimit::from_headers(&headers));
+        assert_eq!(
+            metrics.snapshot(),
+            RateLimitSnapshot { remaining: Some(42), retry_after_seconds: 0, throttled_responses: 1 }
+        );
+    }
+
+    #[test]
+    fn a_retry_after_date_counts_from_the_clock() {
+        let clock = FixedClock::at("2024-05-01T12:30:00Z");
+        let retry_after = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(value));
+            RateLimit::with_clock(&headers, &clock).retry_after
+        };
+
+        assert_eq!(retry_after("Wed, 01 May 2024 12:31:30 GMT"), Some(Duration::from_secs(90)));
+        // Dates already past mean retrying right away
+        assert_eq!(retry_after("Wed, 01 May 2024 12:00:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(retry_after("120"), Some(Duration::from_secs(120)));
+    }
+}
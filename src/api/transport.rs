@@ -0,0 +1,268 @@
+use crate::api::ertflix_client::Error;
+use crate::api::rate_limit::RateLimit;
+use crate::config;
+use tracing::{debug, error, trace};
+use reqwest::{header, Client, RequestBuilder, Response};
+use std::time::Duration;
+
+/// Cache validators of a response, sent back on the next request for the same endpoint
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// The raw outcome of an HTTP exchange with Ertflix
+#[derive(Debug, Clone, Default)]
+pub struct TransportResponse {
+    pub status: u16,
+    pub body: String,
+    pub content_type: Option<String>,
+    pub validators: Validators,
+    pub rate_limit: RateLimit,
+}
+
+impl TransportResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn is_not_modified(&self) -> bool {
+        self.status == 304
+    }
+
+    /// Whether the body is an HTML page rather than JSON, going by the `Content-Type` or,
+    /// when that is missing, by the body opening with a tag
+    pub fn is_html(&self) -> bool {
+        match &self.content_type {
+            Some(content_type) => content_type.to_ascii_lowercase().contains("html"),
+            None => self.body.trim_start().starts_with('<'),
+        }
+    }
+}
+
+/// Carries requests to the Ertflix API.
+///
+/// `DefaultErtflixClient` builds URLs and parses bodies; the transport only moves
+/// bytes, so tests can serve fixtures and alternative HTTP stacks can be plugged in.
+pub trait Transport {
+    async fn get(&self, url: &str) -> Result<TransportResponse, Error>;
+
+    /// Sends a GET that may be answered with 304 Not Modified when `validators` still match.
+    /// Transports that can't send conditional requests fall back to a plain GET.
+    async fn get_conditional(&self, url: &str, validators: &Validators) -> Result<TransportResponse, Error> {
+        trace!("Transport ignores validators {:?} for {}", validators, url);
+        self.get(url).await
+    }
+
+    async fn post(&self, url: &str, body: &serde_json::Value) -> Result<TransportResponse, Error>;
+}
+
+/// `reqwest` backed transport sending the headers the Ertflix web client sends. Bodies
+/// are decompressed by `reqwest`, which advertises the encodings it decodes.
+pub struct ReqwestTransport {
+    pub client: Client,
+}
+
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        Self {
+            client: Client::builder().gzip(true).deflate(true).brotli(true).build().unwrap_or_else(|e| {
+                error!("Failed to build the HTTP client, falling back to the default one: {}", e);
+                Client::new()
+            }),
+        }
+    }
+}
+
+impl ReqwestTransport {
+    async fn send(&self, request: RequestBuilder) -> Result<TransportResponse, Error> {
+        let response = request.send().await.map_err(|e| {
+            let e = Error::from_request(e);
+            error!("HTTP request failed: {}", e);
+            e
+        })?;
+
+        let status = response.status().as_u16();
+        debug!("Received response with status: {}", status);
+        let header = |name: header::HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = Validators {
+            etag: header(header::ETAG),
+            last_modified: header(header::LAST_MODIFIED),
+        };
+        let rate_limit = RateLimit::from_headers(response.headers());
+        if rate_limit != RateLimit::default() {
+            debug!("Rate limit headers: {:?}", rate_limit);
+        }
+
+        let content_type = header(header::CONTENT_TYPE);
+        let body = body_text(read_body(response, MAX_BODY_BYTES).await?)?;
+        trace!("Response body length: {} bytes", body.len());
+
+        Ok(TransportResponse { status, body, content_type, validators, rate_limit })
+    }
+}
+
+impl Transport for ReqwestTransport {
+    async fn get(&self, url: &str) -> Result<TransportResponse, Error> {
+        trace!("GET {}", url);
+        self.send(self.client.get(url).with_ertflix_headers()).await
+    }
+
+    async fn get_conditional(&self, url: &str, validators: &Validators) -> Result<TransportResponse, Error> {
+        trace!("Conditional GET {} with {:?}", url, validators);
+        let mut request = self.client.get(url).with_ertflix_headers();
+        if let Some(etag) = &validators.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        self.send(request).await
+    }
+
+    async fn post(&self, url: &str, body: &serde_json::Value) -> Result<TransportResponse, Error> {
+        trace!("POST {}", url);
+        self.send(self.client.post(url).with_ertflix_headers().json(body)).await
+    }
+}
+
+/// Largest response body accepted once decoded, so a small compressed body can't expand
+/// without bound
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+/// Reads the body `reqwest` decompresses as it arrives, chunk by chunk, failing as soon as
+/// it grows past `limit` bytes rather than after buffering all of it
+async fn read_body(mut response: Response, limit: usize) -> Result<Vec<u8>, Error> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| {
+        error!("Failed to read response body: {}", e);
+        Error::from_request(e)
+    })? {
+        if body.len() + chunk.len() > limit {
+            error!("Response body is larger than {} bytes once decoded", limit);
+            return Err(Error::Custom(format!("Response body is larger than {} bytes once decoded", limit)));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// The text of a decoded body. A body still compressed, because it came without a
+/// `Content-Encoding` or with one `reqwest` doesn't decode, is an error, as it would
+/// otherwise surface as a confusing parse error.
+fn body_text(body: Vec<u8>) -> Result<String, Error> {
+    if let Some(format) = compressed_format(&body) {
+        error!("Response body is {} compressed but was not decoded", format);
+        return Err(Error::Custom(format!("Response body is {} compressed but was not decoded", format)));
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Recognizes compressed data by its magic bytes
+fn compressed_format(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x1f, 0x8b, ..] => Some("gzip"),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Some("zstd"),
+        [0x78, 0x01 | 0x5e | 0x9c | 0xda, ..] => Some("zlib"),
+        _ => None,
+    }
+}
+
+trait ErtflixRequestBuilder {
+    fn with_ertflix_headers(self) -> Self;
+}
+
+impl ErtflixRequestBuilder for RequestBuilder {
+    fn with_ertflix_headers(self) -> Self {
+        self.header(
+            "User-Agent",
+            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:142.0) Gecko/20100101 Firefox/142.0",
+        )
+            .header("Accept", "*/*")
+            .header("Accept-Language", "en")
+            .header("Origin", "https://www.ertflix.gr")
+            .header("DNT", "1")
+            .header("Connection", "keep-alive")
+            .header("Sec-Fetch-Dest", "empty")
+            .header("Sec-Fetch-Mode", "cors")
+            .header("Sec-Fetch-Site", "same-site")
+            .header("Pragma", "no-cache")
+            .header("Cache-Control", "no-cache")
+            .header("TE", "trailers")
+            .timeout(Duration::from_secs(config::TIMEOUT_SECONDS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Answers a single request with `body`, sent with `Content-Encoding: gzip`
+    fn serve_gzipped(body: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/section", listener.local_addr().unwrap());
+        let body = gzip(body);
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 4096]);
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(&body);
+        });
+        url
+    }
+
+    #[actix_web::test]
+    async fn gzip_encoded_responses_are_decoded() {
+        let url = serve_gzipped(br#"{"sectionId":1}"#);
+
+        let response = ReqwestTransport::default().get(&url).await.unwrap();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, r#"{"sectionId":1}"#);
+    }
+
+    #[test]
+    fn a_compressed_body_without_content_encoding_is_an_error() {
+        let error = body_text(gzip(b"{}")).unwrap_err();
+        assert!(error.to_string().contains("gzip compressed but was not decoded"), "{}", error);
+    }
+
+    #[actix_web::test]
+    async fn bodies_larger_than_the_limit_once_decoded_are_an_error() {
+        let body = [b' '; 1000];
+        let client = ReqwestTransport::default().client;
+
+        let response = client.get(serve_gzipped(&body)).send().await.unwrap();
+        assert_eq!(read_body(response, 1000).await.unwrap().len(), 1000);
+        let response = client.get(serve_gzipped(&body)).send().await.unwrap();
+        let error = read_body(response, 999).await.unwrap_err();
+        assert!(error.to_string().contains("larger than 999 bytes"), "{}", error);
+    }
+}
@@ -0,0 +1,54 @@
+//! Where the current time comes from. Constructors of time-stamped responses take a
+//! `Clock`, so tests can pin the time and assert on exact output.
+
+use chrono::{DateTime, Timelike, Utc};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// The current time as Jellyfin formats dates, with seven fractional digits,
+    /// e.g. `2024-05-01T12:30:00.1234567Z`
+    fn jellyfin_timestamp(&self) -> String {
+        let now = self.now();
+        format!("{}.{:07}Z", now.format("%Y-%m-%dT%H:%M:%S"), now.nanosecond() % 1_000_000_000 / 100)
+    }
+}
+
+/// The system clock, used outside tests
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Reports the same instant whenever it is asked
+#[cfg(test)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+#[cfg(test)]
+impl FixedClock {
+    /// A clock stopped at `rfc3339`, e.g. `2024-05-01T12:30:00.1234567Z`
+    pub fn at(rfc3339: &str) -> Self {
+        Self(DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc))
+    }
+}
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_have_seven_fractional_digits() {
+        assert_eq!(FixedClock::at("2024-05-01T12:30:05.123456789Z").jellyfin_timestamp(), "2024-05-01T12:30:05.1234567Z");
+        assert_eq!(FixedClock::at("2024-05-01T08:00:00Z").jellyfin_timestamp(), "2024-05-01T08:00:00.0000000Z");
+    }
+}
@@ -1,30 +1,732 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error;
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use serde::{Deserialize, Serialize};
-use log::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub ertflix: ErtflixConfig,
     pub redis: RedisConfig,
     pub cache: CacheConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub images: ImageConfig,
+    #[serde(default)]
+    pub polling: PollingConfig,
+    #[serde(default)]
+    pub cache_warming: CacheWarmingConfig,
+    #[serde(default)]
+    pub streams: StreamsConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub full_episodes: FullEpisodesConfig,
+    #[serde(default)]
+    pub language: LanguageConfig,
+    #[serde(default)]
+    pub library: LibraryConfig,
+    #[serde(default)]
+    pub sorting: SortingConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub identity: IdentityConfig,
+}
+
+impl Config {
+    /// Loads the configuration from the JSON file named by the `ERTFLIX_2_JELLYFIN_CONFIG`
+    /// environment variable. Sections the file leaves out keep their defaults, and the
+    /// defaults are used as they are when the variable is unset or the file doesn't exist.
+    pub fn load() -> Result<Self, Box<dyn error::Error>> {
+        let Some(path) = std::env::var_os(CONFIG_PATH_ENV) else {
+            info!("{} is not set, using the default configuration", CONFIG_PATH_ENV);
+            return Ok(Self::default());
+        };
+        let path = Path::new(&path);
+        if !path.exists() {
+            warn!("Configuration file {} does not exist, using the default configuration", path.display());
+            return Ok(Self::default());
+        }
+        Self::from_file(path)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn error::Error>> {
+        info!("Loading configuration from {}", path.display());
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let config: Self = serde_json::from_str(&contents)
+            .map_err(|e| format!("invalid configuration in {}: {}", path.display(), e))?;
+        debug!("Loaded configuration with ERTFLIX base URL {}", config.ertflix.base_url);
+        Ok(config)
+    }
+
+    /// The settings that differ in `other`, split by whether they can change while serving
+    pub fn changes(&self, other: &Config) -> ConfigChanges {
+        let mut changed = Vec::new();
+        match (serde_json::to_value(self), serde_json::to_value(other)) {
+            (Ok(old), Ok(new)) => changed_paths("", &old, &new, &mut changed),
+            (Err(e), _) | (_, Err(e)) => warn!("Failed to compare configurations: {}", e),
+        }
+        let (need_restart, applied) = changed
+            .into_iter()
+            .partition(|path| RESTART_REQUIRED.iter().any(|prefix| path.starts_with(prefix)));
+        ConfigChanges { applied, need_restart }
+    }
+}
+
+/// Settings only read at startup, as prefixes of the dotted paths `Config::changes` reports
+const RESTART_REQUIRED: &[&str] = &[
+    "server.",
+    "redis.",
+    "identity.",
+    "polling.",
+    "cache_warming.",
+    "streams.",
+    "logging.",
+    "full_episodes.",
+    "images.max_concurrent_fetches",
+    "admin.idempotency_window_seconds",
+    "ertflix.base_url",
+    "ertflix.fallback_base_urls",
+    "ertflix.max_concurrent_requests",
+    "ertflix.best_effort_tiles",
+    "ertflix.platform_codename",
+    "ertflix.tag_source_sections",
+    "ertflix.tile_batch_size",
+    "ertflix.partial_tile_batches",
+    "ertflix.max_retries",
+    "ertflix.retry_base_delay_millis",
+    "cache.stale_grace_seconds",
+    "cache.local_ttl_millis",
+    "cache.tiles_ttl_seconds",
+    "library.min_year",
+    "library.merge_movie_parts",
+    "library.remote_trailers",
+    "library.max_collection_children",
+];
+
+/// Collects the dotted paths of the values that differ between `old` and `new`
+fn changed_paths(path: &str, old: &serde_json::Value, new: &serde_json::Value, changed: &mut Vec<String>) {
+    match (old, new) {
+        (serde_json::Value::Object(old), serde_json::Value::Object(new)) => {
+            let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for key in keys {
+                let null = serde_json::Value::Null;
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                changed_paths(&child, old.get(key).unwrap_or(&null), new.get(key).unwrap_or(&null), changed);
+            }
+        }
+        _ if old != new => changed.push(path.to_string()),
+        _ => {}
+    }
+}
+
+/// What reloading the configuration changed, as dotted paths like `cache.movies_ttl_seconds`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConfigChanges {
+    /// Changes that took effect right away
+    pub applied: Vec<String>,
+    /// Changes to settings only read at startup, which take effect after a restart
+    pub need_restart: Vec<String>,
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.need_restart.is_empty()
+    }
+}
+
+/// The configuration requests are served with. Reloading swaps it as a whole, so a
+/// request sees either the old or the new configuration, never a mix of both.
+pub struct SharedConfig {
+    current: RwLock<Arc<Config>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        Self { current: RwLock::new(Arc::new(config)) }
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Swaps in `config`, returning what changed
+    pub fn replace(&self, config: Config) -> ConfigChanges {
+        let changes = self.current().changes(&config);
+        *self.current.write().unwrap() = Arc::new(config);
+        changes
+    }
+}
+
+/// How the server and its single user introduce themselves to clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdentityConfig {
+    pub server_id: String,
+    pub server_name: String,
+    pub user_id: String,
+    pub username: String,
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        Self {
+            server_id: DEFAULT_SERVER_ID.into(),
+            server_name: DEFAULT_SERVER_NAME.into(),
+            user_id: DEFAULT_USER_ID.into(),
+            username: DEFAULT_USERNAME.into(),
+        }
+    }
+}
+
+static IDENTITY: OnceLock<IdentityConfig> = OnceLock::new();
+
+/// The identity installed by `set_identity` at startup, or the default one before that
+pub fn identity() -> &'static IdentityConfig {
+    IDENTITY.get_or_init(IdentityConfig::default)
+}
+
+/// Installs the identity of the loaded configuration. Only the first call takes effect.
+pub fn set_identity(identity: IdentityConfig) {
+    debug!("Identifying as server {} for user {}", identity.server_id, identity.username);
+    if IDENTITY.set(identity).is_err() {
+        warn!("Server identity was already set, keeping the first one");
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Sign in usernames other than the configured user as a guest with a restricted policy
+    pub allow_guest: bool,
+    /// Accept any username and password and serve requests without a session token, as
+    /// releases before credentials were checked did
+    pub allow_all: bool,
+    /// Argon2 hash of the configured user's password, as a PHC string (`$argon2id$v=19$...`).
+    /// Without one the user has no password and only an empty one is accepted.
+    pub password_hash: Option<String>,
+}
+
+impl AuthConfig {
+    /// Whether `username` and `password` are those of the configured user
+    pub fn accepts(&self, username: &str, password: &str) -> bool {
+        if username != identity().username {
+            return false;
+        }
+        match &self.password_hash {
+            Some(expected) => match PasswordHash::new(expected.trim()) {
+                Ok(expected) => Argon2::default().verify_password(password.as_bytes(), &expected).is_ok(),
+                Err(e) => {
+                    warn!("auth.password_hash is not an Argon2 PHC string, refusing the sign in: {}", e);
+                    false
+                }
+            },
+            None => password.is_empty(),
+        }
+    }
+}
+
+/// Compares secrets in time independent of where they differ
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// How a listing is ordered, using Jellyfin's `SortBy` and `SortOrder` values
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SortSpec {
+    pub sort_by: String,
+    pub sort_order: String,
+}
+
+impl SortSpec {
+    pub fn new(sort_by: &str, sort_order: &str) -> Self {
+        Self { sort_by: sort_by.into(), sort_order: sort_order.into() }
+    }
+
+    pub fn is_descending(&self) -> bool {
+        self.sort_order.eq_ignore_ascii_case("Descending")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SortingConfig {
+    /// Used for listings without a sort of their own
+    pub default: SortSpec,
+    /// Default sort per listing, keyed by `movies`, `tv_shows` or a collection's `ParentId`
+    pub collections: HashMap<String, SortSpec>,
+}
+
+impl Default for SortingConfig {
+    fn default() -> Self {
+        Self {
+            default: SortSpec::new("SortName", "Ascending"),
+            collections: HashMap::from([
+                ("movies".to_string(), SortSpec::new("ProductionYear", "Descending")),
+                ("tv_shows".to_string(), SortSpec::new("SortName", "Ascending")),
+            ]),
+        }
+    }
+}
+
+impl SortingConfig {
+    pub fn for_collection(&self, key: &str) -> &SortSpec {
+        self.collections.get(key).unwrap_or(&self.default)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryConfig {
+    /// Leave out movies released before this year; movies without a known year are kept
+    pub min_year: Option<u32>,
+    /// Advertise items as downloadable. ERTFLIX streams can't be downloaded through the adapter.
+    pub can_download: bool,
+    /// Advertise items as deletable. The library is read-only, so this is off by default.
+    pub can_delete: bool,
+    /// Merge movies ERTFLIX splits into separate "Part 1"/"Μέρος 2" tiles into one item with a
+    /// media source per part. Off by default, since titles alone can cause false merges.
+    pub merge_movie_parts: bool,
+    /// List the trailers ERTFLIX has for items as `RemoteTrailers`
+    pub remote_trailers: bool,
+    /// Most children kept per collection in `/UserViews`; larger sections report this as their child count
+    pub max_collection_children: usize,
+    /// Answer `/movies` and `/tv` with 503 when ERTFLIX lists no items of that type, e.g. after a
+    /// section was renamed, so monitoring notices. Off by default, answering with an empty list.
+    pub unavailable_when_empty: bool,
+    /// Leave the empty and default fields of collections out of `/UserViews`, apart from
+    /// the ones clients rely on
+    pub slim_collections: bool,
+    /// Most items a listing answers with at once, whatever `Limit` asks for
+    pub max_page_size: usize,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            min_year: None,
+            can_download: false,
+            can_delete: false,
+            merge_movie_parts: false,
+            remote_trailers: true,
+            max_collection_children: 1000,
+            unavailable_when_empty: false,
+            slim_collections: false,
+            max_page_size: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LanguageConfig {
+    /// ISO 639-1 code used when the client's Accept-Language matches nothing supported
+    pub default_language: String,
+    /// ISO 639-1 codes clients may choose through Accept-Language
+    pub supported_languages: Vec<String>,
+}
+
+impl Default for LanguageConfig {
+    fn default() -> Self {
+        Self {
+            default_language: "en".into(),
+            supported_languages: vec!["en".into(), "el".into()],
+        }
+    }
+}
+
+impl LanguageConfig {
+    /// Picks the supported language the `Accept-Language` header ranks highest,
+    /// falling back to the default language
+    pub fn negotiate(&self, accept_language: Option<&str>) -> String {
+        let mut preferences: Vec<(String, f32)> = accept_language
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.trim().split(';');
+                let tag = parts.next()?.trim();
+                let primary = tag.split('-').next()?.to_lowercase();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (!primary.is_empty() && quality > 0.0).then_some((primary, quality))
+            })
+            .collect();
+        preferences.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        preferences
+            .into_iter()
+            .map(|(language, _)| language)
+            .find(|language| self.supported_languages.iter().any(|supported| supported.eq_ignore_ascii_case(language)))
+            .unwrap_or_else(|| self.default_language.clone())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FullEpisodesConfig {
+    /// Drop clips and trailers from series so only full episodes remain
+    pub enabled: bool,
+    /// Episodes shorter than this are treated as clips; 0 disables the duration check
+    pub min_duration_seconds: u32,
+    /// Case-insensitive title fragments that mark a tile or episode as a clip
+    pub clip_title_patterns: Vec<String>,
+}
+
+impl Default for FullEpisodesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_duration_seconds: 600, // 10 minutes
+            clip_title_patterns: vec![
+                "trailer".into(),
+                "τρέιλερ".into(),
+                "clip".into(),
+                "απόσπασμα".into(),
+            ],
+        }
+    }
+}
+
+impl FullEpisodesConfig {
+    /// Whether a title marks a clip rather than a full episode or series
+    pub fn is_clip_title(&self, title: &str) -> bool {
+        let title = title.to_lowercase();
+        self.clip_title_patterns
+            .iter()
+            .any(|pattern| title.contains(&pattern.to_lowercase()))
+    }
+
+    /// Whether an episode is too short or titled like a clip; episodes without a known duration are kept
+    pub fn is_clip_episode(&self, title: &str, duration_seconds: u32) -> bool {
+        (duration_seconds > 0 && duration_seconds < self.min_duration_seconds) || self.is_clip_title(title)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// actix-web `Logger` format string, see https://docs.rs/actix-web/latest/actix_web/middleware/struct.Logger.html
+    pub request_format: String,
+    /// Query parameters whose values are replaced before the request line is logged
+    pub redacted_query_params: Vec<String>,
+    /// Headers that are never logged, even if the format asks for them
+    pub redacted_headers: Vec<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            request_format: r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#.into(),
+            redacted_query_params: vec![
+                "token".into(),
+                "api_key".into(),
+                "access_token".into(),
+                "password".into(),
+            ],
+            redacted_headers: vec![
+                "Authorization".into(),
+                "X-Emby-Authorization".into(),
+                "X-Emby-Token".into(),
+                "X-MediaBrowser-Token".into(),
+                "X-Admin-Token".into(),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamsConfig {
+    /// A stream that has not reported progress for this long is considered stopped
+    pub session_timeout_seconds: u64,
+    /// How long a resolved stream URL is reused before it is resolved again
+    pub url_ttl_seconds: u64,
+    /// Periodically resolve the stream URLs of favorite items ahead of playback
+    pub prefetch_favorites: bool,
+    /// Should be shorter than `url_ttl_seconds` so favorites never hold an expired URL
+    pub prefetch_interval_seconds: u64,
+    /// Maximum number of stream URLs resolved at once while prefetching
+    pub prefetch_concurrency: usize,
+}
+
+impl Default for StreamsConfig {
+    fn default() -> Self {
+        Self {
+            session_timeout_seconds: 300, // 5 minutes
+            url_ttl_seconds: 1800,        // 30 minutes
+            prefetch_favorites: false,
+            prefetch_interval_seconds: 900, // 15 minutes
+            prefetch_concurrency: 4,
+        }
+    }
+}
+
+impl StreamsConfig {
+    /// How long a silent stream counts as active, at least a second so the sweeper can tick
+    pub fn session_timeout(&self) -> Duration {
+        Duration::from_secs(self.session_timeout_seconds.max(1))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollingConfig {
+    /// Periodically check the Ertflix page content and refresh the library when it changes
+    pub enabled: bool,
+    pub interval_seconds: u64,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_seconds: 300, // 5 minutes
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheWarmingConfig {
+    /// Periodically fetch collections, movies and TV shows into the cache, so requests
+    /// never wait on ERTFLIX for them
+    pub enabled: bool,
+    /// Should be shorter than the shortest of their TTLs, so entries are replaced before they expire
+    pub interval_seconds: u64,
+}
+
+impl Default for CacheWarmingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_seconds: 1500, // 25 minutes, within the 30 minutes collections are cached for
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageConfig {
+    /// Width/height ratio of primary images, 2:3 for posters
+    pub primary_aspect_ratio: f64,
+    pub collections_primary_aspect_ratio: Option<f64>,
+    pub movies_primary_aspect_ratio: Option<f64>,
+    pub tv_shows_primary_aspect_ratio: Option<f64>,
+    /// Serve artwork through the adapter; when off, image requests are redirected to the ERTFLIX CDN
+    pub proxy: bool,
+    /// Upper bound on artwork downloads from the CDN at once, independent of ERTFLIX API requests
+    pub max_concurrent_fetches: usize,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            primary_aspect_ratio: 2.0 / 3.0,
+            collections_primary_aspect_ratio: None,
+            movies_primary_aspect_ratio: None,
+            tv_shows_primary_aspect_ratio: None,
+            proxy: true,
+            max_concurrent_fetches: 8,
+        }
+    }
+}
+
+impl ImageConfig {
+    pub fn collections_aspect_ratio(&self) -> f64 {
+        self.collections_primary_aspect_ratio.unwrap_or(self.primary_aspect_ratio)
+    }
+
+    pub fn movies_aspect_ratio(&self) -> f64 {
+        self.movies_primary_aspect_ratio.unwrap_or(self.primary_aspect_ratio)
+    }
+
+    pub fn tv_shows_aspect_ratio(&self) -> f64 {
+        self.tv_shows_primary_aspect_ratio.unwrap_or(self.primary_aspect_ratio)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Number of HTTP worker threads, defaults to the number of available CPUs
+    pub workers: usize,
+    /// Address and port to listen on
+    pub bind_address: String,
+    /// How long in-flight requests may take to finish after SIGINT or SIGTERM
+    pub shutdown_timeout_seconds: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        debug!("Defaulting HTTP worker count to {} available CPUs", workers);
+        Self { workers, bind_address: DEFAULT_BIND_ADDRESS.into(), shutdown_timeout_seconds: 30 }
+    }
+}
+
+impl ServerConfig {
+    /// Number of HTTP workers to start, at least one
+    pub fn worker_count(&self) -> usize {
+        self.workers.max(1)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ErtflixConfig {
     pub base_url: String,
+    /// Hosts tried in order when the primary `base_url` cannot be reached
+    pub fallback_base_urls: Vec<String>,
+    /// Upper bound on ERTFLIX requests issued at once for bulk work
+    pub max_concurrent_requests: usize,
+    /// When a GetTiles response is cut off, keep the tiles that arrived complete instead of failing
+    pub best_effort_tiles: bool,
+    /// ERTFLIX platform to request content for, e.g. `www`, `tv` or `mobile`
+    pub platform_codename: String,
+    /// Debugging aid: tag every item with the codename of the section it was listed in.
+    /// Off by default so internal codenames don't reach clients.
+    pub tag_source_sections: bool,
+    /// Most tile ids requested in one GetTiles call; larger lists are split into batches
+    /// fetched concurrently, up to `max_concurrent_requests` at once
+    pub tile_batch_size: usize,
+    /// When some GetTiles batches fail, keep the tiles of the others instead of failing
+    pub partial_tile_batches: bool,
+    /// How often a request failing with a connection error, timeout or 5xx answer is retried
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further one and jittered
+    pub retry_base_delay_millis: u64,
+    /// Sections listing the movies: one codename, or a list whose sections are aggregated
+    #[serde(rename = "movies_section_codename", deserialize_with = "one_or_many")]
+    pub movies_section_codenames: Vec<String>,
+    /// Sections listing the TV shows: one codename, or a list whose sections are aggregated
+    #[serde(rename = "tv_shows_section_codename", deserialize_with = "one_or_many")]
+    pub tv_shows_section_codenames: Vec<String>,
+    /// Name of a collection, listed last, holding the tiles of the sections that have no
+    /// toplist codename. Those tiles are dropped when unset.
+    pub uncategorized_collection: Option<String>,
+}
+
+impl Default for ErtflixConfig {
+    fn default() -> Self {
+        Self {
+            base_url: ERTFLIX_API_URL.to_string(),
+            fallback_base_urls: vec![],
+            max_concurrent_requests: 4,
+            best_effort_tiles: false,
+            platform_codename: DEFAULT_PLATFORM_CODENAME.to_string(),
+            tag_source_sections: false,
+            tile_batch_size: DEFAULT_TILE_BATCH_SIZE,
+            partial_tile_batches: false,
+            max_retries: 3,
+            retry_base_delay_millis: 200,
+            movies_section_codenames: vec![crate::api::ertflix_client::MOVIES_SECTION_CODENAME.to_string()],
+            tv_shows_section_codenames: vec![crate::api::ertflix_client::TV_SHOWS_SECTION_CODENAME.to_string()],
+            uncategorized_collection: None,
+        }
+    }
+}
+
+/// Reads either a single string or a list of them
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(codename) => vec![codename],
+        OneOrMany::Many(codenames) => codenames,
+    })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RedisConfig {
+    /// Keeps the shared cache in Redis instead of in memory, so it survives restarts
+    pub enabled: bool,
     pub url: String,
     pub connection_pool_size: u32,
 }
 
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "redis://127.0.0.1:6379".to_string(),
+            connection_pool_size: 10,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CacheConfig {
     pub default_ttl_seconds: u64,
     pub movies_ttl_seconds: u64,
     pub tv_shows_ttl_seconds: u64,
     pub collections_ttl_seconds: u64,
+    /// How long past its TTL an entry may still be served while it is refreshed in the background
+    pub stale_grace_seconds: u64,
+    /// How long each worker keeps its own copy of a fresh entry before reading the shared cache again; 0 disables it
+    pub local_ttl_millis: u64,
+    /// How long the details of a single tile are reused, so sections listing the same tile
+    /// don't fetch it again; 0 disables the tile cache
+    pub tiles_ttl_seconds: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            default_ttl_seconds: 3600,     // 1 hour
+            movies_ttl_seconds: 7200,      // 2 hours
+            tv_shows_ttl_seconds: 3600,    // 1 hour
+            collections_ttl_seconds: 1800, // 30 minutes
+            stale_grace_seconds: 300,      // 5 minutes
+            local_ttl_millis: 1000,
+            tiles_ttl_seconds: 7200,       // 2 hours
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Token expected in the `X-Admin-Token` header. When unset, admin endpoints are only
+    /// open to sessions of the configured user.
+    pub token: Option<String>,
+    /// How long a response is remembered for a given `Idempotency-Key`
+    pub idempotency_window_seconds: u64,
+    /// Known-good movie tile id that `/admin/selftest` fetches and converts
+    pub selftest_tile_id: Option<String>,
+    /// Let `/movies` and `/tv` list another ERTFLIX section through `?section=<codename>`,
+    /// for debugging clients against it. Off by default.
+    pub section_overrides: bool,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            idempotency_window_seconds: 600, // 10 minutes
+            selftest_tile_id: None,
+            section_overrides: false,
+        }
+    }
 }
 
 impl Default for Config {
@@ -35,19 +737,22 @@ impl Default for Config {
         debug!("Setting up default cache TTL values");
 
         let config = Self {
-            ertflix: ErtflixConfig {
-                base_url: ERTFLIX_API_URL.to_string(),
-            },
-            redis: RedisConfig {
-                url: "redis://127.0.0.1:6379".to_string(),
-                connection_pool_size: 10,
-            },
-            cache: CacheConfig {
-                default_ttl_seconds: 3600,     // 1 hour
-                movies_ttl_seconds: 7200,      // 2 hours
-                tv_shows_ttl_seconds: 3600,    // 1 hour
-                collections_ttl_seconds: 1800, // 30 minutes
-            },
+            ertflix: ErtflixConfig::default(),
+            redis: RedisConfig::default(),
+            cache: CacheConfig::default(),
+            admin: AdminConfig::default(),
+            server: ServerConfig::default(),
+            images: ImageConfig::default(),
+            polling: PollingConfig::default(),
+            cache_warming: CacheWarmingConfig::default(),
+            streams: StreamsConfig::default(),
+            logging: LoggingConfig::default(),
+            full_episodes: FullEpisodesConfig::default(),
+            language: LanguageConfig::default(),
+            library: LibraryConfig::default(),
+            sorting: SortingConfig::default(),
+            auth: AuthConfig::default(),
+            identity: IdentityConfig::default(),
         };
 
         trace!("Default configuration created with cache TTLs - default: {}s, movies: {}s, TV shows: {}s, collections: {}s",
@@ -60,7 +765,100 @@ impl Default for Config {
 }
 
 pub const ERTFLIX_API_URL: &str = "https://api.ertflix.gr";
+pub const DEFAULT_PLATFORM_CODENAME: &str = "www";
+pub const DEFAULT_TILE_BATCH_SIZE: usize = 50;
 pub const TIMEOUT_SECONDS: u64 = 30; // Timeout for API requests
-pub const SERVER_ID: &str = "optiplex-adapter"; // Replace with your actual server ID
-pub const USER_ID: &str = "optiplex-user"; // Replace with your actual user ID
-pub const USERNAME: &str = "antonis"; // Replace with your actual username
\ No newline at end of file
+pub const DEFAULT_SERVER_ID: &str = "optiplex-adapter";
+pub const DEFAULT_SERVER_NAME: &str = "Ertflix Adapter";
+pub const DEFAULT_USER_ID: &str = "optiplex-user";
+pub const DEFAULT_USERNAME: &str = "antonis";
+pub const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:25860";
+/// Environment variable naming the JSON configuration file
+pub const CONFIG_PATH_ENV: &str = "ERTFLIX_2_JELLYFIN_CONFIG";
+pub const GUEST_USER_ID: &str = "optiplex-guest";
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_configured_worker_count_is_used_but_never_zero() {
+        let config: Config = serde_json::from_str(r#"{ "server": { "workers": 3 } }"#).unwrap();
+        assert_eq!(config.server.worker_count(), 3);
+
+        let config: Config = serde_json::from_str(r#"{ "server": { "workers": 0 } }"#).unwrap();
+        assert_eq!(config.server.worker_count(), 1);
+
+        let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        assert_eq!(Config::default().server.worker_count(), cpus);
+    }
+
+    #[test]
+    fn a_zero_session_timeout_is_raised_to_a_second() {
+        let config: Config = serde_json::from_str(r#"{ "streams": { "session_timeout_seconds": 0 } }"#).unwrap();
+        assert_eq!(config.streams.session_timeout(), Duration::from_secs(1));
+        assert_eq!(Config::default().streams.session_timeout(), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn sections_given_in_part_keep_the_defaults_of_the_fields_left_out() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "redis": { "enabled": true },
+                "cache": { "movies_ttl_seconds": 60 },
+                "polling": { "enabled": true },
+                "logging": { "redacted_headers": [] },
+                "language": { "default_language": "el" },
+                "sorting": { "collections": {} }
+            }"#,
+        )
+        .unwrap();
+        let defaults = Config::default();
+
+        assert!(config.redis.enabled);
+        assert_eq!(config.redis.url, defaults.redis.url);
+        assert_eq!(config.cache.movies_ttl_seconds, 60);
+        assert_eq!(config.cache.collections_ttl_seconds, defaults.cache.collections_ttl_seconds);
+        assert_eq!(config.polling.interval_seconds, defaults.polling.interval_seconds);
+        assert_eq!(config.logging.request_format, defaults.logging.request_format);
+        assert_eq!(config.language.supported_languages, defaults.language.supported_languages);
+        assert_eq!(config.sorting.default.sort_by, defaults.sorting.default.sort_by);
+    }
+
+    #[test]
+    fn an_ertflix_section_without_a_base_url_keeps_the_default_one() {
+        let config: Config = serde_json::from_str(r#"{ "ertflix": { "max_retries": 1 } }"#).unwrap();
+
+        assert_eq!(config.ertflix.max_retries, 1);
+        assert_eq!(config.ertflix.base_url, ERTFLIX_API_URL);
+        assert_eq!(config.ertflix.platform_codename, DEFAULT_PLATFORM_CODENAME);
+        assert_eq!(config.ertflix.movies_section_codenames, ErtflixConfig::default().movies_section_codenames);
+    }
+
+    #[test]
+    fn a_full_episodes_section_that_only_enables_the_filter_keeps_the_default_patterns() {
+        let config: Config = serde_json::from_str(r#"{ "full_episodes": { "enabled": true } }"#).unwrap();
+
+        assert!(config.full_episodes.enabled);
+        assert_eq!(config.full_episodes.min_duration_seconds, 600);
+        assert_eq!(config.full_episodes.clip_title_patterns, FullEpisodesConfig::default().clip_title_patterns);
+    }
+
+    #[test]
+    fn an_images_section_that_only_turns_off_the_proxy_keeps_the_default_ratios() {
+        let config: Config = serde_json::from_str(r#"{ "images": { "proxy": false } }"#).unwrap();
+
+        assert!(!config.images.proxy);
+        assert_eq!(config.images.primary_aspect_ratio, 2.0 / 3.0);
+        assert_eq!(config.images.max_concurrent_fetches, 8);
+    }
+
+    #[test]
+    fn a_password_hash_that_is_not_an_argon2_phc_string_accepts_no_password() {
+        // The hex SHA-1 earlier releases expected, of "hunter2"
+        let auth = AuthConfig { password_hash: Some("f3bbbd66a63d4bf1747940578ec3d0103530e21d".into()), ..Default::default() };
+
+        for password in ["hunter2", "f3bbbd66a63d4bf1747940578ec3d0103530e21d", ""] {
+            assert!(!auth.accepts(&identity().username, password), "{:?}", password);
+        }
+    }
+}
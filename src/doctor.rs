@@ -0,0 +1,158 @@
+use std::net::ToSocketAddrs;
+use reqwest::Url;
+use tracing::{debug, info};
+
+use crate::api::ertflix_client::ErtflixClient;
+use crate::config::Config;
+use crate::services::redis_store::RedisStore;
+
+/// Outcome of a single connectivity check
+pub struct CheckResult {
+    pub name: String,
+    pub outcome: Result<String, String>,
+}
+
+impl CheckResult {
+    fn new(name: &str, outcome: Result<String, String>) -> Self {
+        Self { name: name.to_string(), outcome }
+    }
+}
+
+/// Runs every check against the configured Ertflix host, printing one line per
+/// check. Returns whether all of them passed.
+pub async fn run<T: ErtflixClient>(config: &Config) -> bool {
+    info!("Running connectivity checks against {}", config.ertflix.base_url);
+    let client = T::from_config(&config.ertflix);
+    let results = run_checks(&client, config).await;
+
+    for result in &results {
+        match &result.outcome {
+            Ok(detail) => println!("[PASS] {}: {}", result.name, detail),
+            Err(detail) => println!("[FAIL] {}: {}", result.name, detail),
+        }
+    }
+
+    let failures = results.iter().filter(|result| result.outcome.is_err()).count();
+    println!("{} checks, {} failed", results.len(), failures);
+    failures == 0
+}
+
+pub async fn run_checks<T: ErtflixClient>(client: &T, config: &Config) -> Vec<CheckResult> {
+    let mut results = vec![CheckResult::new("DNS resolution", check_dns(&config.ertflix.base_url))];
+
+    debug!("Checking GetPageContent");
+    results.push(CheckResult::new(
+        "GetPageContent",
+        match client.get_collections(|section| section).await {
+            Ok(sections) => Ok(format!("{} sections", sections.len())),
+            Err(e) => Err(e.to_string()),
+        },
+    ));
+
+    let sections = config
+        .ertflix
+        .movies_section_codenames
+        .iter()
+        .map(|codename| ("Movies section", codename))
+        .chain(config.ertflix.tv_shows_section_codenames.iter().map(|codename| ("TV shows section", codename)));
+    for (name, codename) in sections {
+        debug!("Checking section {}", codename);
+        let outcome = match client.get_section_content(codename.to_string()).await {
+            Ok(sections) => {
+                let tiles = sections
+                    .iter()
+                    .map(|section| section.tiles_ids.as_ref().map_or(0, Vec::len))
+                    .sum::<usize>();
+                if tiles > 0 {
+                    Ok(format!("{} returned {} tiles", codename, tiles))
+                } else {
+                    Err(format!("{} returned no tiles", codename))
+                }
+            }
+            Err(e) => Err(format!("{}: {}", codename, e)),
+        };
+        results.push(CheckResult::new(name, outcome));
+    }
+
+    results.push(CheckResult::new("Redis", check_redis(config).await));
+
+    results
+}
+
+async fn check_redis(config: &Config) -> Result<String, String> {
+    if !config.redis.enabled {
+        return Ok("disabled, skipped".to_string());
+    }
+    debug!("Pinging Redis at {}", config.redis.url);
+    let store = RedisStore::new(&config.redis).map_err(|e| format!("invalid URL {}: {}", config.redis.url, e))?;
+    match store.ping().await {
+        Ok(reply) => Ok(format!("{} answered {}", config.redis.url, reply)),
+        Err(e) => Err(format!("{}: {}", config.redis.url, e)),
+    }
+}
+
+fn check_dns(base_url: &str) -> Result<String, String> {
+    let url = if base_url.contains("://") {
+        base_url.to_string()
+    } else {
+        format!("https://{base_url}")
+    };
+    let url = Url::parse(&url).map_err(|e| format!("invalid base URL {}: {}", base_url, e))?;
+    let host = url.host_str().ok_or_else(|| format!("no host in {}", base_url))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    debug!("Resolving {}:{}", host, port);
+    let addresses: Vec<_> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("{}: {}", host, e))?
+        .collect();
+    match addresses.first() {
+        Some(address) => Ok(format!("{} resolves to {}", host, address.ip())),
+        None => Err(format!("{} resolved to no addresses", host)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ertflix_client::{DefaultErtflixClient, Error, MOVIES_SECTION_CODENAME};
+    use crate::api::transport::{Transport, TransportResponse};
+
+    /// Lists a tile in the movies section and none in any other section
+    #[derive(Default)]
+    struct EmptyTvSection;
+
+    impl Transport for EmptyTvSection {
+        async fn get(&self, url: &str) -> Result<TransportResponse, Error> {
+            let tiles = if url.contains("GetPageContent") || url.contains(MOVIES_SECTION_CODENAME) {
+                r#"[{"originEntityId":1,"codename":"movie","id":"movie"}]"#
+            } else {
+                "[]"
+            };
+            let section = format!(r#"{{"toplistCodename":"section","sectionId":1,"tilesIds":{}}}"#, tiles);
+            let body = if url.contains("GetPageContent") {
+                format!(r#"{{"sectionContents":[{}]}}"#, section)
+            } else {
+                format!("[{}]", section)
+            };
+            Ok(TransportResponse { status: 200, body, ..Default::default() })
+        }
+
+        async fn post(&self, url: &str, _body: &serde_json::Value) -> Result<TransportResponse, Error> {
+            panic!("unexpected POST to {}", url)
+        }
+    }
+
+    #[actix_web::test]
+    async fn a_section_without_tiles_fails_its_check_only() {
+        let mut config = Config::default();
+        config.ertflix.base_url = "http://127.0.0.1".to_string();
+        let client = DefaultErtflixClient::with_transport(EmptyTvSection, vec![config.ertflix.base_url.clone()]);
+
+        let results = run_checks(&client, &config).await;
+
+        let failed: Vec<&str> = results.iter().filter(|result| result.outcome.is_err()).map(|result| result.name.as_str()).collect();
+        assert_eq!(failed, ["TV shows section"]);
+        assert_eq!(results.len(), 5);
+    }
+}
@@ -0,0 +1,99 @@
+use std::error;
+use std::fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use crate::api::ertflix_client;
+
+/// Errors handlers answer with. Every variant is sent as a JSON body with a `message`
+/// and a machine-readable `code`, so clients always get a `Content-Type` and something
+/// to act on, including on failures.
+#[derive(Debug)]
+pub enum AppError {
+    /// The request lacked a valid admin or session token, or signed in with wrong credentials
+    Unauthorized,
+    NotFound(String),
+    BadRequest(String),
+    /// ERTFLIX could not be reached or answered with an error
+    Upstream(String),
+    /// ERTFLIX answered with a body that could not be parsed
+    UpstreamParse(String),
+    /// ERTFLIX did not answer in time
+    UpstreamTimeout(String),
+    /// ERTFLIX refused to serve the server's region
+    UpstreamGeoBlocked(String),
+    /// ERTFLIX answered, but what it answered can't be served, e.g. an empty library
+    Unavailable(String),
+    /// The request clashes with one still being processed, e.g. a retry with the same `Idempotency-Key`
+    Conflict(String),
+}
+
+impl AppError {
+    /// Maps a failure to fetch from ERTFLIX: not found when ERTFLIX does not know the
+    /// requested id, a timeout, geo-block or parse failure when that is what went wrong,
+    /// and an upstream error for any other problem
+    pub fn from_upstream(e: &(dyn error::Error + 'static)) -> Self {
+        match e.downcast_ref::<ertflix_client::Error>() {
+            Some(ertflix_client::Error::NotFound(id)) => AppError::NotFound(id.clone()),
+            Some(ertflix_client::Error::Timeout(_)) => AppError::UpstreamTimeout(e.to_string()),
+            Some(ertflix_client::Error::Parse(_)) => AppError::UpstreamParse(e.to_string()),
+            Some(ertflix_client::Error::GeoBlocked(_)) => AppError::UpstreamGeoBlocked(e.to_string()),
+            _ => AppError::Upstream(e.to_string()),
+        }
+    }
+
+    /// Machine-readable name of the error, sent as `code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Unauthorized => "unauthorized",
+            AppError::NotFound(_) => "not_found",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Upstream(_) => "upstream_error",
+            AppError::UpstreamParse(_) => "upstream_parse_error",
+            AppError::UpstreamTimeout(_) => "upstream_timeout",
+            AppError::UpstreamGeoBlocked(_) => "upstream_geo_blocked",
+            AppError::Unavailable(_) => "unavailable",
+            AppError::Conflict(_) => "conflict",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Unauthorized => write!(f, "Unauthorized"),
+            AppError::NotFound(what) => write!(f, "Not found: {}", what),
+            AppError::BadRequest(reason) => write!(f, "Bad request: {}", reason),
+            AppError::Upstream(reason) => write!(f, "ERTFLIX request failed: {}", reason),
+            AppError::UpstreamParse(reason) => write!(f, "ERTFLIX answered with an unreadable body: {}", reason),
+            AppError::UpstreamTimeout(reason) => write!(f, "ERTFLIX did not answer in time: {}", reason),
+            AppError::UpstreamGeoBlocked(reason) => write!(
+                f,
+                "ERTFLIX is not available where this server runs, run it from Greece or through a Greek VPN: {}",
+                reason
+            ),
+            AppError::Unavailable(reason) => write!(f, "Unavailable: {}", reason),
+            AppError::Conflict(reason) => write!(f, "Conflict: {}", reason),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Upstream(_) | AppError::UpstreamParse(_) | AppError::UpstreamGeoBlocked(_) => StatusCode::BAD_GATEWAY,
+            AppError::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "message": self.to_string(),
+            "code": self.code(),
+        }))
+    }
+}
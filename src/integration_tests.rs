@@ -0,0 +1,1867 @@
+//! End-to-end tests of the journey Infuse takes through the server, against a fake ERTFLIX.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use actix_web::http::header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use actix_web::{middleware, test, web, App};
+use argon2::password_hash::{PasswordHasher, SaltString};
+use argon2::Argon2;
+use serde_json::{json, Value};
+use crate::api::ertflix_client::{DefaultErtflixClient, Error, MOVIES_SECTION_CODENAME, TV_SHOWS_SECTION_CODENAME};
+use crate::api::mock_ertflix_client::MockErtflixClient;
+use crate::api::transport::{Transport, TransportResponse};
+use crate::clock::FixedClock;
+use crate::config::{self, Config};
+use crate::models::jellyfin::{self, ItemId};
+use crate::routes;
+use crate::services::cache::CacheStatus;
+use crate::services::idempotency::IdempotencyStore;
+use crate::services::image_proxy::ImageProxy;
+use crate::services::media_service::MediaService;
+use crate::services::sessions::SessionStore;
+use crate::services::stream_prefetch;
+use crate::services::stream_tracker::StreamTracker;
+use crate::services::supervisor::Supervisor;
+use crate::services::user_data::UserDataStore;
+
+/// Answers a request in place of the fixed library
+type Answer = Box<dyn Fn(&str) -> Result<TransportResponse, Error> + Send + Sync>;
+
+/// Rewrites a tile GetTiles returns
+type TileEdit = Box<dyn Fn(&mut Value) + Send + Sync>;
+
+/// Serves a small fixed library: two movies and one series. Tests can override the answers
+/// for some URLs and edit the tiles served, and read back the requests their own fake got;
+/// clones share all of that, so a test keeps one while the app sends through another.
+#[derive(Clone, Default)]
+struct FakeErtflix(Arc<FakeErtflixState>);
+
+#[derive(Default)]
+struct FakeErtflixState {
+    /// Answers for URLs containing a fragment, the latest added tried first
+    answers: Mutex<Vec<(String, Answer)>>,
+    tile_edits: Mutex<Vec<TileEdit>>,
+    latency: Mutex<Duration>,
+    /// Every URL asked for, in order
+    requests: Mutex<Vec<String>>,
+    requested_tiles: AtomicUsize,
+    in_flight: AtomicUsize,
+    peak_in_flight: AtomicUsize,
+}
+
+fn section(codename: &str, section_id: i32, tile_ids: &[&str]) -> Value {
+    json!({
+        "toplistCodename": codename,
+        "sectionId": section_id,
+        "tilesIds": tile_ids
+            .iter()
+            .map(|id| json!({ "originEntityId": 1, "codename": format!("{}-codename", id), "id": id }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn ok(body: Value) -> Result<TransportResponse, Error> {
+    Ok(TransportResponse { status: 200, body: body.to_string(), ..Default::default() })
+}
+
+fn status(status: u16) -> Result<TransportResponse, Error> {
+    Ok(TransportResponse { status, ..Default::default() })
+}
+
+impl FakeErtflix {
+    /// Answers requests to URLs containing `fragment` with `answer`; an empty fragment matches every URL
+    fn answer(&self, fragment: &str, answer: impl Fn(&str) -> Result<TransportResponse, Error> + Send + Sync + 'static) -> &Self {
+        self.0.answers.lock().unwrap().push((fragment.to_string(), Box::new(answer)));
+        self
+    }
+
+    /// Applies `edit` to every tile GetTiles returns
+    fn edit_tiles(&self, edit: impl Fn(&mut Value) + Send + Sync + 'static) -> &Self {
+        self.0.tile_edits.lock().unwrap().push(Box::new(edit));
+        self
+    }
+
+    /// Holds every answer back for `latency`
+    fn delay_answers(&self, latency: Duration) -> &Self {
+        *self.0.latency.lock().unwrap() = latency;
+        self
+    }
+
+    /// Requests made so far to URLs containing `fragment`
+    fn requests_to(&self, fragment: &str) -> usize {
+        self.0.requests.lock().unwrap().iter().filter(|url| url.contains(fragment)).count()
+    }
+
+    /// Tiles asked for through GetTiles so far
+    fn requested_tiles(&self) -> usize {
+        self.0.requested_tiles.load(Ordering::SeqCst)
+    }
+
+    /// The most requests that waited for an answer at once
+    fn peak_requests_in_flight(&self) -> usize {
+        self.0.peak_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Records a request to `url`, waits out the latency and evaluates to the answer overriding the library's, if any
+    async fn receive(&self, url: &str) -> Option<Result<TransportResponse, Error>> {
+        self.0.requests.lock().unwrap().push(url.to_string());
+        let in_flight = self.0.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.0.peak_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        let latency = *self.0.latency.lock().unwrap();
+        if !latency.is_zero() {
+            actix_web::rt::time::sleep(latency).await;
+        }
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        let answers = self.0.answers.lock().unwrap();
+        answers.iter().rev().find(|(fragment, _)| url.contains(fragment.as_str())).map(|(_, answer)| answer(url))
+    }
+}
+
+impl Transport for FakeErtflix {
+    async fn get(&self, url: &str) -> Result<TransportResponse, Error> {
+        if let Some(answer) = self.receive(url).await {
+            answer
+        } else if url.contains("GetPageContent") {
+            ok(json!({
+                "sectionContents": [
+                    section(MOVIES_SECTION_CODENAME, 1, &["movie-1"]),
+                    section(TV_SHOWS_SECTION_CODENAME, 2, &["series-1"]),
+                ],
+            }))
+        } else if url.contains(&format!("sectionCodename={}", MOVIES_SECTION_CODENAME)) {
+            ok(json!([section(MOVIES_SECTION_CODENAME, 1, &["movie-1", "movie-2"])]))
+        } else if url.contains(&format!("sectionCodename={}", TV_SHOWS_SECTION_CODENAME)) {
+            ok(json!([section(TV_SHOWS_SECTION_CODENAME, 2, &["series-1"])]))
+        } else if url.contains("GetSeriesDetails") {
+            ok(json!({
+                "episodes": [
+                    { "id": "episode-2", "title": "Επεισόδιο 2", "seasonNumber": 1, "durationSeconds": 2700 },
+                    { "id": "episode-1", "title": "Επεισόδιο 1", "seasonNumber": 1, "durationSeconds": 2700 },
+                ],
+            }))
+        } else {
+            status(404)
+        }
+    }
+
+    async fn post(&self, url: &str, body: &Value) -> Result<TransportResponse, Error> {
+        self.0.requested_tiles.fetch_add(body["requestedTiles"].as_array().map_or(0, Vec::len), Ordering::SeqCst);
+        if let Some(answer) = self.receive(url).await {
+            return answer;
+        }
+        assert!(url.contains("GetTiles"), "unexpected POST to {}", url);
+        let edits = self.0.tile_edits.lock().unwrap();
+        let tiles: Vec<Value> = body["requestedTiles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tile| {
+                let id = tile["id"].as_str().unwrap();
+                let mut images = vec![json!({ "role": "poster", "url": format!("https://images.test/{}/poster.jpg", id) })];
+                let mut tile = json!({ "originEntityId": 1, "codename": format!("{}-codename", id), "id": id, "title": format!("Title of {}", id), "year": 2020 });
+                if id == "movie-1" {
+                    images.push(json!({ "role": "background", "url": format!("https://images.test/{}/background.jpg", id) }));
+                    tile["trailerUrl"] = json!(format!("https://trailers.test/{}.m3u8", id));
+                }
+                tile["images"] = json!(images);
+                edits.iter().for_each(|edit| edit(&mut tile));
+                tile
+            })
+            .collect();
+        ok(json!(tiles))
+    }
+}
+
+type FakeClient = DefaultErtflixClient<FakeErtflix>;
+
+/// A `MediaService` listing the library through `fake`, which the caller keeps to inspect
+async fn media_service_over(config: &Config, fake: &FakeErtflix) -> MediaService<FakeClient> {
+    let client = FakeClient::from_config_with_transport(&config.ertflix, fake.clone());
+    MediaService::with_client(config, client).await.unwrap()
+}
+
+/// Starts the full route table on top of `FakeErtflix`, with the same shared state as `main`.
+/// `secured` also puts the session token check in front of it, and `over` serves the
+/// given fake rather than a fresh one.
+macro_rules! fake_app {
+    (@app $config:expr, over $fake:expr) => {{
+        let config: Config = $config;
+        let media_service = media_service_over(&config, $fake).await;
+        fake_app!(@serve config, media_service, FakeClient)
+    }};
+    (@app $config:expr, $client:ty) => {{
+        let config: Config = $config;
+        let media_service = MediaService::<$client>::from_config(&config).await.unwrap();
+        fake_app!(@serve config, media_service, $client)
+    }};
+    (@serve $config:expr, $media_service:expr, $client:ty) => {{
+        let config: Config = $config;
+        App::new()
+            .app_data(web::Data::new($media_service))
+            .app_data(web::Data::new(IdempotencyStore::from_config(&config)))
+            .app_data(web::Data::new(UserDataStore::new()))
+            .app_data(web::Data::new(StreamTracker::new(Duration::from_secs(60))))
+            .app_data(web::Data::new(SessionStore::new()))
+            .app_data(web::Data::new(ImageProxy::new(config.images.max_concurrent_fetches)))
+            .app_data(web::Data::new(Supervisor::default()))
+            .app_data(web::Data::new(config::SharedConfig::new(config)))
+            .configure(routes::init_routes::<$client>)
+    }};
+    (secured $config:expr) => {
+        test::init_service(
+            fake_app!(@app $config, FakeClient).wrap(middleware::from_fn(routes::auth::require_session_token)),
+        )
+        .await
+    };
+    ($config:expr) => {
+        fake_app!($config, FakeClient)
+    };
+    ($config:expr, over $fake:expr) => {
+        test::init_service(fake_app!(@app $config, over $fake)).await
+    };
+    ($config:expr, $client:ty) => {
+        test::init_service(fake_app!(@app $config, $client)).await
+    };
+}
+
+/// Signs in as `$username` with an empty password and evaluates to the issued access token
+macro_rules! sign_in {
+    ($app:expr, $username:expr) => {{
+        let auth: Value = test::call_and_read_body_json(
+            $app,
+            test::TestRequest::post()
+                .uri("/Users/AuthenticateByName")
+                .insert_header(("X-Emby-Authorization", r#"MediaBrowser Client="Infuse", Device="iPad", DeviceId="device-9", Version="8.0""#))
+                .set_json(json!({ "Username": $username, "Pw": "" }))
+                .to_request(),
+        )
+        .await;
+        auth["AccessToken"].as_str().expect("AccessToken").to_string()
+    }};
+}
+
+#[actix_web::test]
+async fn auth_views_items_journey() {
+    let app = fake_app!(Config::default());
+
+    let info: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::get().uri("/System/Info/Public").to_request(),
+    )
+    .await;
+    assert!(info["Id"].is_string());
+    assert!(info["ServerName"].is_string());
+
+    let auth: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::post()
+            .uri("/Users/AuthenticateByName")
+            .insert_header((
+                "X-Emby-Authorization",
+                r#"MediaBrowser Client="Infuse", Device="iPhone", DeviceId="device-1", Version="8.0""#,
+            ))
+            .set_json(json!({ "Username": config::identity().username, "Pw": "" }))
+            .to_request(),
+    )
+    .await;
+    let token = auth["AccessToken"].as_str().expect("AccessToken");
+    let user_id = auth["User"]["Id"].as_str().expect("User.Id");
+    assert!(auth["SessionInfo"]["Id"].is_string());
+
+    let views: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::get()
+            .uri("/UserViews")
+            .insert_header(("X-Emby-Token", token))
+            .to_request(),
+    )
+    .await;
+    let views = views["Items"].as_array().expect("Items");
+    assert!(!views.is_empty());
+    let parent_id = views[0]["Id"].as_str().expect("view Id");
+    assert_eq!(views[0]["Type"], "CollectionFolder");
+
+    let items: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::get()
+            .uri(&format!("/Users/{}/Items?ParentId={}", user_id, parent_id))
+            .insert_header(("X-Emby-Token", token))
+            .to_request(),
+    )
+    .await;
+    let listed = items["Items"].as_array().expect("Items");
+    assert!(!listed.is_empty());
+    assert_eq!(items["TotalRecordCount"], listed.len());
+}
+
+#[actix_web::test]
+async fn dashboard_probes_get_empty_lists() {
+    let mut config = Config::default();
+    config.admin.token = Some("secret".to_string());
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(config::SharedConfig::new(config)))
+            .configure(routes::init_routes::<FakeClient>),
+    )
+    .await;
+
+    for uri in ["/Plugins", "/ScheduledTasks"] {
+        let listed: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("X-Admin-Token", "secret"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(listed, json!([]), "{}", uri);
+
+        let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(response.status(), 401, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+async fn reloaded_settings_apply_to_the_next_requests() {
+    let mut config = Config::default();
+    config.admin.token = Some("old".to_string());
+    let shared = web::Data::new(config::SharedConfig::new(config.clone()));
+    let app = test::init_service(App::new().app_data(shared.clone()).configure(routes::init_routes::<FakeClient>)).await;
+    let plugins = |token: &str| test::TestRequest::get().uri("/Plugins").insert_header(("X-Admin-Token", token)).to_request();
+
+    assert_eq!(test::call_service(&app, plugins("old")).await.status(), 200);
+
+    config.admin.token = Some("new".to_string());
+    config.server.bind_address = "127.0.0.1:8096".to_string();
+    let changes = shared.replace(config);
+
+    assert_eq!(changes.applied, ["admin.token"]);
+    assert_eq!(changes.need_restart, ["server.bind_address"]);
+    assert_eq!(test::call_service(&app, plugins("old")).await.status(), 401);
+    assert_eq!(test::call_service(&app, plugins("new")).await.status(), 200);
+}
+
+#[actix_web::test]
+async fn items_are_tagged_with_their_source_section_when_enabled() {
+    let list_items = async |tag_source_sections: bool| -> Value {
+        let mut config = Config::default();
+        config.ertflix.tag_source_sections = tag_source_sections;
+        let app = fake_app!(config);
+        test::call_and_read_body_json(&app, test::TestRequest::get().uri("/Items").to_request()).await
+    };
+
+    let tagged = list_items(true).await;
+    let items = tagged["Items"].as_array().unwrap();
+    let movie = items.iter().find(|item| item["Id"] == ItemId::from_ertflix("movie-1").as_str()).unwrap();
+    assert_eq!(movie["Tags"], json!([MOVIES_SECTION_CODENAME]));
+    let show = items.iter().find(|item| item["Id"] == ItemId::from_ertflix("series-1").as_str()).unwrap();
+    assert_eq!(show["Tags"], json!([TV_SHOWS_SECTION_CODENAME]));
+
+    let untagged = list_items(false).await;
+    assert!(untagged["Items"].as_array().unwrap().iter().all(|item| item.get("Tags").is_none()));
+}
+
+#[actix_web::test]
+async fn unknown_username_signs_in_as_guest_when_allowed() {
+    let mut config = Config::default();
+    config.auth.allow_guest = true;
+    let app = fake_app!(config);
+
+    let authenticate = |username: &str| {
+        test::TestRequest::post()
+            .uri("/Users/AuthenticateByName")
+            .insert_header(("X-Emby-Authorization", r#"MediaBrowser Client="Infuse", Device="iPad", DeviceId="device-2", Version="8.0""#))
+            .set_json(json!({ "Username": username, "Pw": "" }))
+            .to_request()
+    };
+
+    let guest: Value = test::call_and_read_body_json(&app, authenticate("visitor")).await;
+    assert_eq!(guest["User"]["Name"], "visitor");
+    assert_eq!(guest["User"]["Id"], crate::config::GUEST_USER_ID);
+    assert_eq!(guest["User"]["Policy"]["IsAdministrator"], false);
+    assert_eq!(guest["User"]["Policy"]["EnableContentDeletion"], false);
+    assert_eq!(guest["SessionInfo"]["UserId"], crate::config::GUEST_USER_ID);
+    assert_eq!(guest["SessionInfo"]["UserName"], "visitor");
+
+    let owner: Value = test::call_and_read_body_json(&app, authenticate(&crate::config::identity().username)).await;
+    assert_eq!(owner["User"]["Policy"]["IsAdministrator"], true);
+    assert_eq!(owner["SessionInfo"]["UserId"], crate::config::identity().user_id);
+}
+
+#[actix_web::test]
+async fn sign_in_is_refused_unless_the_credentials_match() {
+    let mut config = Config::default();
+    let salt = SaltString::from_b64("c2FsdHNhbHRzYWx0").unwrap();
+    config.auth.password_hash = Some(Argon2::default().hash_password(b"hunter2", &salt).unwrap().to_string());
+    let app = fake_app!(config);
+
+    let authenticate = |username: &str, password: &str| {
+        test::TestRequest::post()
+            .uri("/Users/AuthenticateByName")
+            .insert_header(("X-Emby-Authorization", r#"MediaBrowser Client="Infuse", Device="iPad", DeviceId="device-3", Version="8.0""#))
+            .set_json(json!({ "Username": username, "Pw": password }))
+            .to_request()
+    };
+    let owner = config::identity().username.as_str();
+
+    let accepted = test::call_service(&app, authenticate(owner, "hunter2")).await;
+    assert_eq!(accepted.status(), 200);
+    for (username, password) in [(owner, "wrong"), (owner, ""), ("visitor", "hunter2")] {
+        let refused = test::call_service(&app, authenticate(username, password)).await;
+        assert_eq!(refused.status(), 401, "{} / {}", username, password);
+    }
+
+    let mut open = Config::default();
+    open.auth.allow_all = true;
+    let app = fake_app!(open);
+    let anyone = test::call_service(&app, authenticate("visitor", "anything")).await;
+    assert_eq!(anyone.status(), 200);
+}
+
+#[actix_web::test]
+async fn accept_language_picks_the_default_track_language() {
+    let mut config = Config::default();
+    config.language.default_language = "el".into();
+    let app = fake_app!(config);
+
+    let authenticate = |accept_language: Option<&str>| {
+        let mut request = test::TestRequest::post()
+            .uri("/Users/AuthenticateByName")
+            .insert_header(("X-Emby-Authorization", r#"MediaBrowser Client="Infuse", Device="iPad", DeviceId="device-4", Version="8.0""#))
+            .set_json(json!({ "Username": config::identity().username, "Pw": "" }));
+        if let Some(accept_language) = accept_language {
+            request = request.insert_header(("Accept-Language", accept_language));
+        }
+        request.to_request()
+    };
+
+    for (accept_language, expected) in [(Some("en-US,el;q=0.5"), "eng"), (Some("fr"), "gre"), (None, "gre")] {
+        let auth: Value = test::call_and_read_body_json(&app, authenticate(accept_language)).await;
+        let preferences = &auth["User"]["Configuration"];
+        assert_eq!(preferences["AudioLanguagePreference"], expected, "{:?}", accept_language);
+        assert_eq!(preferences["SubtitleLanguagePreference"], expected, "{:?}", accept_language);
+    }
+}
+
+#[actix_web::test]
+async fn protected_routes_need_an_issued_token() {
+    let app = fake_app!(secured Config::default());
+
+    let public = test::call_service(&app, test::TestRequest::get().uri("/System/Info/Public").to_request()).await;
+    assert_eq!(public.status(), 200);
+    for token in [None, Some("made-up")] {
+        let mut request = test::TestRequest::get().uri("/UserViews");
+        if let Some(token) = token {
+            request = request.insert_header(("X-Emby-Token", token));
+        }
+        let refused = test::call_service(&app, request.to_request()).await;
+        assert_eq!(refused.status(), 401, "{:?}", token);
+    }
+
+    let auth: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::post()
+            .uri("/Users/AuthenticateByName")
+            .insert_header(("X-Emby-Authorization", r#"MediaBrowser Client="Infuse", Device="iPad", DeviceId="device-4", Version="8.0""#))
+            .set_json(json!({ "Username": config::identity().username, "Pw": "" }))
+            .to_request(),
+    )
+    .await;
+    let token = auth["AccessToken"].as_str().expect("AccessToken");
+    for header in ["X-Emby-Token", "X-MediaBrowser-Token"] {
+        let views = test::call_service(&app, test::TestRequest::get().uri("/UserViews").insert_header((header, token)).to_request()).await;
+        assert_eq!(views.status(), 200, "{}", header);
+    }
+}
+
+#[actix_web::test]
+async fn items_and_views_are_not_downloadable_or_deletable_by_default() {
+    let app = fake_app!(Config::default());
+
+    let views: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/UserViews").to_request()).await;
+    let items: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/Items").to_request()).await;
+
+    for item in views["Items"].as_array().unwrap().iter().chain(items["Items"].as_array().unwrap()) {
+        assert_eq!(item["CanDownload"], false, "{}", item);
+        assert_eq!(item["CanDelete"], false, "{}", item);
+    }
+}
+
+#[actix_web::test]
+async fn backdrops_are_tagged_and_served() {
+    let mut config = Config::default();
+    config.images.proxy = false;
+    let app = fake_app!(config);
+
+    let movies: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/movies").to_request()).await;
+    let movies = movies["Items"].as_array().unwrap();
+    let with_backdrop = movies.iter().find(|movie| movie["Name"] == "Title of movie-1").unwrap();
+    let without_backdrop = movies.iter().find(|movie| movie["Name"] == "Title of movie-2").unwrap();
+    assert_eq!(with_backdrop["BackdropImageTags"].as_array().unwrap().len(), 1);
+    assert!(with_backdrop["ImageTags"]["Primary"].is_string());
+    assert_eq!(without_backdrop["BackdropImageTags"], json!([]));
+    assert!(without_backdrop["ImageTags"].get("Logo").is_none());
+
+    let item_id = with_backdrop["Id"].as_str().unwrap();
+    for uri in [format!("/Items/{}/Images/Backdrop", item_id), format!("/Items/{}/Images/Backdrop/0", item_id)] {
+        let response = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(response.status(), 302, "{}", uri);
+        assert_eq!(response.headers().get("Location").unwrap(), "https://images.test/movie-1/background.jpg");
+    }
+
+    let missing = format!("/Items/{}/Images/Backdrop", without_backdrop["Id"].as_str().unwrap());
+    let response = test::call_service(&app, test::TestRequest::get().uri(&missing).to_request()).await;
+    assert_eq!(response.status(), 404);
+}
+
+/// `FakeErtflix` with the TV shows section failing
+fn tv_shows_down() -> FakeErtflix {
+    let fake = FakeErtflix::default();
+    fake.answer(&format!("sectionCodename={}", TV_SHOWS_SECTION_CODENAME), |_| {
+        Ok(TransportResponse { status: 500, body: "Internal Server Error".to_string(), ..Default::default() })
+    });
+    fake
+}
+
+#[actix_web::test]
+async fn refresh_report_keeps_the_types_that_succeeded() {
+    let media_service = media_service_over(&Config::default(), &tv_shows_down()).await;
+
+    let report = media_service.refresh_all().await;
+
+    assert!(!report.is_success());
+    assert_eq!(report.movies, Some(2));
+    assert_eq!(report.collections, Some(2));
+    assert_eq!(report.tv_shows, None);
+    assert_eq!(report.errors.keys().collect::<Vec<_>>(), ["TvShows"]);
+    assert_eq!(media_service.last_refresh().unwrap().movies, Some(2));
+}
+
+#[actix_web::test]
+async fn refresh_reports_are_dated_by_the_clock() {
+    let media_service = MediaService::<FakeClient>::from_config(&Config::default())
+        .await
+        .unwrap()
+        .with_clock(Box::new(FixedClock::at("2024-05-01T12:30:00Z")));
+
+    assert_eq!(media_service.refresh_all().await.finished_at, "2024-05-01T12:30:00+00:00");
+    assert_eq!(media_service.warm_cache().await.finished_at, "2024-05-01T12:30:00+00:00");
+}
+
+#[actix_web::test]
+async fn warming_caches_the_types_that_succeeded() {
+    let media_service = media_service_over(&Config::default(), &tv_shows_down()).await;
+
+    let report = media_service.warm_cache().await;
+
+    assert_eq!((report.collections, report.movies, report.tv_shows), (Some(2), Some(2), None));
+    assert_eq!(report.errors.keys().collect::<Vec<_>>(), ["TvShows"]);
+    let (movies, status) = media_service.get_movies_with_status().await.unwrap();
+    assert_eq!((movies.len(), status), (2, CacheStatus::Hit));
+    assert!(media_service.get_tv_shows().await.is_err());
+    // Warming is not a refresh
+    assert!(media_service.last_refresh().is_none());
+}
+
+#[actix_web::test]
+async fn a_failed_refresh_keeps_serving_the_cached_library() {
+    let fake = FakeErtflix::default();
+    let media_service = media_service_over(&Config::default(), &fake).await;
+    assert!(media_service.refresh_all().await.is_success());
+
+    fake.answer("", |_| Ok(TransportResponse { status: 500, body: "Internal Server Error".to_string(), ..Default::default() }));
+    let report = media_service.refresh_all().await;
+
+    assert_eq!((report.collections, report.movies, report.tv_shows), (None, None, None));
+    let (movies, status) = media_service.get_movies_with_status().await.unwrap();
+    assert_eq!((movies.len(), status), (2, CacheStatus::Hit));
+    assert_eq!(media_service.get_tv_shows().await.unwrap().len(), 1);
+    assert_eq!(media_service.get_collections().await.unwrap().len(), 2);
+}
+
+#[actix_web::test]
+async fn split_movie_parts_are_merged_when_enabled() {
+    let mut config = Config::default();
+    config.library.merge_movie_parts = true;
+    // movie-1 and movie-2 titled as the two parts of one film
+    let fake = FakeErtflix::default();
+    fake.edit_tiles(|tile| {
+        let part = if tile["id"] == "movie-1" { "Μέρος 1" } else { "Μέρος 2" };
+        tile["title"] = json!(format!("Ο Θίασος - {}", part));
+    });
+    let media_service = media_service_over(&config, &fake).await;
+
+    let movies = media_service.get_movies().await.unwrap();
+    assert_eq!(movies.len(), 1);
+    let movie = media_service.convert_to_jellyfin_movie(movies.into_iter().next().unwrap());
+
+    assert_eq!(movie.title, "Ο Θίασος");
+    assert_eq!(movie.part_count, Some(2));
+    let sources: Vec<&str> = movie.media_sources.iter().map(|source| source.name.as_str()).collect();
+    assert_eq!(sources, ["Ο Θίασος - Μέρος 1", "Ο Θίασος - Μέρος 2"]);
+}
+
+#[actix_web::test]
+async fn tiles_with_a_trailer_list_it_as_a_remote_trailer() {
+    let media_service = MediaService::<FakeClient>::from_config(&Config::default()).await.unwrap();
+
+    let movies: Vec<Value> = media_service
+        .get_movies()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|movie| serde_json::to_value(media_service.convert_to_jellyfin_movie(movie)).unwrap())
+        .collect();
+
+    let with_trailer = movies.iter().find(|movie| movie["Name"] == "Title of movie-1").unwrap();
+    assert_eq!(
+        with_trailer["RemoteTrailers"],
+        json!([{ "Url": "https://trailers.test/movie-1.m3u8", "Name": "Title of movie-1 - Trailer" }]),
+    );
+    let without_trailer = movies.iter().find(|movie| movie["Name"] == "Title of movie-2").unwrap();
+    assert_eq!(without_trailer["RemoteTrailers"], json!([]));
+}
+
+#[actix_web::test]
+async fn sections_over_the_cap_report_the_capped_child_count() {
+    let mut config = Config::default();
+    config.library.max_collection_children = 1;
+    let app = fake_app!(config);
+
+    let views: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/UserViews").to_request()).await;
+
+    let movies = views["Items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|view| view["Name"] == MOVIES_SECTION_CODENAME)
+        .unwrap();
+    assert_eq!(movies["ChildCount"], 1);
+}
+
+/// Answers every request with a PNG, standing in for the ERTFLIX image CDN
+fn local_image_cdn() -> &'static str {
+    static BASE_URL: OnceLock<String> = OnceLock::new();
+    BASE_URL.get_or_init(|| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 4096]);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 4\r\nConnection: close\r\n\r\n\x89PNG");
+            }
+        });
+        base_url
+    })
+}
+
+#[actix_web::test]
+async fn responses_declare_their_content_type() {
+    // Artwork served by `local_image_cdn`
+    let fake = FakeErtflix::default();
+    fake.edit_tiles(|tile| {
+        for image in tile["images"].as_array_mut().unwrap() {
+            image["url"] = json!(image["url"].as_str().unwrap().replace("https://images.test", local_image_cdn()));
+        }
+    });
+    let app = fake_app!(Config::default(), over &fake);
+    let content_type = async |uri: &str| {
+        let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        (response.status().as_u16(), response.headers().get(CONTENT_TYPE).unwrap().to_str().unwrap().to_string())
+    };
+
+    assert_eq!(content_type("/UserViews").await, (200, "application/json".to_string()));
+    let missing = format!("/Items/{}/Images/Primary", ItemId::from_ertflix("no-such-item"));
+    assert_eq!(content_type(&missing).await, (404, "application/json".to_string()));
+    let poster = format!("/Items/{}/Images/Primary", ItemId::from_ertflix("movie-1"));
+    assert_eq!(content_type(&poster).await, (200, "image/png".to_string()));
+}
+
+#[actix_web::test]
+async fn landing_page_names_the_server() {
+    let app = fake_app!(Config::default());
+
+    let response = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers().get(CONTENT_TYPE).unwrap().to_str().unwrap().starts_with("text/html"));
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert!(body.contains(&config::identity().server_name));
+    assert!(body.contains("href=\"/metrics\""));
+}
+
+#[actix_web::test]
+async fn admin_responses_are_indented_when_pretty_is_asked_for() {
+    let mut config = Config::default();
+    config.admin.token = Some("secret".to_string());
+    let app = fake_app!(config);
+    let body = async |uri: &str| {
+        let request = test::TestRequest::get().uri(uri).insert_header(("X-Admin-Token", "secret"));
+        let response = test::call_service(&app, request.to_request()).await;
+        String::from_utf8(test::read_body(response).await.to_vec()).unwrap()
+    };
+
+    assert!(!body("/admin/health").await.contains('\n'));
+    let pretty = body("/admin/health?pretty=true").await;
+    assert!(pretty.contains("\n  \"Status\": \"ok\""), "{}", pretty);
+}
+
+#[actix_web::test]
+async fn admin_endpoints_need_the_admin_token_or_a_session_of_the_configured_user() {
+    let mut config = Config::default();
+    config.auth.allow_guest = true;
+    let app = fake_app!(secured config.clone());
+    let mappings = |token: Option<&str>| {
+        let mut request = test::TestRequest::get().uri("/admin/mappings");
+        if let Some(token) = token {
+            request = request.insert_header(("X-Emby-Token", token));
+        }
+        request.to_request()
+    };
+
+    assert_eq!(test::call_service(&app, mappings(None)).await.status(), 401);
+    let guest = sign_in!(&app, "visitor");
+    assert_eq!(test::call_service(&app, mappings(Some(&guest))).await.status(), 401);
+    let owner = sign_in!(&app, config::identity().username);
+    assert_eq!(test::call_service(&app, mappings(Some(&owner))).await.status(), 200);
+
+    // Without the session check in front, the handlers still refuse
+    let app = fake_app!(config.clone());
+    for uri in ["/admin/mappings", "/admin/export.csv", "/admin/health", "/admin/users/alice/favorites/export"] {
+        let refused = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(refused.status(), 401, "{}", uri);
+    }
+
+    config.admin.token = Some("secret".to_string());
+    let app = fake_app!(secured config);
+    for (token, status) in [("wrong", 401), ("secret", 200)] {
+        let request = test::TestRequest::get().uri("/admin/mappings").insert_header(("X-Admin-Token", token));
+        assert_eq!(test::call_service(&app, request.to_request()).await.status(), status, "{}", token);
+    }
+}
+
+#[actix_web::test]
+async fn library_export_needs_the_admin_token() {
+    let mut config = Config::default();
+    config.admin.token = Some("secret".to_string());
+    let app = fake_app!(config);
+
+    let refused = test::call_service(&app, test::TestRequest::get().uri("/admin/export.csv").to_request()).await;
+    assert_eq!(refused.status(), 401);
+
+    let request = test::TestRequest::get().uri("/admin/export.csv").insert_header(("X-Admin-Token", "secret"));
+    let response = test::call_service(&app, request.to_request()).await;
+    assert_eq!(response.status(), 200);
+    assert!(response.headers().get(CONTENT_TYPE).unwrap().to_str().unwrap().starts_with("text/csv"));
+    let csv = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert_eq!(
+        csv.lines().collect::<Vec<_>>(),
+        ["id,title,type,year,genres", "movie-1,Title of movie-1,Movie,2020,", "movie-2,Title of movie-2,Movie,2020,", "series-1,Title of series-1,Series,,"]
+    );
+}
+
+#[actix_web::test]
+async fn admin_mappings_show_the_section_behind_each_collection() {
+    let mut config = Config::default();
+    config.admin.token = Some("secret".to_string());
+    let app = fake_app!(config);
+
+    let unauthorized = test::call_service(&app, test::TestRequest::get().uri("/admin/mappings").to_request()).await;
+    assert_eq!(unauthorized.status(), 401);
+
+    let request = test::TestRequest::get().uri("/admin/mappings").insert_header(("X-Admin-Token", "secret"));
+    let mappings: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+    assert_eq!(
+        mappings,
+        json!([
+            {
+                "CollectionId": ItemId::from_ertflix("1").to_string(),
+                "Name": MOVIES_SECTION_CODENAME,
+                "SectionCodename": MOVIES_SECTION_CODENAME,
+                "ContentType": "movies",
+                "ItemCount": 2,
+            },
+            {
+                "CollectionId": ItemId::from_ertflix("2").to_string(),
+                "Name": TV_SHOWS_SECTION_CODENAME,
+                "SectionCodename": TV_SHOWS_SECTION_CODENAME,
+                "ContentType": "tvshows",
+                "ItemCount": 1,
+            },
+        ])
+    );
+}
+
+#[actix_web::test]
+async fn favorites_can_be_exported_and_imported_for_another_user() {
+    let mut config = Config::default();
+    config.admin.token = Some("secret".to_string());
+    let app = fake_app!(config);
+    let movie = ItemId::from_ertflix("movie-1").to_string();
+    let unknown = ItemId::from_ertflix("no-longer-listed").to_string();
+    let admin = |request: test::TestRequest| request.insert_header(("X-Admin-Token", "secret")).to_request();
+
+    let favorite = test::TestRequest::post().uri(&format!("/Users/alice/FavoriteItems/{}", movie));
+    assert!(test::call_service(&app, admin(favorite)).await.status().is_success());
+
+    let export_uri = "/admin/users/alice/favorites/export";
+    let unauthorized = test::call_service(&app, test::TestRequest::get().uri(export_uri).to_request()).await;
+    assert_eq!(unauthorized.status(), 401);
+    let mut export: Value = test::call_and_read_body_json(&app, admin(test::TestRequest::get().uri(export_uri))).await;
+    assert_eq!(export, json!({ "ItemIds": [movie] }));
+
+    export["ItemIds"].as_array_mut().unwrap().push(json!(unknown));
+    let import = test::TestRequest::post().uri("/admin/users/bob/favorites/import").set_json(&export);
+    let imported: Value = test::call_and_read_body_json(&app, admin(import)).await;
+    assert_eq!(imported, json!({ "Imported": 2, "NotInLibrary": 1 }));
+
+    let bob: Value = test::call_and_read_body_json(&app, admin(test::TestRequest::get().uri("/admin/users/bob/favorites/export"))).await;
+    let mut expected = vec![movie, unknown];
+    expected.sort();
+    assert_eq!(bob["ItemIds"], json!(expected));
+
+    let malformed = test::TestRequest::post().uri("/admin/users/bob/favorites/import").set_json(json!({ "ItemIds": ["not an id"] }));
+    assert_eq!(test::call_service(&app, admin(malformed)).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn users_may_only_move_their_own_favorites() {
+    let mut config = Config::default();
+    config.auth.allow_guest = true;
+    let app = fake_app!(secured config);
+    let export = |user_id: &str, token: Option<&str>| {
+        let mut request = test::TestRequest::get().uri(&format!("/admin/users/{}/favorites/export", user_id));
+        if let Some(token) = token {
+            request = request.insert_header(("X-Emby-Token", token));
+        }
+        request.to_request()
+    };
+    let owner = config::identity().user_id.clone();
+
+    assert_eq!(test::call_service(&app, export(&owner, None)).await.status(), 401);
+    let unauthenticated = test::TestRequest::post()
+        .uri(&format!("/admin/users/{}/favorites/import", owner))
+        .set_json(json!({ "ItemIds": [] }))
+        .to_request();
+    assert_eq!(test::call_service(&app, unauthenticated).await.status(), 401);
+
+    let guest = sign_in!(&app, "visitor");
+    assert_eq!(test::call_service(&app, export(&owner, Some(&guest))).await.status(), 401);
+    assert_eq!(test::call_service(&app, export(config::GUEST_USER_ID, Some(&guest))).await.status(), 200);
+    let import = |user_id: &str| {
+        test::TestRequest::post()
+            .uri(&format!("/admin/users/{}/favorites/import", user_id))
+            .insert_header(("X-Emby-Token", guest.as_str()))
+            .set_json(json!({ "ItemIds": [ItemId::from_ertflix("movie-1").to_string()] }))
+            .to_request()
+    };
+    assert_eq!(test::call_service(&app, import(&owner)).await.status(), 401);
+    assert_eq!(test::call_service(&app, import(config::GUEST_USER_ID)).await.status(), 200);
+
+    let owner_token = sign_in!(&app, config::identity().username);
+    assert_eq!(test::call_service(&app, export(config::GUEST_USER_ID, Some(&owner_token))).await.status(), 200);
+}
+
+#[actix_web::test]
+async fn recursive_listing_of_a_tv_collection_includes_the_episodes() {
+    let app = fake_app!(Config::default());
+    let tv_collection = ItemId::from_ertflix("2");
+    let types = async |uri: String| {
+        let items: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        items["Items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| format!("{}: {}", item["Type"].as_str().unwrap(), item["Name"].as_str().unwrap()))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(types(format!("/Items?ParentId={}", tv_collection)).await, vec!["Series: Title of series-1"]);
+    assert_eq!(
+        types(format!("/Items?ParentId={}&Recursive=true", tv_collection)).await,
+        vec!["Series: Title of series-1", "Episode: Επεισόδιο 1", "Episode: Επεισόδιο 2"],
+    );
+    assert_eq!(
+        types(format!("/Items?ParentId={}&Recursive=true&IncludeItemTypes=Episode", tv_collection)).await,
+        vec!["Episode: Επεισόδιο 1", "Episode: Επεισόδιο 2"],
+    );
+}
+
+#[actix_web::test]
+async fn items_carry_the_fields_clients_read() {
+    let app = fake_app!(Config::default());
+    let uri = format!("/Items?ParentId={}&Recursive=true", ItemId::from_ertflix("2"));
+    let items: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    let items = items["Items"].as_array().unwrap();
+    let (series, episode) = (&items[0], &items[1]);
+
+    for item in [series, episode] {
+        for field in ["Id", "Name", "Type", "ServerId", "ImageTags", "UserData", "MediaType"] {
+            assert!(!item[field].is_null(), "{} of {}", field, item);
+        }
+    }
+    assert_eq!((series["MediaType"].as_str(), episode["MediaType"].as_str()), (Some("Unknown"), Some("Video")));
+    assert!(episode["SeriesPrimaryImageTag"].is_string());
+    assert_eq!(episode["SeriesPrimaryImageTag"], series["ImageTags"]["Primary"]);
+    assert_eq!(episode["UserData"]["ItemId"], episode["Id"]);
+}
+
+#[actix_web::test]
+async fn unchanged_views_are_answered_with_not_modified() {
+    let app = fake_app!(Config::default());
+
+    let response = test::call_service(&app, test::TestRequest::get().uri("/UserViews").to_request()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let etag = response.headers().get(ETAG).expect("ETag").to_str().unwrap().to_string();
+
+    let response = test::call_service(
+        &app,
+        test::TestRequest::get().uri("/UserViews").insert_header((IF_NONE_MATCH, etag.clone())).to_request(),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 304);
+    assert!(test::read_body(response).await.is_empty());
+
+    let response = test::call_service(
+        &app,
+        test::TestRequest::get().uri("/UserViews").insert_header((IF_NONE_MATCH, "\"stale\"")).to_request(),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[actix_web::test]
+async fn movies_of_the_mock_client_are_listed() {
+    let app = fake_app!(Config::default(), MockErtflixClient);
+
+    let movies: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/movies").to_request()).await;
+    assert_eq!(movies["TotalRecordCount"], 2);
+    let movie_id = ItemId::from_ertflix("mock-movie-1").to_string();
+    let movie = movies["Items"].as_array().unwrap().iter().find(|movie| movie["Id"] == movie_id).expect("mock-movie-1");
+    assert_eq!(movie["Name"], "Η Λίμνη");
+    assert_eq!(movie["Type"], "Movie");
+    assert_eq!(movie["ProductionYear"], 1999);
+    assert_eq!(movie["Overview"], "Description of Η Λίμνη");
+}
+
+#[actix_web::test]
+async fn tv_shows_of_the_mock_client_are_listed() {
+    let app = fake_app!(Config::default(), MockErtflixClient);
+
+    let shows: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/tv").to_request()).await;
+    let shows = shows.as_array().expect("a list of series");
+    assert_eq!(shows.len(), 1);
+    assert_eq!(shows[0]["Id"], ItemId::from_ertflix("mock-series-1").to_string());
+    assert_eq!(shows[0]["Name"], "Το Νησί");
+    assert_eq!(shows[0]["Type"], "Series");
+    assert_eq!(shows[0]["IsFolder"], true);
+}
+
+#[actix_web::test]
+async fn views_of_the_mock_client_are_its_sections_and_continue_watching() {
+    let app = fake_app!(Config::default(), MockErtflixClient);
+
+    let views: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/UserViews").to_request()).await;
+    let names: Vec<&str> = views["Items"].as_array().unwrap().iter().map(|view| view["Name"].as_str().unwrap()).collect();
+    assert_eq!(names, vec![MOVIES_SECTION_CODENAME, TV_SHOWS_SECTION_CODENAME, "Continue Watching"]);
+    assert_eq!(views["Items"][0]["ChildCount"], 2);
+    assert_eq!(views["Items"][1]["ChildCount"], 1);
+    assert!(views["Items"].as_array().unwrap().iter().all(|view| view["Type"] == "CollectionFolder"));
+}
+
+/// `FakeErtflix` with its movies and TV shows sections emptied, as after ERTFLIX renames them
+fn empty_library() -> FakeErtflix {
+    let fake = FakeErtflix::default();
+    fake.answer(&format!("sectionCodename={}", MOVIES_SECTION_CODENAME), |_| ok(json!([section(MOVIES_SECTION_CODENAME, 1, &[])])))
+        .answer(&format!("sectionCodename={}", TV_SHOWS_SECTION_CODENAME), |_| ok(json!([section(TV_SHOWS_SECTION_CODENAME, 2, &[])])));
+    fake
+}
+
+#[actix_web::test]
+async fn an_empty_library_is_listed_as_empty_by_default() {
+    let app = fake_app!(Config::default(), over &empty_library());
+
+    let movies: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/movies").to_request()).await;
+    assert_eq!(movies["TotalRecordCount"], 0);
+    let shows: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/tv").to_request()).await;
+    assert_eq!(shows, json!([]));
+}
+
+#[actix_web::test]
+async fn an_empty_library_is_unavailable_when_configured() {
+    let mut config = Config::default();
+    config.library.unavailable_when_empty = true;
+    let app = fake_app!(config, over &empty_library());
+
+    for uri in ["/movies", "/tv"] {
+        let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(response.status().as_u16(), 503, "{}", uri);
+        let body: Value = test::read_body_json(response).await;
+        assert!(body["message"].as_str().unwrap().contains("library unavailable"), "{}", body);
+    }
+}
+
+#[actix_web::test]
+async fn slim_collections_are_smaller_but_keep_the_required_fields() {
+    let views = async |slim_collections: bool| {
+        let mut config = Config::default();
+        config.library.slim_collections = slim_collections;
+        let app = fake_app!(config);
+        let response = test::call_service(&app, test::TestRequest::get().uri("/UserViews").to_request()).await;
+        test::read_body(response).await
+    };
+
+    let full = views(false).await;
+    let slim = views(true).await;
+    assert!(slim.len() * 4 < full.len() * 3, "slim {} bytes, full {} bytes", slim.len(), full.len());
+
+    let slim: Value = serde_json::from_slice(&slim).unwrap();
+    for view in slim["Items"].as_array().unwrap() {
+        for field in ["Name", "Id", "ServerId", "Type", "IsFolder", "CollectionType", "ChildCount", "UserData", "ImageTags"] {
+            assert!(view.get(field).is_some(), "{} lacks {}", view["Name"], field);
+        }
+        assert!(view.get("Taglines").is_none());
+    }
+}
+
+#[actix_web::test]
+async fn the_section_param_lists_another_section_when_overrides_are_enabled() {
+    let names = async |config: Config| {
+        let app = fake_app!(config, MockErtflixClient);
+        let uri = format!("/movies?section={}", TV_SHOWS_SECTION_CODENAME);
+        let response = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        let status = response.status().as_u16();
+        let movies: Value = test::read_body_json(response).await;
+        let names: Vec<String> = movies["Items"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|movie| movie["Name"].as_str().unwrap().to_string())
+            .collect();
+        (status, names)
+    };
+
+    assert_eq!(names(Config::default()).await, (400, vec![]));
+    let mut config = Config::default();
+    config.admin.section_overrides = true;
+    assert_eq!(names(config).await, (200, vec!["Το Νησί".to_string()]));
+}
+
+/// Answers every request with 503, as ERTFLIX does during maintenance
+fn ertflix_down() -> FakeErtflix {
+    let fake = FakeErtflix::default();
+    fake.answer("", |_| status(503));
+    fake
+}
+
+#[actix_web::test]
+async fn an_ertflix_timeout_is_a_gateway_timeout() {
+    let mut config = Config::default();
+    config.ertflix.max_retries = 0;
+    // Never answers in time
+    let fake = FakeErtflix::default();
+    fake.answer("", |url| Err(Error::Timeout(format!("no answer from {}", url))));
+    let app = fake_app!(config, over &fake);
+
+    let response = test::call_service(&app, test::TestRequest::get().uri("/movies").to_request()).await;
+    assert_eq!(response.status(), 504);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["code"], "upstream_timeout");
+
+    let metrics = test::call_and_read_body(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+    let metrics = String::from_utf8(metrics.to_vec()).unwrap();
+    assert!(metrics.contains("ertflix_upstream_timeouts_total 1\n"), "{}", metrics);
+    assert!(metrics.contains("ertflix_upstream_errors_total 0\n"), "{}", metrics);
+}
+
+#[actix_web::test]
+async fn a_geo_blocked_answer_is_reported_as_such() {
+    // Answers like ERTFLIX does outside Greece, with an HTML page
+    let fake = FakeErtflix::default();
+    fake.answer("", |_| {
+        Ok(TransportResponse {
+            status: 403,
+            body: "<!DOCTYPE html><html><body>Not available in your region</body></html>".to_string(),
+            content_type: Some("text/html; charset=utf-8".to_string()),
+            ..Default::default()
+        })
+    });
+    let app = fake_app!(Config::default(), over &fake);
+
+    let response = test::call_service(&app, test::TestRequest::get().uri("/UserViews").to_request()).await;
+    assert_eq!(response.status(), 502);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["code"], "upstream_geo_blocked");
+    let message = body["message"].as_str().unwrap();
+    assert!(message.contains("Greek VPN") && message.contains("text/html"), "{}", message);
+}
+
+#[actix_web::test]
+async fn health_answers_even_when_ertflix_is_down_but_ready_does_not() {
+    let up = fake_app!(Config::default());
+    let down = fake_app!(Config::default(), over &ertflix_down());
+
+    for app in [&up, &down] {
+        let health: Value = test::call_and_read_body_json(app, test::TestRequest::get().uri("/health").to_request()).await;
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["version"], env!("CARGO_PKG_VERSION"));
+        assert!(health["uptime_seconds"].is_u64());
+    }
+
+    let ready = test::call_service(&up, test::TestRequest::get().uri("/ready").to_request()).await;
+    assert_eq!(ready.status(), 200);
+    let ready: Value = test::read_body_json(ready).await;
+    assert_eq!(ready["checks"]["ertflix"], "ok");
+
+    let unavailable = test::call_service(&down, test::TestRequest::get().uri("/ready").to_request()).await;
+    assert_eq!(unavailable.status(), 503);
+    let unavailable: Value = test::read_body_json(unavailable).await;
+    assert_eq!(unavailable["status"], "unavailable");
+    assert!(unavailable["checks"]["ertflix"].as_str().unwrap().contains("503"));
+}
+
+#[actix_web::test]
+async fn movies_of_every_section_are_listed_once() {
+    // The movies listed over two sections that share a tile
+    let fake = FakeErtflix::default();
+    fake.answer(&format!("sectionCodename={}", MOVIES_SECTION_CODENAME), |_| {
+        ok(json!([
+            section(MOVIES_SECTION_CODENAME, 1, &["movie-1", "movie-2"]),
+            section(MOVIES_SECTION_CODENAME, 3, &["movie-2", "movie-3"]),
+        ]))
+    });
+    let media_service = media_service_over(&Config::default(), &fake).await;
+
+    let movies = media_service.get_movies().await.unwrap();
+
+    let ids: Vec<&str> = movies.iter().map(|movie| movie.id.as_str()).collect();
+    assert_eq!(ids, ["movie-1", "movie-2", "movie-3"]);
+}
+
+#[actix_web::test]
+async fn views_and_movies_are_paged() {
+    let app = fake_app!(Config::default());
+    let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+    let all: Value = test::call_and_read_body_json(&app, get("/UserViews")).await;
+    let total = all["TotalRecordCount"].as_u64().unwrap();
+    let page: Value = test::call_and_read_body_json(&app, get("/UserViews?StartIndex=1&Limit=1")).await;
+    assert_eq!(page["Items"].as_array().unwrap().len(), 1);
+    assert_eq!(page["Items"][0]["Id"], all["Items"][1]["Id"]);
+    assert_eq!(page["StartIndex"], 1);
+    assert_eq!(page["TotalRecordCount"], total);
+
+    let past_the_end: Value = test::call_and_read_body_json(&app, get("/movies?StartIndex=10&Limit=5")).await;
+    assert_eq!(past_the_end["Items"], json!([]));
+    assert_eq!(past_the_end["StartIndex"], 10);
+    assert_eq!(past_the_end["TotalRecordCount"], 2);
+}
+
+#[actix_web::test]
+async fn limit_is_clamped_to_the_max_page_size() {
+    let mut config = Config::default();
+    config.library.max_page_size = 1;
+    let app = fake_app!(config);
+
+    let movies: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/movies?Limit=50").to_request()).await;
+    assert_eq!(movies["Items"].as_array().unwrap().len(), 1);
+    assert_eq!(movies["TotalRecordCount"], 2);
+}
+
+#[actix_web::test]
+async fn items_are_sorted_as_asked_with_missing_keys_last() {
+    let app = fake_app!(Config::default(), MockErtflixClient);
+    let names = async |sort: &str| -> Vec<String> {
+        let items: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&format!("/Items?{}", sort)).to_request()).await;
+        items["Items"].as_array().unwrap().iter().map(|item| item["Name"].as_str().unwrap().to_string()).collect()
+    };
+
+    // The series has no ProductionYear, so it comes last both ways
+    assert_eq!(names("SortBy=ProductionYear&SortOrder=Ascending").await, ["Η Λίμνη", "Ο Φάρος", "Το Νησί"]);
+    assert_eq!(names("SortBy=ProductionYear&SortOrder=Descending").await, ["Ο Φάρος", "Η Λίμνη", "Το Νησί"]);
+    assert_eq!(names("SortBy=SortName&SortOrder=Descending").await, ["Το Νησί", "Ο Φάρος", "Η Λίμνη"]);
+    assert_eq!(names("SortBy=ProductionYear,SortName").await, ["Η Λίμνη", "Ο Φάρος", "Το Νησί"]);
+}
+
+#[actix_web::test]
+async fn listings_without_a_sort_use_the_one_configured_for_their_collection() {
+    let names = async |sorting: config::SortingConfig, uri: &str| -> Vec<String> {
+        let app = fake_app!(Config { sorting, ..Default::default() }, MockErtflixClient);
+        let items: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        items["Items"].as_array().unwrap().iter().map(|item| item["Name"].as_str().unwrap().to_string()).collect()
+    };
+
+    // Movies are newest first by default, everything else by name
+    assert_eq!(names(config::SortingConfig::default(), "/movies").await, ["Ο Φάρος", "Η Λίμνη"]);
+    assert_eq!(names(config::SortingConfig::default(), "/Items").await, ["Η Λίμνη", "Ο Φάρος", "Το Νησί"]);
+
+    let mut sorting = config::SortingConfig::default();
+    sorting.collections.insert("movies".to_string(), config::SortSpec::new("SortName", "Ascending"));
+    sorting.default = config::SortSpec::new("ProductionYear", "Descending");
+    assert_eq!(names(sorting.clone(), "/movies").await, ["Η Λίμνη", "Ο Φάρος"]);
+    assert_eq!(names(sorting.clone(), "/Items").await, ["Ο Φάρος", "Η Λίμνη", "Το Νησί"]);
+    // A sort the client asks for wins over the configured one
+    assert_eq!(names(sorting, "/movies?SortBy=SortName&SortOrder=Descending").await, ["Ο Φάρος", "Η Λίμνη"]);
+}
+
+#[actix_web::test]
+async fn a_repeated_idempotency_key_replays_the_refresh_without_running_it_again() {
+    let mut config = Config::default();
+    config.admin.token = Some("secret".to_string());
+    let fake = FakeErtflix::default();
+    let app = fake_app!(config, over &fake);
+    // Every refresh starts with a page fetch
+    let page_fetches = || fake.requests_to("GetPageContent");
+    let refresh = |key: &str| {
+        test::TestRequest::post()
+            .uri("/admin/refresh")
+            .insert_header(("X-Admin-Token", "secret"))
+            .insert_header(("Idempotency-Key", key.to_string()))
+            .to_request()
+    };
+
+    let first = test::call_service(&app, refresh("refresh-1")).await;
+    assert_eq!(first.status(), 200);
+    let first = test::read_body(first).await;
+    let fetches = page_fetches();
+    assert!(fetches > 0);
+
+    let replayed = test::call_service(&app, refresh("refresh-1")).await;
+    assert_eq!(replayed.status(), 200);
+    assert_eq!(test::read_body(replayed).await, first);
+    assert_eq!(page_fetches(), fetches);
+
+    let other = test::call_service(&app, refresh("refresh-2")).await;
+    assert_eq!(other.status(), 200);
+    assert!(page_fetches() > fetches);
+}
+
+#[actix_web::test]
+async fn favorited_items_are_marked_and_listed_by_the_favorites_filter() {
+    let mut open = Config::default();
+    open.auth.allow_all = true;
+    let app = fake_app!(open);
+    let movie = ItemId::from_ertflix("movie-1").to_string();
+    let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+    let ids = |items: &Value| -> Vec<String> {
+        items["Items"].as_array().unwrap().iter().map(|item| item["Id"].as_str().unwrap().to_string()).collect()
+    };
+
+    let favorites: Value = test::call_and_read_body_json(&app, get("/Users/alice/Items?IsFavorite=true".to_string())).await;
+    assert_eq!(ids(&favorites), Vec::<String>::new());
+
+    let marked: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::post().uri(&format!("/Users/alice/FavoriteItems/{}", movie)).to_request(),
+    )
+    .await;
+    assert_eq!(marked["IsFavorite"], true);
+
+    let item: Value = test::call_and_read_body_json(&app, get(format!("/Users/alice/Items/{}", movie))).await;
+    assert_eq!(item["UserData"]["IsFavorite"], true);
+    let favorites: Value = test::call_and_read_body_json(&app, get("/Users/alice/Items?IsFavorite=true".to_string())).await;
+    assert_eq!(ids(&favorites), vec![movie.clone()]);
+    let others: Value = test::call_and_read_body_json(&app, get("/Users/alice/Items?IsFavorite=false".to_string())).await;
+    assert!(!ids(&others).contains(&movie));
+    // Favorites are per user
+    let bob: Value = test::call_and_read_body_json(&app, get("/Users/bob/Items?IsFavorite=true".to_string())).await;
+    assert_eq!(ids(&bob), Vec::<String>::new());
+
+    let unmarked = test::TestRequest::delete().uri(&format!("/Users/alice/FavoriteItems/{}", movie)).to_request();
+    assert!(test::call_service(&app, unmarked).await.status().is_success());
+    let favorites: Value = test::call_and_read_body_json(&app, get("/Users/alice/Items?IsFavorite=true".to_string())).await;
+    assert_eq!(ids(&favorites), Vec::<String>::new());
+}
+
+#[actix_web::test]
+async fn guests_may_not_favorite_items_for_another_user() {
+    let mut config = Config::default();
+    config.auth.allow_guest = true;
+    let app = fake_app!(secured config);
+    let guest = sign_in!(&app, "visitor");
+    let owner = config::identity().user_id.clone();
+    let movie = ItemId::from_ertflix("movie-1").to_string();
+    let as_guest = |request: test::TestRequest| request.insert_header(("X-Emby-Token", guest.as_str())).to_request();
+    let favorite = |user_id: &str| test::TestRequest::post().uri(&format!("/Users/{}/FavoriteItems/{}", user_id, movie));
+
+    assert_eq!(test::call_service(&app, as_guest(favorite(&owner))).await.status(), 401);
+    let unfavorite = test::TestRequest::delete().uri(&format!("/Users/{}/FavoriteItems/{}", owner, movie));
+    assert_eq!(test::call_service(&app, as_guest(unfavorite)).await.status(), 401);
+    let favorites = test::TestRequest::get().uri(&format!("/Users/{}/Items?IsFavorite=true", owner));
+    assert_eq!(test::call_service(&app, as_guest(favorites)).await.status(), 401);
+
+    assert_eq!(test::call_service(&app, as_guest(favorite(config::GUEST_USER_ID))).await.status(), 200);
+    let owner_token = sign_in!(&app, config::identity().username);
+    let favorites = test::TestRequest::get()
+        .uri(&format!("/Users/{}/Items?IsFavorite=true", owner))
+        .insert_header(("X-Emby-Token", owner_token))
+        .to_request();
+    let favorites: Value = test::call_and_read_body_json(&app, favorites).await;
+    assert_eq!(favorites["TotalRecordCount"], 0);
+}
+
+#[actix_web::test]
+async fn played_items_are_left_out_of_the_unplayed_filter() {
+    let mut open = Config::default();
+    open.auth.allow_all = true;
+    let app = fake_app!(open);
+    let movie = ItemId::from_ertflix("movie-1").to_string();
+    let unplayed = || test::TestRequest::get().uri("/Users/alice/Items?IsPlayed=false").to_request();
+    let ids = |items: &Value| -> Vec<String> {
+        items["Items"].as_array().unwrap().iter().map(|item| item["Id"].as_str().unwrap().to_string()).collect()
+    };
+
+    let before: Value = test::call_and_read_body_json(&app, unplayed()).await;
+    assert!(ids(&before).contains(&movie));
+
+    let marked: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::post().uri(&format!("/Users/alice/PlayedItems/{}", movie)).to_request(),
+    )
+    .await;
+    assert_eq!(marked["Played"], true);
+    assert_eq!(marked["PlayCount"], 1);
+
+    let after: Value = test::call_and_read_body_json(&app, unplayed()).await;
+    assert!(!ids(&after).contains(&movie));
+    assert_eq!(ids(&after).len(), ids(&before).len() - 1);
+    let played: Value =
+        test::call_and_read_body_json(&app, test::TestRequest::get().uri("/Users/alice/Items?IsPlayed=true").to_request()).await;
+    assert_eq!(ids(&played), vec![movie.clone()]);
+
+    let cleared = test::TestRequest::delete().uri(&format!("/Users/alice/PlayedItems/{}", movie)).to_request();
+    assert!(test::call_service(&app, cleared).await.status().is_success());
+    let again: Value = test::call_and_read_body_json(&app, unplayed()).await;
+    assert!(ids(&again).contains(&movie));
+}
+
+#[actix_web::test]
+async fn guests_may_not_mark_items_played_for_another_user() {
+    let mut config = Config::default();
+    config.auth.allow_guest = true;
+    let app = fake_app!(secured config);
+    let guest = sign_in!(&app, "visitor");
+    let owner = config::identity().user_id.clone();
+    let movie = ItemId::from_ertflix("movie-1").to_string();
+    let as_guest = |request: test::TestRequest| request.insert_header(("X-Emby-Token", guest.as_str())).to_request();
+    let played = |user_id: &str| format!("/Users/{}/PlayedItems/{}", user_id, movie);
+
+    assert_eq!(test::call_service(&app, as_guest(test::TestRequest::post().uri(&played(&owner)))).await.status(), 401);
+    assert_eq!(test::call_service(&app, as_guest(test::TestRequest::delete().uri(&played(&owner)))).await.status(), 401);
+    let marked = as_guest(test::TestRequest::post().uri(&played(config::GUEST_USER_ID)));
+    assert_eq!(test::call_service(&app, marked).await.status(), 200);
+}
+
+#[actix_web::test]
+async fn the_configured_aspect_ratios_are_emitted() {
+    let mut config = Config::default();
+    config.images.primary_aspect_ratio = 0.5;
+    config.images.movies_primary_aspect_ratio = Some(1.5);
+    let app = fake_app!(config);
+    let ratios = async |uri: &str| -> Vec<f64> {
+        let items: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        // `/tv` answers with a bare list, the others with a query result
+        let items = items.get("Items").unwrap_or(&items).as_array().unwrap();
+        items.iter().map(|item| item["PrimaryImageAspectRatio"].as_f64().unwrap()).collect()
+    };
+
+    // Both sections and Continue Watching
+    assert_eq!(ratios("/UserViews").await, [0.5, 0.5, 0.5]);
+    assert_eq!(ratios("/movies").await, [1.5, 1.5]);
+    assert_eq!(ratios("/tv").await, [0.5]);
+    assert_eq!(ratios("/Items?IncludeItemTypes=Movie").await, [1.5, 1.5]);
+}
+
+#[actix_web::test]
+async fn continue_watching_lists_the_session_users_unfinished_items_most_recent_first() {
+    let mut config = Config::default();
+    config.auth.allow_guest = true;
+    let app = fake_app!(secured config);
+    let owner_token = sign_in!(&app, config::DEFAULT_USERNAME);
+    let guest_token = sign_in!(&app, "visitor");
+    let owner = config::identity().user_id.clone();
+    let [movie_1, movie_2, series] = ["movie-1", "movie-2", "series-1"].map(|id| ItemId::from_ertflix(id).to_string());
+    let progress = |item_id: &str, token: &str| {
+        test::TestRequest::post()
+            .uri("/Sessions/Playing/Progress")
+            .insert_header(("X-Emby-Token", token.to_string()))
+            .set_json(json!({ "ItemId": item_id, "PositionTicks": 600_000_000 }))
+            .to_request()
+    };
+    let continue_watching = async |user_id: &str, token: &str| -> Vec<String> {
+        let uri = format!("/Users/{}/Items?ParentId={}", user_id, jellyfin::CONTINUE_WATCHING_ID);
+        let request = test::TestRequest::get().uri(&uri).insert_header(("X-Emby-Token", token.to_string())).to_request();
+        let items: Value = test::call_and_read_body_json(&app, request).await;
+        items["Items"].as_array().unwrap().iter().map(|item| item["Id"].as_str().unwrap().to_string()).collect()
+    };
+
+    assert_eq!(continue_watching(&owner, &owner_token).await, Vec::<String>::new());
+
+    for item_id in [&movie_1, &movie_2, &series] {
+        assert_eq!(test::call_service(&app, progress(item_id, &owner_token)).await.status(), 204);
+    }
+    let finished = test::TestRequest::post()
+        .uri(&format!("/Users/{}/PlayedItems/{}", owner, movie_2))
+        .insert_header(("X-Emby-Token", owner_token.clone()))
+        .to_request();
+    assert!(test::call_service(&app, finished).await.status().is_success());
+
+    assert_eq!(continue_watching(&owner, &owner_token).await, vec![series, movie_1]);
+    // Progress is kept for the user of the session that reported it
+    assert_eq!(continue_watching(config::GUEST_USER_ID, &guest_token).await, Vec::<String>::new());
+}
+
+/// Every `Id` anywhere in `value`
+fn ids_in(value: &Value) -> Vec<String> {
+    match value {
+        Value::Object(fields) => fields
+            .iter()
+            .flat_map(|(key, field)| match (key.as_str(), field) {
+                ("Id", Value::String(id)) => vec![id.clone()],
+                _ => ids_in(field),
+            })
+            .collect(),
+        Value::Array(values) => values.iter().flat_map(ids_in).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[actix_web::test]
+async fn every_emitted_id_is_a_32_hex_guid_and_items_keep_their_ertflix_id() {
+    let app = fake_app!(Config::default());
+    let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+    let views: Value = test::call_and_read_body_json(&app, get("/UserViews")).await;
+    let items: Value = test::call_and_read_body_json(&app, get("/Items?Recursive=true&Fields=Seasons")).await;
+    let ids: Vec<String> = ids_in(&views).into_iter().chain(ids_in(&items)).collect();
+
+    // Views, two movies, the series, its season and its two episodes
+    assert!(ids.len() >= 9, "{:?}", ids);
+    for id in &ids {
+        assert!(id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()), "{} is not a 32-hex GUID", id);
+    }
+    let movie = items["Items"].as_array().unwrap().iter().find(|item| item["Type"] == "Movie").unwrap();
+    assert_eq!(movie["Id"], ItemId::from_ertflix(movie["ProviderIds"]["Ertflix"].as_str().unwrap()).to_string());
+}
+
+#[actix_web::test]
+async fn playback_info_and_playback_reports_adjust_the_active_streams() {
+    let app = fake_app!(Config::default(), MockErtflixClient);
+    let active_streams = async || -> String {
+        let metrics = test::call_and_read_body(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        let metrics = String::from_utf8(metrics.to_vec()).unwrap();
+        metrics.lines().find(|line| line.starts_with("ertflix_active_streams ")).unwrap().to_string()
+    };
+    let report = |uri: &str, body: Value| test::TestRequest::post().uri(uri).set_json(body).to_request();
+    let movie = ItemId::from_ertflix("mock-movie-1").to_string();
+
+    assert_eq!(active_streams().await, "ertflix_active_streams 0");
+
+    let playback_info: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::get().uri(&format!("/Items/{}/PlaybackInfo", movie)).to_request(),
+    )
+    .await;
+    let play_session_id = playback_info["PlaySessionId"].as_str().unwrap();
+    assert_eq!(active_streams().await, "ertflix_active_streams 1");
+
+    let started = report("/Sessions/Playing", json!({ "ItemId": movie, "PlaySessionId": play_session_id }));
+    assert_eq!(test::call_service(&app, started).await.status(), 204);
+    assert_eq!(active_streams().await, "ertflix_active_streams 1");
+    let other = report("/Sessions/Playing", json!({ "ItemId": movie, "PlaySessionId": "other-device" }));
+    test::call_service(&app, other).await;
+    assert_eq!(active_streams().await, "ertflix_active_streams 2");
+
+    let stopped = report("/Sessions/Playing/Stopped", json!({ "ItemId": movie, "PlaySessionId": play_session_id }));
+    assert_eq!(test::call_service(&app, stopped).await.status(), 204);
+    assert_eq!(active_streams().await, "ertflix_active_streams 1");
+}
+
+#[actix_web::test]
+async fn items_without_a_user_segment_match_the_default_users_items() {
+    let mut open = Config::default();
+    open.auth.allow_all = true;
+    let app = fake_app!(open);
+    let owner = config::identity().user_id.clone();
+    let get = async |uri: String| -> Value {
+        test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await
+    };
+
+    for query in [
+        String::new(),
+        format!("ParentId={}", ItemId::from_ertflix("1")),
+        format!("ParentId={}&Recursive=true", ItemId::from_ertflix("2")),
+        "IncludeItemTypes=Movie&SortBy=SortName&SortOrder=Descending".to_string(),
+        "StartIndex=1&Limit=1".to_string(),
+        "IsFavorite=false".to_string(),
+    ] {
+        let unscoped = get(format!("/Items?{}", query)).await;
+        let scoped = get(format!("/Users/{}/Items?{}", owner, query)).await;
+        assert!(unscoped["TotalRecordCount"].as_u64().unwrap() > 0, "{}", query);
+        assert_eq!(unscoped, scoped, "{}", query);
+    }
+}
+
+#[actix_web::test]
+async fn expired_movies_are_served_stale_while_they_are_fetched_again() {
+    let mut config = Config::default();
+    config.cache.movies_ttl_seconds = 0;
+    config.cache.stale_grace_seconds = 60;
+    let fake = FakeErtflix::default();
+    let app = fake_app!(config, over &fake);
+    let movie_section_fetches = || fake.requests_to(&format!("sectionCodename={}", MOVIES_SECTION_CODENAME));
+    let movies = async || {
+        let response = test::call_service(&app, test::TestRequest::get().uri("/movies").to_request()).await;
+        let cache = response.headers().get("X-Cache").unwrap().to_str().unwrap().to_string();
+        let movies: Value = test::read_body_json(response).await;
+        (cache, movies["TotalRecordCount"].clone())
+    };
+
+    assert_eq!(movies().await, ("MISS".to_string(), json!(2)));
+    let fetched = movie_section_fetches();
+
+    assert_eq!(movies().await, ("REVALIDATING".to_string(), json!(2)));
+    // The stale answer doesn't wait for the fetch, which runs in the background
+    for _ in 0..50 {
+        if movie_section_fetches() > fetched {
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(movie_section_fetches(), fetched + 1);
+}
+
+#[actix_web::test]
+async fn expired_movies_past_the_grace_window_are_fetched_before_answering() {
+    let mut config = Config::default();
+    config.cache.movies_ttl_seconds = 0;
+    config.cache.stale_grace_seconds = 0;
+    let app = fake_app!(config);
+
+    for _ in 0..2 {
+        let response = test::call_service(&app, test::TestRequest::get().uri("/movies").to_request()).await;
+        assert_eq!(response.headers().get("X-Cache").unwrap(), "MISS");
+    }
+}
+
+#[actix_web::test]
+async fn plain_series_listings_fetch_no_series_details() {
+    let fake = FakeErtflix::default();
+    let app = fake_app!(Config::default(), over &fake);
+    let get = async |uri: String| -> Value {
+        test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await
+    };
+
+    get("/tv".to_string()).await;
+    get("/Items?IncludeItemTypes=Series".to_string()).await;
+    get(format!("/Items?ParentId={}", ItemId::from_ertflix("2"))).await;
+    assert_eq!(fake.requests_to("GetSeriesDetails"), 0);
+
+    let listed = get("/tv?Fields=Seasons".to_string()).await;
+    assert_eq!(listed[0]["Seasons"].as_array().unwrap().len(), 1);
+    assert!(fake.requests_to("GetSeriesDetails") > 0);
+}
+
+#[actix_web::test]
+async fn series_are_listed_without_seasons_unless_asked_and_detailed_with_them() {
+    let app = fake_app!(Config::default());
+    let series_id = ItemId::from_ertflix("series-1").to_string();
+    let get = async |uri: String| -> Value {
+        test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await
+    };
+    let seasons = |series: &Value| series["Seasons"].as_array().map_or(0, Vec::len);
+
+    let listed = get("/Items?IncludeItemTypes=Series".to_string()).await;
+    assert_eq!(seasons(&listed["Items"][0]), 0);
+
+    let listed = get("/Items?IncludeItemTypes=Series&Fields=Seasons".to_string()).await;
+    assert_eq!(seasons(&listed["Items"][0]), 1);
+    assert_eq!(listed["Items"][0]["Seasons"][0]["Episodes"].as_array().unwrap().len(), 2);
+
+    let detail = get(format!("/Items/{}", series_id)).await;
+    assert_eq!(detail["Id"], series_id);
+    assert_eq!(seasons(&detail), 1);
+}
+
+#[actix_web::test]
+async fn an_item_ertflix_does_not_list_is_not_found() {
+    let app = fake_app!(Config::default());
+    let unknown = ItemId::from_ertflix("no-such-tile");
+
+    let response = test::call_service(&app, test::TestRequest::get().uri(&format!("/Items/{}", unknown)).to_request()).await;
+    assert_eq!(response.status(), 404);
+    let error: Value = test::read_body_json(response).await;
+    assert_eq!(error["code"], "not_found");
+}
+
+#[actix_web::test]
+async fn a_malformed_item_id_is_a_bad_request() {
+    let app = fake_app!(Config::default());
+
+    for uri in ["/Items/movie-1", "/Users/user/Items/not-a-guid", "/Items/0123"] {
+        let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(response.status(), 400, "{}", uri);
+        let error: Value = test::read_body_json(response).await;
+        assert_eq!(error["code"], "bad_request", "{}", uri);
+    }
+}
+
+#[actix_web::test]
+async fn short_episodes_are_dropped_when_only_full_episodes_are_kept() {
+    let episodes = async |enabled: bool| -> Vec<String> {
+        let mut config = Config::default();
+        config.full_episodes.enabled = enabled;
+        // A one minute clip among the episodes of the series
+        let fake = FakeErtflix::default();
+        fake.answer("GetSeriesDetails", |_| {
+            ok(json!({
+                "episodes": [
+                    { "id": "episode-1", "title": "Επεισόδιο 1", "seasonNumber": 1, "durationSeconds": 2700 },
+                    { "id": "preview", "title": "Πρώτη ματιά", "seasonNumber": 1, "durationSeconds": 60 },
+                    { "id": "episode-2", "title": "Επεισόδιο 2", "seasonNumber": 1, "durationSeconds": 2700 },
+                ],
+            }))
+        });
+        let app = fake_app!(config, over &fake);
+        let items: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get().uri("/Items?Recursive=true&IncludeItemTypes=Episode").to_request(),
+        )
+        .await;
+        items["Items"].as_array().unwrap().iter().map(|item| item["Name"].as_str().unwrap().to_string()).collect()
+    };
+
+    assert_eq!(episodes(false).await.len(), 3);
+    assert_eq!(episodes(true).await, ["Επεισόδιο 1", "Επεισόδιο 2"]);
+}
+
+#[actix_web::test]
+async fn favorites_get_their_stream_urls_resolved_ahead_of_playback() {
+    let fake = FakeErtflix::default();
+    fake.answer("AcquireContent", |_| ok(json!({ "mediaFiles": [{ "formats": [{ "url": "https://streams.test/master.m3u8" }] }] })));
+    let media_service = media_service_over(&Config::default(), &fake).await;
+    let user_data_store = UserDataStore::new();
+    let [movie_1, movie_2, series] = ["movie-1", "movie-2", "series-1"].map(ItemId::from_ertflix);
+    user_data_store.set_favorite("alice", &movie_1, true);
+    user_data_store.set_favorite("alice", &movie_2, true);
+    user_data_store.set_favorite("bob", &series, true);
+
+    let resolved = stream_prefetch::prefetch_favorites(&media_service, &user_data_store, "alice", 2).await;
+
+    assert_eq!(resolved, 2);
+    assert_eq!(fake.requests_to("AcquireContent"), 2);
+    // Playback uses the prefetched URL rather than resolving it again
+    assert_eq!(media_service.get_stream_url(&movie_1).await.unwrap(), "https://streams.test/master.m3u8");
+    assert_eq!(fake.requests_to("AcquireContent"), 2);
+}
+
+#[actix_web::test]
+async fn single_items_come_from_the_cache_then_the_listing_then_ertflix() {
+    let fake = FakeErtflix::default();
+    let media_service = media_service_over(&Config::default(), &fake).await;
+    media_service.get_movies().await.unwrap();
+    let listed = fake.requested_tiles();
+
+    // Found in the cached movie listing
+    assert_eq!(media_service.get_movie_by_id("movie-2").await.unwrap().id, "movie-2");
+    assert_eq!(fake.requested_tiles(), listed);
+
+    // Not listed, so its tile is fetched once and then served from its own cache entry
+    for _ in 0..2 {
+        assert_eq!(media_service.get_movie_by_id("movie-9").await.unwrap().id, "movie-9");
+        assert_eq!(fake.requested_tiles(), listed + 1);
+    }
+}
+
+#[actix_web::test]
+async fn movies_released_before_the_min_year_are_left_out_but_undated_ones_are_kept() {
+    let ids = async |min_year: Option<u32>| {
+        let mut config = Config::default();
+        config.library.min_year = min_year;
+        // movie-1 missing its year and movie-2 released in 1960
+        let fake = FakeErtflix::default();
+        fake.edit_tiles(|tile| {
+            if tile["id"] == "movie-1" {
+                tile.as_object_mut().unwrap().remove("year");
+            } else {
+                tile["year"] = json!(1960);
+            }
+        });
+        let media_service = media_service_over(&config, &fake).await;
+        let mut ids: Vec<String> = media_service.get_movies().await.unwrap().into_iter().map(|movie| movie.id).collect();
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(ids(None).await, ["movie-1", "movie-2"]);
+    assert_eq!(ids(Some(1950)).await, ["movie-1", "movie-2"]);
+    assert_eq!(ids(Some(2000)).await, ["movie-1"]);
+}
+
+#[actix_web::test]
+async fn the_selftest_passes_for_a_good_tile_and_fails_when_the_conversion_does() {
+    // Tile "untitled" comes without a title
+    let fake = FakeErtflix::default();
+    fake.edit_tiles(|tile| {
+        if tile["id"] == "untitled" {
+            tile["title"] = json!(" ");
+        }
+    });
+    let app_for = async |tile_id: &str| {
+        let mut config = Config::default();
+        config.admin.token = Some("secret".to_string());
+        config.admin.selftest_tile_id = Some(tile_id.to_string());
+        fake_app!(config, over &fake)
+    };
+    let selftest = || test::TestRequest::get().uri("/admin/selftest").insert_header(("X-Admin-Token", "secret")).to_request();
+    let outcome = async |response: actix_web::dev::ServiceResponse| {
+        let status = response.status();
+        let report: Value = test::read_body_json(response).await;
+        (status, report)
+    };
+
+    let app = app_for("movie-1").await;
+    for _ in 0..2 {
+        let fetches = fake.requests_to("GetTiles");
+        let (status, report) = outcome(test::call_service(&app, selftest()).await).await;
+        assert_eq!(status, 200);
+        assert_eq!(report["Passed"], true);
+        assert_eq!(report["TileId"], "movie-1");
+        assert!(report["Checks"].as_array().unwrap().iter().all(|check| check["Passed"] == true), "{}", report);
+        let id = report["Checks"].as_array().unwrap().iter().find(|check| check["Name"] == "id").unwrap();
+        assert_eq!(id["Detail"], ItemId::from_ertflix("movie-1").to_string());
+        // Every run reaches ERTFLIX rather than a cached tile
+        assert!(fake.requests_to("GetTiles") > fetches);
+    }
+
+    let app = app_for("untitled").await;
+    let (status, report) = outcome(test::call_service(&app, selftest()).await).await;
+    assert_eq!(status, 503);
+    assert_eq!(report["Passed"], false);
+    let failed: Vec<&Value> = report["Checks"].as_array().unwrap().iter().filter(|check| check["Passed"] == false).collect();
+    assert_eq!(failed.len(), 1, "{}", report);
+    assert_eq!(failed[0]["Name"], "title");
+}
+
+const ROW_COUNT: i32 = 6;
+
+/// `row-N` holding N tiles
+fn row_tiles(n: i32) -> Vec<String> {
+    (1..=n).map(|tile| format!("row-{}-tile-{}", n, tile)).collect()
+}
+
+/// A page of six rows, `row-N` holding N tiles although the page lists only its first
+fn many_rows() -> FakeErtflix {
+    let fake = FakeErtflix::default();
+    fake.answer("GetPageContent", |_| {
+        let rows: Vec<Value> = (1..=ROW_COUNT)
+            .map(|n| section(&format!("row-{}", n), n, &[row_tiles(n)[0].as_str()]))
+            .collect();
+        ok(json!({ "sectionContents": rows }))
+    })
+    .answer("sectionCodename=row-", |url| {
+        let n: i32 = url.split("sectionCodename=row-").nth(1).and_then(|rest| rest.split('&').next()?.parse().ok()).unwrap();
+        let tiles = row_tiles(n);
+        ok(json!([section(&format!("row-{}", n), n, &tiles.iter().map(String::as_str).collect::<Vec<_>>())]))
+    });
+    fake
+}
+
+#[actix_web::test]
+async fn collection_child_counts_are_resolved_a_bounded_number_at_a_time() {
+    let mut config = Config::default();
+    config.ertflix.max_concurrent_requests = 2;
+    let fake = many_rows();
+    fake.delay_answers(Duration::from_millis(20));
+    let media_service = media_service_over(&config, &fake).await;
+
+    let collections = media_service.get_collections().await.unwrap();
+
+    for n in 1..=ROW_COUNT {
+        let row = collections.iter().find(|collection| collection.name == format!("row-{}", n)).unwrap();
+        assert_eq!(row.child_count, n);
+    }
+    assert_eq!(fake.peak_requests_in_flight(), 2);
+}
+
+#[actix_web::test]
+async fn sessions_are_dated_by_the_clock_of_the_session_store() {
+    let mut config = Config::default();
+    config.admin.token = Some("secret".to_string());
+    let sessions = SessionStore::with_clock(Box::new(FixedClock::at("2024-05-01T12:30:00Z")));
+    let app = test::init_service(fake_app!(@app config, FakeClient).app_data(web::Data::new(sessions))).await;
+
+    let auth: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::post()
+            .uri("/Users/AuthenticateByName")
+            .insert_header(("X-Emby-Authorization", r#"MediaBrowser Client="Infuse", Device="iPad", DeviceId="device-5", Version="8.0""#))
+            .set_json(json!({ "Username": config::identity().username, "Pw": "" }))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(auth["SessionInfo"]["LastActivityDate"], "2024-05-01T12:30:00.0000000Z");
+
+    let listed: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::get().uri("/Sessions").insert_header(("X-Admin-Token", "secret")).to_request(),
+    )
+    .await;
+    assert_eq!(listed[0]["LastActivityDate"], "2024-05-01T12:30:00+00:00");
+}
+
+#[actix_web::test]
+async fn only_admins_list_the_sessions_of_other_users() {
+    let mut config = Config::default();
+    config.admin.token = Some("secret".to_string());
+    config.auth.allow_guest = true;
+    let app = fake_app!(secured config);
+    let owner_token = sign_in!(&app, config::identity().username);
+    let guest_token = sign_in!(&app, "visitor");
+    let listed_users = async |request: test::TestRequest| -> Vec<String> {
+        let sessions: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+        sessions.as_array().unwrap().iter().map(|session| session["UserId"].as_str().unwrap().to_string()).collect()
+    };
+    let sessions = || test::TestRequest::get().uri("/Sessions");
+
+    let mut everyone = listed_users(sessions().insert_header(("X-Admin-Token", "secret"))).await;
+    everyone.sort();
+    let mut expected = vec![config::identity().user_id.clone(), config::GUEST_USER_ID.to_string()];
+    expected.sort();
+    assert_eq!(everyone, expected);
+    assert_eq!(
+        listed_users(sessions().insert_header(("X-Emby-Token", guest_token))).await,
+        [config::GUEST_USER_ID]
+    );
+    assert_eq!(
+        listed_users(sessions().insert_header(("X-Emby-Token", owner_token))).await,
+        [config::identity().user_id.clone()]
+    );
+}
+
+#[actix_web::test]
+async fn the_token_of_a_session_an_admin_ended_is_refused() {
+    let mut config = Config::default();
+    config.admin.token = Some("secret".to_string());
+    let app = fake_app!(secured config);
+    let views = |token: &str| test::TestRequest::get().uri("/UserViews").insert_header(("X-Emby-Token", token)).to_request();
+
+    let token = sign_in!(&app, config::identity().username);
+    assert_eq!(test::call_service(&app, views(&token)).await.status(), 200);
+    let sessions: Value = test::call_and_read_body_json(
+        &app,
+        test::TestRequest::get().uri("/Sessions").insert_header(("X-Admin-Token", "secret")).to_request(),
+    )
+    .await;
+    let session_id = sessions[0]["Id"].as_str().unwrap();
+
+    let ended = test::TestRequest::delete()
+        .uri(&format!("/Sessions/{}", session_id))
+        .insert_header(("X-Admin-Token", "secret"))
+        .to_request();
+    assert_eq!(test::call_service(&app, ended).await.status(), 204);
+    assert_eq!(test::call_service(&app, views(&token)).await.status(), 401);
+}
@@ -1,13 +1,30 @@
-use actix_web::{web, App, HttpServer, middleware::Logger};
-use tracing::{info, warn};
+use actix_web::{middleware, web, App, HttpServer};
+use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+use std::time::Duration;
+
 use crate::api::ertflix_client::DefaultErtflixClient;
+use crate::services::cache_warmer;
+use crate::services::idempotency::IdempotencyStore;
+use crate::services::image_proxy::ImageProxy;
+use crate::services::library_watcher::LibraryWatcher;
 use crate::services::media_service;
+use crate::services::stream_prefetch;
+use crate::services::sessions::SessionStore;
+use crate::services::stream_tracker::StreamTracker;
+use crate::services::supervisor::Supervisor;
+use crate::services::user_data::UserDataStore;
 
 mod api;
+mod clock;
 mod config;
+mod doctor;
+mod error;
+#[cfg(test)]
+mod integration_tests;
 mod models;
+mod request_log;
 mod routes;
 mod services;
 
@@ -22,34 +39,203 @@ async fn main() -> std::io::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    std::sync::LazyLock::force(&routes::handlers::STARTED);
+
+    let app_config = match config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Failed to load configuration: {}", e);
+            return Err(std::io::Error::other(e.to_string()));
+        }
+    };
+    config::set_identity(app_config.identity.clone());
+
+    if std::env::args().nth(1).as_deref() == Some("doctor") {
+        let passed = doctor::run::<DefaultErtflixClient>(&app_config).await;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
     info!("Starting Ertflix to Jellyfin adapter server");
-    info!("Binding to address: 0.0.0.0:25860");
+    let bind_address = app_config.server.bind_address.clone();
+    info!("Binding to address: {}", bind_address);
+
+    let media_service = match media_service::MediaService::<DefaultErtflixClient>::from_config(&app_config).await {
+        Ok(service) => web::Data::new(service),
+        Err(e) => {
+            warn!("Failed to initialize media service: {}", e);
+            return Err(std::io::Error::other(e.to_string()));
+        }
+    };
+
+    info!("Media service initialized with Ertflix API URL: {}", app_config.ertflix.base_url);
+
+    let idempotency_store = web::Data::new(IdempotencyStore::from_config(&app_config));
+    let user_data_store = web::Data::new(UserDataStore::new());
+    let session_store = web::Data::new(SessionStore::new());
+    let image_proxy = web::Data::new(ImageProxy::new(app_config.images.max_concurrent_fetches));
+    let supervisor = web::Data::new(Supervisor::default());
+
+    if app_config.polling.enabled {
+        let media_service = media_service.clone();
+        let interval = Duration::from_secs(app_config.polling.interval_seconds);
+        supervisor.spawn("LibraryWatcher", move || {
+            let media_service = media_service.clone();
+            async move { LibraryWatcher::new().run(&media_service, interval).await }
+        });
+    }
+
+    if app_config.cache_warming.enabled {
+        let media_service = media_service.clone();
+        let interval = Duration::from_secs(app_config.cache_warming.interval_seconds);
+        let cache = &app_config.cache;
+        let shortest_ttl = Duration::from_secs(
+            cache.collections_ttl_seconds.min(cache.movies_ttl_seconds).min(cache.tv_shows_ttl_seconds),
+        );
+        supervisor.spawn("CacheWarmer", move || {
+            let media_service = media_service.clone();
+            async move { cache_warmer::run(&media_service, interval, shortest_ttl).await }
+        });
+    }
 
-    let media_service = web::Data::new(
-        media_service::MediaService::<DefaultErtflixClient>::new(config::ERTFLIX_API_URL)
-    );
+    if app_config.streams.prefetch_favorites {
+        let media_service = media_service.clone();
+        let user_data_store = user_data_store.clone();
+        let concurrency = app_config.streams.prefetch_concurrency;
+        let interval = Duration::from_secs(app_config.streams.prefetch_interval_seconds);
+        supervisor.spawn("StreamPrefetch", move || {
+            let media_service = media_service.clone();
+            let user_data_store = user_data_store.clone();
+            async move {
+                stream_prefetch::run(&media_service, &user_data_store, &config::identity().user_id, concurrency, interval).await
+            }
+        });
+    }
+
+    let stream_tracker = web::Data::new(StreamTracker::new(app_config.streams.session_timeout()));
+    {
+        let stream_tracker = stream_tracker.clone();
+        supervisor.spawn("StreamSweeper", move || {
+            let stream_tracker = stream_tracker.clone();
+            async move {
+                let mut ticker = actix_web::rt::time::interval(stream_tracker.timeout());
+                loop {
+                    ticker.tick().await;
+                    stream_tracker.sweep();
+                }
+            }
+        });
+    }
 
-    info!("Media service initialized with Ertflix API URL: {}", config::ERTFLIX_API_URL);
+    let workers = app_config.server.worker_count();
+    info!("Using {} HTTP workers", workers);
+    let shutdown_timeout = app_config.server.shutdown_timeout_seconds;
+    let logging = app_config.logging.clone();
+    let app_config = web::Data::new(config::SharedConfig::new(app_config));
+    #[cfg(unix)]
+    actix_web::rt::spawn(reload_on_hangup(app_config.clone(), media_service.clone()));
 
     let server_result = HttpServer::new(move || {
         info!("Configuring new app worker");
         App::new()
             .app_data(media_service.clone())
-            .wrap(Logger::default()) // Add request logging middleware
-            .wrap(tracing_actix_web::TracingLogger::default()) // Add tracing middleware
+            .app_data(app_config.clone())
+            .app_data(idempotency_store.clone())
+            .app_data(user_data_store.clone())
+            .app_data(stream_tracker.clone())
+            .app_data(session_store.clone())
+            .app_data(image_proxy.clone())
+            .app_data(supervisor.clone())
+            .wrap(middleware::from_fn(routes::auth::require_session_token))
+            .wrap(request_log::request_logger(&logging)) // Add request logging middleware
+            .wrap(tracing_actix_web::TracingLogger::<request_log::RedactedRootSpan>::new()) // Add tracing middleware
             .configure(routes::init_routes::<DefaultErtflixClient>)
     })
-    .bind("0.0.0.0:25860");
+    .workers(workers)
+    .shutdown_timeout(shutdown_timeout)
+    // Signals are handled below, so the shutdown can be logged
+    .disable_signals()
+    .bind(&bind_address);
 
     match server_result {
         Ok(server) => {
-            info!("Server successfully bound to 0.0.0.0:25860");
+            info!("Server successfully bound to {}", bind_address);
             info!("Server starting...");
-            server.run().await
+            let server = server.run();
+            let handle = server.handle();
+            actix_web::rt::spawn(async move {
+                let signal = shutdown_signal().await;
+                info!("Received {}, draining in-flight requests for up to {}s", signal, shutdown_timeout);
+                handle.stop(true).await;
+            });
+            server.await?;
+            info!("Server shut down cleanly");
+            Ok(())
         }
         Err(e) => {
-            warn!("Failed to bind server to 0.0.0.0:25860: {}", e);
+            warn!("Failed to bind server to {}: {}", bind_address, e);
             Err(e)
         }
     }
 }
+
+/// Reloads the configuration file on every SIGHUP, applying what can change while
+/// serving and logging what only takes effect after a restart. A file that fails to
+/// load leaves the current configuration in place.
+#[cfg(unix)]
+async fn reload_on_hangup(
+    shared_config: web::Data<config::SharedConfig>,
+    media_service: web::Data<media_service::MediaService<DefaultErtflixClient>>,
+) {
+    use actix_web::rt::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Failed to listen for SIGHUP, the configuration can't be reloaded: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading the configuration");
+        let reloaded = match config::Config::load() {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                error!("Keeping the current configuration, reloading it failed: {}", e);
+                continue;
+            }
+        };
+        media_service.apply_config(&reloaded).await;
+        let changes = shared_config.replace(reloaded);
+        if changes.is_empty() {
+            info!("Configuration reloaded, nothing changed");
+            continue;
+        }
+        if !changes.applied.is_empty() {
+            info!("Configuration reloaded, applied changes to {}", changes.applied.join(", "));
+        }
+        if !changes.need_restart.is_empty() {
+            warn!("Changes to {} take effect after a restart", changes.need_restart.join(", "));
+        }
+    }
+}
+
+/// Waits for SIGINT or, on Unix, SIGTERM, returning the name of the signal received
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let interrupt = std::pin::pin!(actix_web::rt::signal::ctrl_c());
+                let terminate = std::pin::pin!(terminate.recv());
+                return match futures::future::select(interrupt, terminate).await {
+                    futures::future::Either::Left(_) => "SIGINT",
+                    futures::future::Either::Right(_) => "SIGTERM",
+                };
+            }
+            Err(e) => warn!("Failed to listen for SIGTERM, only SIGINT stops the server: {}", e),
+        }
+    }
+    let _ = actix_web::rt::signal::ctrl_c().await;
+    "SIGINT"
+}
@@ -1,44 +1,255 @@
 use crate::api::ertflix_client;
+use crate::config;
+use tracing::debug;
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct Movie {
     pub id: String,
+    pub codename: String,
     pub title: String,
-    pub year: u32,
+    pub year: Option<u32>,
     pub genre: Vec<String>,
     pub description: String,
+    #[serde(default)]
+    pub images: ImageUrls,
+    /// Codenames of the sections the movie was listed in, when source tagging is enabled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Every part of a movie ERTFLIX split across tiles, in order, once merged
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<MoviePart>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trailer_url: Option<String>,
+}
+
+/// One tile of a movie that ERTFLIX lists in several parts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MoviePart {
+    pub id: String,
+    pub codename: String,
+    pub title: String,
+    pub part_number: u32,
 }
 
 impl From<ertflix_client::Tile> for Movie {
     fn from(tile: ertflix_client::Tile) -> Self {
+        let genre = tile.genre_names();
         Self {
             id: tile.id,
+            codename: tile.codename,
             title: tile.title.unwrap_or_default(),
-            year: tile.year.unwrap_or(1970), // Placeholder for year
-            genre: Vec::new(),               // Placeholder for an empty list of genres
+            year: tile.year,
+            genre,
             description: tile.description.unwrap_or_default(), // Placeholder for description
+            images: ImageUrls::from_tile_images(&tile.images),
+            tags: Vec::new(),
+            parts: Vec::new(),
+            trailer_url: tile.trailer_url,
+        }
+    }
+}
+
+/// Merges movies whose titles mark them as parts of the same film (e.g. "Ο Θίασος - Μέρος 1"
+/// and "Ο Θίασος - Μέρος 2") into one movie listing every part. The merged movie keeps the
+/// first part's id and details under the shared title. A lone part is left as it is.
+pub fn merge_movie_parts(movies: Vec<Movie>) -> Vec<Movie> {
+    let mut merged: Vec<Movie> = Vec::with_capacity(movies.len());
+    let mut split: Vec<(String, Vec<(u32, Movie)>)> = Vec::new();
+
+    for movie in movies {
+        let Some((base_title, part_number)) = part_from_title(&movie.title) else {
+            merged.push(movie);
+            continue;
+        };
+        match split.iter_mut().find(|(title, _)| title.to_lowercase() == base_title.to_lowercase()) {
+            Some((_, parts)) => parts.push((part_number, movie)),
+            None => split.push((base_title, vec![(part_number, movie)])),
+        }
+    }
+
+    for (base_title, mut parts) in split {
+        if parts.len() < 2 {
+            merged.extend(parts.into_iter().map(|(_, movie)| movie));
+            continue;
+        }
+        parts.sort_by_key(|(part_number, _)| *part_number);
+        let listed: Vec<MoviePart> = parts
+            .iter()
+            .map(|(part_number, movie)| MoviePart {
+                id: movie.id.clone(),
+                codename: movie.codename.clone(),
+                title: movie.title.clone(),
+                part_number: *part_number,
+            })
+            .collect();
+        let (_, mut movie) = parts.swap_remove(0);
+        debug!("Merging {} parts of {}", listed.len(), base_title);
+        movie.title = base_title;
+        movie.parts = listed;
+        merged.push(movie);
+    }
+    merged
+}
+
+/// Splits titles such as "Ο Θίασος - Μέρος 2", "Ο Θίασος (2ο Μέρος)" or "Heat: Part 1" into
+/// the shared title and the part number
+fn part_from_title(title: &str) -> Option<(String, u32)> {
+    let lower = title.to_lowercase();
+    // Offsets found in the lowercase title are used to cut the original one
+    if lower.len() != title.len() {
+        return None;
+    }
+    let (marker_start, marker) = ["μέρος", "μερος", "part"]
+        .iter()
+        .filter_map(|marker| lower.rfind(marker).map(|start| (start, *marker)))
+        .max_by_key(|(start, _)| *start)?;
+
+    let trailing = |c: char| c.is_whitespace() || matches!(c, ')' | ']' | '.');
+    let after = lower[marker_start + marker.len()..].trim_matches(trailing);
+    let before = lower[..marker_start].trim_end();
+    let (part_number, base_end) = if !after.is_empty() {
+        // "Μέρος 2"
+        (after.parse().ok()?, marker_start)
+    } else {
+        // "2ο Μέρος"
+        let ordinal = before.rsplit(|c: char| c.is_whitespace() || c == '(').next()?;
+        let digits = ordinal.trim_end_matches(['ο', 'o']);
+        (digits.parse().ok()?, before.len() - ordinal.len())
+    };
+
+    let base = title[..base_end].trim_end_matches(|c: char| c.is_whitespace() || matches!(c, '-' | '–' | ':' | ',' | '(' | '['));
+    if base.is_empty() {
+        return None;
+    }
+    Some((base.to_string(), part_number))
+}
+
+/// Artwork URLs of an item, by the Jellyfin image type they are served as
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrls {
+    pub primary: Option<String>,
+    pub backdrop: Option<String>,
+    pub thumb: Option<String>,
+    pub logo: Option<String>,
+}
+
+impl ImageUrls {
+    /// Sorts tile images by role. The poster is the primary image, falling back to the
+    /// photo, which also serves as the thumb. Unknown roles are ignored.
+    pub fn from_tile_images(images: &[ertflix_client::TileImage]) -> Self {
+        let url = |role: &str| {
+            images
+                .iter()
+                .find(|image| image.role.eq_ignore_ascii_case(role) && !image.url.is_empty())
+                .map(|image| image.url.clone())
+        };
+        Self {
+            primary: url("poster").or_else(|| url("photo")),
+            backdrop: url("background"),
+            thumb: url("photo"),
+            logo: url("logo"),
         }
     }
+
+    /// The URL of a Jellyfin image type such as `Primary` or `Backdrop`
+    pub fn get(&self, image_type: &str) -> Option<&str> {
+        let url = match image_type.to_ascii_lowercase().as_str() {
+            "primary" => &self.primary,
+            "backdrop" => &self.backdrop,
+            "thumb" => &self.thumb,
+            "logo" => &self.logo,
+            _ => return None,
+        };
+        url.as_deref()
+    }
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct TVShow {
     pub id: String,
+    pub codename: String,
     pub title: String,
     pub seasons: Vec<Season>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub images: ImageUrls,
+    /// Codenames of the sections the show was listed in, when source tagging is enabled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trailer_url: Option<String>,
 }
 
 impl From<ertflix_client::Tile> for TVShow {
     fn from(tile: ertflix_client::Tile) -> Self {
         Self {
             id: tile.id,
-            title: tile.title.unwrap_or(tile.codename),
+            title: tile.title.unwrap_or_else(|| tile.codename.clone()),
+            description: tile.description.unwrap_or_default(),
+            images: ImageUrls::from_tile_images(&tile.images),
+            codename: tile.codename,
             seasons: Vec::new(), // Placeholder for an empty list of seasons
+            tags: Vec::new(),
+            trailer_url: tile.trailer_url,
         }
     }
 }
 
+impl TVShow {
+    /// Drops episodes the filter considers clips, before they are numbered
+    pub fn drop_clips(&mut self, filter: &config::FullEpisodesConfig) {
+        for season in &mut self.seasons {
+            season
+                .episodes
+                .retain(|episode| !filter.is_clip_episode(&episode.title, episode.duration));
+        }
+    }
+
+    /// Fills in season and episode numbers ERTFLIX left out, so clients can order them.
+    /// Seasons without a number are numbered by position. Episodes use the number in
+    /// their title (e.g. "Επεισόδιο 5") if present, otherwise their position in the season.
+    pub fn normalize_numbering(&mut self) {
+        for (index, season) in self.seasons.iter_mut().enumerate() {
+            if season.season_number == 0 {
+                season.season_number = index as u32 + 1;
+            }
+            for (index, episode) in season.episodes.iter_mut().enumerate() {
+                if episode.episode_number.is_none() {
+                    episode.episode_number =
+                        Some(episode_number_from_title(&episode.title).unwrap_or(index as u32 + 1));
+                }
+            }
+            season.episodes.sort_by_key(|episode| episode.episode_number);
+        }
+    }
+}
+
+/// Extracts the episode number from titles such as "Επεισόδιο 5" or "ΕΠΕΙΣΟΔΙΟ 12"
+fn episode_number_from_title(title: &str) -> Option<u32> {
+    number_after(title, &["επεισόδιο", "επεισοδιο", "episode"])
+}
+
+/// Extracts the season number from titles such as "Κύκλος 2 - Επεισόδιο 5"
+pub fn season_number_from_title(title: &str) -> Option<u32> {
+    number_after(title, &["κύκλος", "κυκλος", "season"])
+}
+
+/// The number following the first of `markers` found in `title`, ignoring case
+fn number_after(title: &str, markers: &[&str]) -> Option<u32> {
+    let title = title.to_lowercase();
+    markers.iter().find_map(|marker| {
+        let (_, rest) = title.split_once(marker)?;
+        let digits: String = rest
+            .trim_start_matches(|c: char| c.is_whitespace() || c == '.' || c == ':')
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        digits.parse().ok()
+    })
+}
+
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct Season {
     pub season_number: u32,
@@ -50,10 +261,52 @@ pub struct Episode {
     pub id: String,
     pub title: String,
     pub duration: u32, // duration in seconds
+    #[serde(default)]
+    pub episode_number: Option<u32>, // not always provided by ERTFLIX
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct Collection {
     pub name: String,
     pub id: String,
+    pub tile_ids: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn greek_titles_are_parsed_into_episode_and_season_numbers() {
+        assert_eq!(episode_number_from_title("Επεισόδιο 5"), Some(5));
+        assert_eq!(episode_number_from_title("ΕΠΕΙΣΟΔΙΟ 12"), Some(12));
+        assert_eq!(episode_number_from_title("Το Νησί - Επεισόδιο: 3"), Some(3));
+        assert_eq!(episode_number_from_title("Το Νησί"), None);
+
+        assert_eq!(season_number_from_title("Κύκλος 2 - Επεισόδιο 5"), Some(2));
+        assert_eq!(season_number_from_title("ΚΥΚΛΟΣ 3"), Some(3));
+        assert_eq!(season_number_from_title("Επεισόδιο 5"), None);
+    }
+
+    #[test]
+    fn episodes_without_a_number_in_their_title_are_numbered_by_position() {
+        let episode = |title: &str| Episode { id: title.to_string(), title: title.to_string(), ..Default::default() };
+        let mut show = TVShow {
+            seasons: vec![
+                Season { season_number: 0, episodes: vec![episode("Επεισόδιο 2"), episode("Επεισόδιο 1")] },
+                Season { season_number: 0, episodes: vec![episode("Η αρχή"), episode("Το τέλος")] },
+            ],
+            ..Default::default()
+        };
+
+        show.normalize_numbering();
+
+        let seasons: Vec<u32> = show.seasons.iter().map(|season| season.season_number).collect();
+        assert_eq!(seasons, [1, 2]);
+        let episodes = |season: &Season| -> Vec<(String, Option<u32>)> {
+            season.episodes.iter().map(|episode| (episode.title.clone(), episode.episode_number)).collect()
+        };
+        assert_eq!(episodes(&show.seasons[0]), [("Επεισόδιο 1".to_string(), Some(1)), ("Επεισόδιο 2".to_string(), Some(2))]);
+        assert_eq!(episodes(&show.seasons[1]), [("Η αρχή".to_string(), Some(1)), ("Το τέλος".to_string(), Some(2))]);
+    }
 }
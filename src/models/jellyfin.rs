@@ -1,29 +1,177 @@
+use crate::clock::{Clock, SystemClock};
 use crate::{config, models::ertflix};
-use chrono::offset::Local;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
 #[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct Movie {
     pub id: String,
+    #[serde(rename = "Name")]
     pub title: String,
-    pub year: i32,
+    pub server_id: String,
+    #[serde(rename = "Type")]
+    pub item_type: String,
+    pub media_type: String,
+    pub is_folder: bool,
+    /// Left out rather than sent as 0 when ERTFLIX doesn't know the year
+    #[serde(rename = "ProductionYear", skip_serializing_if = "Option::is_none")]
+    pub year: Option<i32>,
+    #[serde(rename = "Genres")]
     pub genre: Vec<String>,
+    pub genre_items: Vec<NameGuidPair>,
     pub overview: String,
     pub poster_url: String,
+    pub primary_image_aspect_ratio: f64,
+    pub provider_ids: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    pub image_tags: ImageTags,
+    pub backdrop_image_tags: Vec<String>,
+    pub user_data: UserData,
+    pub can_download: bool,
+    pub can_delete: bool,
+    pub remote_trailers: Vec<MediaUrl>,
+    /// One source per part of a movie ERTFLIX splits across tiles; empty for single-part movies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub media_sources: Vec<MediaSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part_count: Option<usize>,
+}
+
+/// A name with the stable id Jellyfin pairs it with, as in `GenreItems`
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NameGuidPair {
+    pub name: String,
+    pub id: String,
+}
+
+impl NameGuidPair {
+    pub fn genre(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            id: item_guid(&format!("genre:{}", name.to_lowercase())),
+        }
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MediaSource {
+    pub id: String,
+    pub name: String,
+    pub protocol: String,
+    pub is_remote: bool,
+    /// Where the media is played from, for sources resolved to a stream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_stream_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    pub supports_direct_play: bool,
+    pub supports_direct_stream: bool,
+    pub supports_transcoding: bool,
+}
+
+impl MediaSource {
+    /// A source streamed straight from `url`. Jellyfin has no HLS protocol, so manifests are
+    /// sent as `Http` sources with an `hls` container, which clients play directly.
+    pub fn stream(item_id: &ItemId, name: String, url: String) -> Self {
+        let container = if url.contains(".m3u8") {
+            "hls".to_string()
+        } else {
+            url.split(['?', '#'])
+                .next()
+                .and_then(|path| path.rsplit_once('.'))
+                .map(|(_, extension)| extension.to_ascii_lowercase())
+                .unwrap_or_else(|| "hls".to_string())
+        };
+        Self {
+            id: item_id.to_string(),
+            name,
+            protocol: "Http".into(),
+            is_remote: true,
+            path: Some(url.clone()),
+            direct_stream_url: Some(url),
+            container: Some(container),
+            supports_direct_play: true,
+            supports_direct_stream: true,
+            supports_transcoding: false,
+        }
+    }
+}
+
+impl From<&ertflix::MoviePart> for MediaSource {
+    fn from(part: &ertflix::MoviePart) -> Self {
+        Self {
+            id: ItemId::from_ertflix(&part.id).into(),
+            name: part.title.clone(),
+            protocol: "Http".into(),
+            is_remote: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Answer to `/Items/{itemId}/PlaybackInfo`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlaybackInfoResponse {
+    pub media_sources: Vec<MediaSource>,
+    pub play_session_id: String,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct TVShow {
     pub id: String,
+    #[serde(rename = "Name")]
     pub title: String,
+    pub server_id: String,
+    #[serde(rename = "Type")]
+    pub item_type: String,
+    pub media_type: String,
+    pub is_folder: bool,
     pub seasons: Vec<Season>,
+    pub child_count: i32,
     pub overview: String,
     pub poster_url: String,
+    pub primary_image_aspect_ratio: f64,
+    pub provider_ids: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    pub image_tags: ImageTags,
+    pub backdrop_image_tags: Vec<String>,
+    pub user_data: UserData,
+    pub can_download: bool,
+    pub can_delete: bool,
+    pub remote_trailers: Vec<MediaUrl>,
+}
+
+/// A link to media hosted elsewhere, such as a trailer
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MediaUrl {
+    pub url: String,
+    pub name: String,
+}
+
+/// `RemoteTrailers` of an item: its trailer if ERTFLIX has one, none otherwise
+pub fn remote_trailers(trailer_url: Option<String>, title: &str) -> Vec<MediaUrl> {
+    trailer_url
+        .filter(|url| !url.is_empty())
+        .map(|url| MediaUrl { url, name: format!("{} - Trailer", title) })
+        .into_iter()
+        .collect()
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct Season {
     pub id: String,
     pub title: String,
@@ -32,6 +180,7 @@ pub struct Season {
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct Episode {
     pub id: String,
     pub title: String,
@@ -41,6 +190,74 @@ pub struct Episode {
     pub duration: i32,
 }
 
+impl QueryResult<Collection> {
+    /// Validator of the whole list, derived from the etag of each collection in order, so it
+    /// changes whenever a collection is added, removed, reordered or changes its contents
+    pub fn etag(&self) -> String {
+        let etags = self.items.iter().map(|collection| collection.etag.as_str()).collect::<Vec<_>>().join("\n");
+        format!("\"{}\"", Uuid::new_v5(&Uuid::NAMESPACE_URL, etags.as_bytes()).simple())
+    }
+}
+
+/// An episode listed as an item of its own, as recursive item queries return them
+#[derive(Default, Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EpisodeItem {
+    pub id: String,
+    pub name: String,
+    pub server_id: String,
+    #[serde(rename = "Type")]
+    pub item_type: String,
+    pub media_type: String,
+    pub is_folder: bool,
+    pub series_id: String,
+    pub series_name: String,
+    /// ERTFLIX has no artwork of episodes of their own, so clients show the series' poster
+    pub image_tags: ImageTags,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series_primary_image_tag: Option<String>,
+    pub season_id: String,
+    /// Episode number within the season
+    pub index_number: i32,
+    /// Season number
+    pub parent_index_number: i32,
+    pub run_time_ticks: i64,
+    pub user_data: UserData,
+}
+
+impl TVShow {
+    /// The episodes of every season of the series, as items of their own
+    pub fn episode_items(&self) -> Vec<EpisodeItem> {
+        self.seasons
+            .iter()
+            .flat_map(|season| {
+                season.episodes.iter().map(move |episode| {
+                    // Episode ids were mapped to GUIDs when the season was converted
+                    let item_id = ItemId(episode.id.clone());
+                    EpisodeItem {
+                        user_data: UserData::for_item(&item_id),
+                        id: item_id.into(),
+                        name: episode.title.clone(),
+                        server_id: self.server_id.clone(),
+                        item_type: "Episode".into(),
+                        media_type: "Video".into(),
+                        is_folder: false,
+                        series_id: self.id.clone(),
+                        series_name: self.title.clone(),
+                        image_tags: ImageTags::from_urls(&ertflix::ImageUrls::default()),
+                        series_primary_image_tag: self.image_tags.primary.clone(),
+                        season_id: season.id.clone(),
+                        index_number: episode.episode_number,
+                        parent_index_number: episode.season_number,
+                        // Jellyfin counts run time in ticks of 100 nanoseconds
+                        run_time_ticks: episode.duration as i64 * 10_000_000,
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Collection {
@@ -59,7 +276,7 @@ pub struct Collection {
     pub taglines: Vec<String>,
     pub genres: Vec<String>,
     pub play_access: String,
-    pub remote_trailers: Vec<String>,
+    pub remote_trailers: Vec<MediaUrl>,
     pub provider_ids: HashMap<String, String>,
     pub is_folder: bool,
     pub parent_id: String,
@@ -85,33 +302,115 @@ pub struct Collection {
     pub lock_data: bool,
 }
 
-#[derive(Serialize)]
+/// The `{Items, TotalRecordCount, StartIndex}` envelope Jellyfin wraps item lists in
+#[derive(Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
-pub struct Collections {
-    items: Vec<Collection>,
-    total_record_count: usize,
-    start_index: i32,
+pub struct QueryResult<T> {
+    pub items: Vec<T>,
+    pub total_record_count: usize,
+    pub start_index: usize,
 }
 
-impl Collections {
-    pub fn new(items: Vec<Collection>) -> Self {
+impl<T> QueryResult<T> {
+    /// All of `items`, as a single page
+    pub fn new(items: Vec<T>) -> Self {
         Self {
             total_record_count: items.len(),
             items,
             start_index: 0,
         }
     }
+
+    /// The same page with every item converted by `f`
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> QueryResult<U> {
+        QueryResult {
+            items: self.items.into_iter().map(f).collect(),
+            total_record_count: self.total_record_count,
+            start_index: self.start_index,
+        }
+    }
+
+    /// The page of `items` starting at `start_index` and holding at most `limit` of them.
+    /// `TotalRecordCount` still counts every item, so clients can request the next pages.
+    pub fn paged(items: Vec<T>, start_index: usize, limit: Option<usize>) -> Self {
+        let total_record_count = items.len();
+        let page = items.into_iter().skip(start_index).take(limit.unwrap_or(usize::MAX)).collect();
+        Self { total_record_count, start_index, ..Self::new(page) }
+    }
+}
+
+impl<T> QueryResult<T> {
+    /// The same JSON as serializing the envelope of the converted items, written one item at
+    /// a time: the opening of the envelope, then each item, then `TotalRecordCount` and
+    /// `StartIndex`. Each item is passed through `convert` only when its chunk is written, so
+    /// large pages are sent as they are written instead of being held converted or as one string.
+    pub fn into_json_chunks<U: Serialize>(
+        self,
+        mut convert: impl FnMut(T) -> U,
+    ) -> impl Iterator<Item = Result<Vec<u8>, serde_json::Error>> {
+        let tail = format!(r#"],"TotalRecordCount":{},"StartIndex":{}}}"#, self.total_record_count, self.start_index);
+        let items = self.items.into_iter().enumerate().map(move |(index, item)| {
+            let mut chunk = if index == 0 { Vec::new() } else { vec![b','] };
+            serde_json::to_writer(&mut chunk, &convert(item))?;
+            Ok(chunk)
+        });
+        std::iter::once(Ok(br#"{"Items":["#.to_vec()))
+            .chain(items)
+            .chain(std::iter::once(Ok(tail.into_bytes())))
+    }
+}
+
+/// One match of a `/Search/Hints` query
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SearchHint {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "Type")]
+    pub item_type: String,
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
+pub struct SearchHintResult {
+    search_hints: Vec<SearchHint>,
+    total_record_count: usize,
+}
+
+impl SearchHintResult {
+    pub fn new(search_hints: Vec<SearchHint>) -> Self {
+        Self {
+            total_record_count: search_hints.len(),
+            search_hints,
+        }
+    }
+}
+
+impl From<QueryResult<SearchHint>> for SearchHintResult {
+    fn from(page: QueryResult<SearchHint>) -> Self {
+        Self { search_hints: page.items, total_record_count: page.total_record_count }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct UserData {
-    playback_position_ticks: i64,
-    play_count: i32,
-    is_favorite: bool,
-    played: bool,
-    key: String,
-    item_id: String,
+    pub playback_position_ticks: i64,
+    pub play_count: i32,
+    pub is_favorite: bool,
+    pub played: bool,
+    pub key: String,
+    pub item_id: String,
+}
+
+impl UserData {
+    pub fn for_item(item_id: &ItemId) -> Self {
+        Self {
+            key: item_id.to_string(),
+            item_id: item_id.to_string(),
+            ..Default::default()
+        }
+    }
 }
 
 impl Default for UserData {
@@ -127,20 +426,48 @@ impl Default for UserData {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ImageTags {
-    primary: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    primary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumb: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logo: Option<String>,
 }
 
 impl Default for ImageTags {
     fn default() -> Self {
         Self {
-            primary: "00000000000000000000000000000000".into(),
+            primary: Some("00000000000000000000000000000000".into()),
+            thumb: None,
+            logo: None,
         }
     }
 }
 
+impl ImageTags {
+    /// Tags for the image types the item has artwork for; backdrops are tagged separately
+    pub fn from_urls(images: &ertflix::ImageUrls) -> Self {
+        Self {
+            primary: images.primary.as_deref().map(image_tag),
+            thumb: images.thumb.as_deref().map(image_tag),
+            logo: images.logo.as_deref().map(image_tag),
+        }
+    }
+}
+
+/// Derives an image tag from the image URL, so clients refetch artwork only when it changes
+pub fn image_tag(url: &str) -> String {
+    item_guid(url)
+}
+
+/// `BackdropImageTags` of an item: one tag if it has a backdrop, none otherwise
+pub fn backdrop_image_tags(images: &ertflix::ImageUrls) -> Vec<String> {
+    images.backdrop.as_deref().map(image_tag).into_iter().collect()
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ImageBlurHashes {
@@ -155,25 +482,166 @@ impl Default for ImageBlurHashes {
     }
 }
 
+/// Expects the season to have gone through `ertflix::TVShow::normalize_numbering`.
+/// The season id is left for the caller to assign, since it depends on the show.
+impl From<ertflix::Season> for Season {
+    fn from(season: ertflix::Season) -> Self {
+        let season_number = season.season_number as i32;
+        let episodes = season
+            .episodes
+            .into_iter()
+            .map(|episode| Episode {
+                episode_number: episode.episode_number.unwrap_or_default() as i32,
+                season_number,
+                id: item_guid(&episode.id),
+                title: episode.title,
+                overview: String::new(),
+                duration: episode.duration as i32,
+            })
+            .collect();
+
+        Self {
+            id: String::new(),
+            title: format!("Season {}", season_number),
+            season_number,
+            episodes,
+        }
+    }
+}
+
+/// Key under which the original Ertflix id or codename is exposed in `ProviderIds`
+pub const ERTFLIX_PROVIDER: &str = "Ertflix";
+
+/// Maps an Ertflix id or codename to the 32 hex character GUID Jellyfin clients require.
+/// The mapping is deterministic so the same Ertflix entity always gets the same id.
+pub fn item_guid(ertflix_id: &str) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, ertflix_id.as_bytes())
+        .simple()
+        .to_string()
+}
+
+/// Id of a Jellyfin item as clients see it: a 32 hex character GUID.
+/// Ertflix ids and codenames are mapped into this space with `ItemId::from_ertflix`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ItemId(String);
+
+impl ItemId {
+    pub fn from_ertflix(ertflix_id: &str) -> Self {
+        Self(item_guid(ertflix_id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Accepts the 32 hex character form as well as the hyphenated GUID form some clients send
+impl FromStr for ItemId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::try_parse(s)
+            .map(|uuid| Self(uuid.simple().to_string()))
+            .map_err(|_| format!("invalid item id: {}", s))
+    }
+}
+
+impl TryFrom<String> for ItemId {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ItemId> for String {
+    fn from(id: ItemId) -> Self {
+        id.0
+    }
+}
+
+impl fmt::Display for ItemId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Provider ids pointing back at the Ertflix entity an item was built from
+pub fn ertflix_provider_ids(ertflix_id: &str) -> HashMap<String, String> {
+    HashMap::from([(ERTFLIX_PROVIDER.to_string(), ertflix_id.to_string())])
+}
+
+/// Stable id of the synthesized "Continue Watching" view
+pub const CONTINUE_WATCHING_ID: &str = "2f6a3c1d9b8e4f0aa1c5d7e9b3f2a4c6";
+
+/// Fields of a collection clients rely on, kept in slim collections even when empty
+const REQUIRED_COLLECTION_FIELDS: &[&str] = &[
+    "Name", "ServerId", "Id", "Etag", "Type", "IsFolder", "CollectionType", "ChildCount", "UserData", "ImageTags",
+];
+
 impl Collection {
+    /// The collection with its empty and default fields left out, except the required ones
+    pub fn slim(&self) -> serde_json::Value {
+        let mut value = serde_json::json!(self);
+        if let serde_json::Value::Object(fields) = &mut value {
+            fields.retain(|name, field| {
+                let is_empty = match field {
+                    serde_json::Value::Null => true,
+                    serde_json::Value::Bool(flag) => !*flag,
+                    serde_json::Value::Number(number) => number.as_f64() == Some(0.0),
+                    serde_json::Value::String(text) => text.is_empty(),
+                    serde_json::Value::Array(items) => items.is_empty(),
+                    serde_json::Value::Object(map) => map.is_empty(),
+                };
+                !is_empty || REQUIRED_COLLECTION_FIELDS.contains(&name.as_str())
+            });
+        }
+        value
+    }
+
+    /// Synthesized view listing the items the user has started but not finished
+    pub fn continue_watching(item_ids: Vec<ItemId>) -> Self {
+        let child_count = item_ids.len() as i32;
+        let item_ids = item_ids.into_iter().map(String::from).collect();
+        let mut collection = Self::from(ertflix::Collection {
+            name: "Continue Watching".into(),
+            id: CONTINUE_WATCHING_ID.into(),
+            tile_ids: item_ids,
+        });
+        // Synthesized locally, so it keeps its fixed id and has no Ertflix counterpart
+        collection.id = CONTINUE_WATCHING_ID.into();
+        collection.provider_ids.clear();
+        collection.child_count = child_count;
+        collection.sort_name = "continue watching".into();
+        collection
+    }
+
     pub fn from(ertflix_collection: ertflix::Collection) -> Self {
-        let etag = Uuid::new_v5(
-            &Uuid::NAMESPACE_URL,
-            &[
-                ertflix_collection.id.as_bytes(),
-                ertflix_collection.name.as_bytes(),
-            ]
-            .concat(),
-        )
-        .to_string();
+        Self::with_clock(ertflix_collection, &SystemClock)
+    }
+
+    /// The collection of an ERTFLIX section, created at the time `clock` reports
+    pub fn with_clock(ertflix_collection: ertflix::Collection, clock: &dyn Clock) -> Self {
+        // The child tile ids are part of the etag so it changes whenever the section contents do.
+        // Each part is length-prefixed, so different parts never hash alike.
+        let child_count = ertflix_collection.tile_ids.len() as i32;
+        let mut encoded = Vec::new();
+        let parts = [&ertflix_collection.id, &ertflix_collection.name].into_iter().chain(&ertflix_collection.tile_ids);
+        for part in parts {
+            encoded.extend((part.len() as u64).to_le_bytes());
+            encoded.extend(part.as_bytes());
+        }
+        let etag = Uuid::new_v5(&Uuid::NAMESPACE_URL, &encoded).to_string();
         Self {
             name: ertflix_collection.name,
-            server_id: config::SERVER_ID.into(),
-            id: ertflix_collection.id,
+            server_id: config::identity().server_id.clone(),
+            id: item_guid(&ertflix_collection.id),
+            provider_ids: ertflix_provider_ids(&ertflix_collection.id),
             etag,
-            date_created: Local::now().to_string(),
-            can_delete: true,
-            can_download: true,
+            date_created: clock.jellyfin_timestamp(),
+            can_delete: false,
+            can_download: false,
             sort_name: "movies".into(),
             external_urls: vec![],
             path: "".into(),
@@ -183,7 +651,6 @@ impl Collection {
             genres: vec![],
             play_access: "Full".into(),
             remote_trailers: vec![],
-            provider_ids: Default::default(),
             is_folder: true,
             parent_id: "".into(),
             item_type: "CollectionFolder".into(),
@@ -192,7 +659,7 @@ impl Collection {
             genre_items: vec![],
             local_trailer_count: 0,
             user_data: UserData::default(),
-            child_count: 0,
+            child_count,
             special_feature_count: 0,
             display_preferences_id: "".into(),
             tags: vec![],
@@ -208,3 +675,224 @@ impl Collection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::jellyfin_server::{AuthenticationResponse, EmbyAuthorizationHeader, SessionInfo, SystemInfo};
+    use crate::clock::FixedClock;
+    use serde_json::Value;
+    use std::str::FromStr;
+
+    /// Keys whose values are free-form maps rather than Jellyfin DTOs
+    const FREE_FORM_KEYS: &[&str] = &["ProviderIds", "ImageBlurHashes"];
+
+    fn is_pascal_case(key: &str) -> bool {
+        key.chars().next().is_some_and(|c| c.is_ascii_uppercase()) && !key.contains('_')
+    }
+
+    fn non_pascal_case_keys(value: &Value, path: &str, found: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    let key_path = format!("{}.{}", path, key);
+                    if !is_pascal_case(key) {
+                        found.push(key_path.clone());
+                    }
+                    if !FREE_FORM_KEYS.contains(&key.as_str()) {
+                        non_pascal_case_keys(value, &key_path, found);
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    non_pascal_case_keys(item, &format!("{}[{}]", path, index), found);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn show_with_episode() -> TVShow {
+        TVShow {
+            seasons: vec![Season {
+                episodes: vec![Episode::default()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn response_types_serialize_with_pascal_case_keys() {
+        let header = EmbyAuthorizationHeader::from_str(
+            r#"MediaBrowser Client="web", Device="Firefox", DeviceId="abc", Version="10.10.0""#,
+        )
+        .unwrap();
+
+        let cases: Vec<(&str, Value)> = vec![
+            ("Movie", serde_json::to_value(Movie::default()).unwrap()),
+            ("Movies", serde_json::to_value(QueryResult::new(vec![Movie::default()])).unwrap()),
+            ("TVShow", serde_json::to_value(show_with_episode()).unwrap()),
+            ("Collection", serde_json::to_value(Collection::continue_watching(vec![])).unwrap()),
+            (
+                "Collections",
+                serde_json::to_value(QueryResult::new(vec![Collection::continue_watching(vec![])])).unwrap(),
+            ),
+            ("UserData", serde_json::to_value(UserData::for_item(&ItemId::from_ertflix("item"))).unwrap()),
+            ("AuthenticationResponse", serde_json::to_value(AuthenticationResponse::with_clock(header, &FixedClock::at("2024-05-01T12:30:00Z"))).unwrap()),
+            ("SessionInfo", serde_json::to_value(SessionInfo::default()).unwrap()),
+            ("SystemInfo", serde_json::to_value(SystemInfo::default()).unwrap()),
+        ];
+
+        for (name, value) in cases {
+            let mut found = Vec::new();
+            non_pascal_case_keys(&value, name, &mut found);
+            assert!(found.is_empty(), "{} has non PascalCase keys: {:?}", name, found);
+        }
+    }
+
+    #[test]
+    fn query_result_counts_every_item_but_keeps_only_the_page() {
+        let page = QueryResult::paged(vec![1, 2, 3, 4, 5], 1, Some(2));
+
+        assert_eq!(page.items, vec![2, 3]);
+        assert_eq!(page.total_record_count, 5);
+        assert_eq!(page.start_index, 1);
+    }
+
+    #[test]
+    fn query_result_past_the_end_is_an_empty_page() {
+        let page = QueryResult::paged(vec![1, 2], 5, None);
+
+        assert!(page.items.is_empty());
+        assert_eq!(page.total_record_count, 2);
+    }
+
+    #[test]
+    fn query_result_serializes_as_the_jellyfin_envelope() {
+        let value = serde_json::to_value(QueryResult::new(vec!["a"])).unwrap();
+
+        assert_eq!(value, serde_json::json!({ "Items": ["a"], "TotalRecordCount": 1, "StartIndex": 0 }));
+    }
+
+    #[test]
+    fn query_result_chunks_join_into_the_serialized_envelope() {
+        for items in [vec![], vec!["a"], vec!["a", "b\"c", "d"]] {
+            let page = QueryResult::paged(items.clone(), 1, Some(5));
+            let chunks: Vec<Vec<u8>> = QueryResult::paged(items, 1, Some(5)).into_json_chunks(|item| item).collect::<Result<_, _>>().unwrap();
+
+            assert_eq!(String::from_utf8(chunks.concat()).unwrap(), serde_json::to_string(&page).unwrap());
+        }
+    }
+
+    #[test]
+    fn query_result_chunks_convert_each_item_as_it_is_written() {
+        let converted = std::cell::Cell::new(0);
+        let mut chunks = QueryResult::new(vec![1, 2, 3]).into_json_chunks(|item| {
+            converted.set(converted.get() + 1);
+            item * 10
+        });
+
+        assert_eq!(chunks.next().unwrap().unwrap(), br#"{"Items":["#);
+        assert_eq!(converted.get(), 0);
+        assert_eq!(chunks.next().unwrap().unwrap(), b"10");
+        assert_eq!(converted.get(), 1);
+        assert_eq!(String::from_utf8(chunks.map(Result::unwrap).collect::<Vec<_>>().concat()).unwrap(), r#",20,30],"TotalRecordCount":3,"StartIndex":0}"#);
+        assert_eq!(converted.get(), 3);
+    }
+
+    #[test]
+    fn collections_are_dated_by_the_clock_and_tagged_by_their_contents() {
+        let clock = FixedClock::at("2024-05-01T12:30:00.5Z");
+        let collection = Collection::with_clock(
+            ertflix::Collection { name: "Ταινίες".into(), id: "1".into(), tile_ids: vec!["a".into(), "b".into()] },
+            &clock,
+        );
+
+        assert_eq!(collection.date_created, "2024-05-01T12:30:00.5000000Z");
+        assert_eq!(collection.etag, "6ad73f10-d856-5938-bc28-668a6b1fc86e");
+        assert_eq!(QueryResult::new(vec![collection]).etag(), r#""c531ddb1863857b799e9eed89e74c855""#);
+    }
+
+    #[test]
+    fn sign_in_is_dated_by_the_clock() {
+        let header = EmbyAuthorizationHeader::from_str(r#"MediaBrowser Client="Infuse", Device="iPhone", DeviceId="abc", Version="8.0""#).unwrap();
+        let response = AuthenticationResponse::with_clock(header, &FixedClock::at("2024-05-01T12:30:00Z"));
+        let response = serde_json::to_value(response).unwrap();
+
+        for date in [
+            &response["User"]["LastLoginDate"],
+            &response["User"]["LastActivityDate"],
+            &response["SessionInfo"]["LastActivityDate"],
+            &response["SessionInfo"]["LastPlaybackCheckIn"],
+        ] {
+            assert_eq!(date, "2024-05-01T12:30:00.0000000Z");
+        }
+        assert_eq!(response["SessionInfo"]["DeviceName"], "iPhone");
+    }
+
+    #[test]
+    fn adding_a_tile_changes_the_collection_etag() {
+        let collection = |tile_ids: &[&str]| {
+            Collection::from(ertflix::Collection {
+                name: "Ταινίες".into(),
+                id: "1".into(),
+                tile_ids: tile_ids.iter().map(|id| id.to_string()).collect(),
+            })
+        };
+        let before = collection(&["a", "b"]);
+        let after = collection(&["a", "b", "c"]);
+
+        assert_eq!(before.etag, collection(&["a", "b"]).etag);
+        assert_ne!(before.etag, after.etag);
+        assert_ne!(QueryResult::new(vec![before]).etag(), QueryResult::new(vec![after]).etag());
+    }
+
+    #[test]
+    fn collections_whose_parts_only_join_alike_have_different_etags() {
+        let collection = |id: &str, name: &str, tile_ids: &[&str]| {
+            Collection::from(ertflix::Collection {
+                name: name.into(),
+                id: id.into(),
+                tile_ids: tile_ids.iter().map(|id| id.to_string()).collect(),
+            })
+        };
+
+        assert_ne!(collection("12", "3a", &["b"]).etag, collection("123", "a", &["b"]).etag);
+        assert_ne!(collection("1", "a", &["bc"]).etag, collection("1", "ab", &["c"]).etag);
+        assert_ne!(collection("1", "a", &["b,c"]).etag, collection("1", "a", &["b", "c"]).etag);
+    }
+
+    #[test]
+    fn adding_a_collection_changes_the_collections_etag() {
+        let collection = |id: &str| {
+            Collection::from(ertflix::Collection { name: id.into(), id: id.into(), tile_ids: vec!["tile".into()] })
+        };
+        let one = QueryResult::new(vec![collection("1")]);
+        let same = QueryResult::new(vec![collection("1")]);
+        let two = QueryResult::new(vec![collection("1"), collection("2")]);
+
+        assert_eq!(one.etag(), same.etag());
+        assert_ne!(one.etag(), two.etag());
+    }
+
+    #[test]
+    fn item_ids_accept_both_guid_forms_and_reject_anything_else() {
+        let id = ItemId::from_ertflix("movie-1");
+        assert_eq!(id.as_str().len(), 32);
+        assert!(id.as_str().chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(id, ItemId::from_ertflix("movie-1"));
+        assert_ne!(id, ItemId::from_ertflix("movie-2"));
+
+        let hyphenated = Uuid::try_parse(id.as_str()).unwrap().hyphenated().to_string();
+        assert_eq!(ItemId::from_str(&hyphenated).unwrap(), id);
+        assert_eq!(ItemId::from_str(&id.as_str().to_uppercase()).unwrap(), id);
+        assert_eq!(serde_json::from_value::<ItemId>(serde_json::json!(id.to_string())).unwrap(), id);
+
+        for malformed in ["", "movie-1", "not-a-guid", &id.as_str()[1..], &format!("{}0", id)] {
+            assert!(ItemId::from_str(malformed).is_err(), "{:?}", malformed);
+            assert!(serde_json::from_value::<ItemId>(serde_json::json!(malformed)).is_err(), "{:?}", malformed);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::config::{LoggingConfig, SharedConfig};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Logger;
+use actix_web::{web, Error, HttpMessage};
+use tracing::{debug, Span};
+use tracing_actix_web::{DefaultRootSpanBuilder, RequestId, RootSpanBuilder};
+
+const REDACTED: &str = "[REDACTED]";
+const REQUEST_LINE_LABEL: &str = "redacted_request_line";
+
+/// Builds the request logging middleware from the configured format, logging the
+/// request line with sensitive query parameters redacted and never logging the
+/// values of sensitive headers.
+pub fn request_logger(config: &LoggingConfig) -> Logger {
+    debug!("Configuring request logger with format: {}", config.request_format);
+
+    let mut format = config
+        .request_format
+        .replace("%r", &format!("%{{{}}}xi", REQUEST_LINE_LABEL));
+    for header in &config.redacted_headers {
+        format = replace_header_placeholder(&format, header);
+    }
+
+    let redacted_params = config.redacted_query_params.clone();
+    Logger::new(&format).custom_request_replace(REQUEST_LINE_LABEL, move |req: &ServiceRequest| {
+        format!("{} {} {:?}", req.method(), redacted_target(req, &redacted_params), req.version())
+    })
+}
+
+/// The root span of `TracingLogger`, carrying the request target with the sensitive query
+/// parameters of the current logging configuration redacted, as the request line is. Every
+/// event logged while handling the request is printed with its fields.
+pub struct RedactedRootSpan;
+
+impl RootSpanBuilder for RedactedRootSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let redacted_params = match request.app_data::<web::Data<SharedConfig>>() {
+            Some(shared) => shared.current().logging.redacted_query_params.clone(),
+            None => LoggingConfig::default().redacted_query_params,
+        };
+        let request_id = request.extensions().get::<RequestId>().copied();
+        tracing::info_span!(
+            "HTTP request",
+            http.method = %request.method(),
+            http.route = request.match_pattern().unwrap_or_default(),
+            http.target = %redacted_target(request, &redacted_params),
+            http.status_code = tracing::field::Empty,
+            otel.status_code = tracing::field::Empty,
+            request_id = request_id.map(tracing::field::display),
+            exception.message = tracing::field::Empty,
+            exception.details = tracing::field::Empty,
+        )
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+/// The path and query of the request, with the values of `redacted_params` replaced
+fn redacted_target(req: &ServiceRequest, redacted_params: &[String]) -> String {
+    match req.query_string() {
+        "" => req.path().to_string(),
+        query => format!("{}?{}", req.path(), redact_query(query, redacted_params)),
+    }
+}
+
+/// Replaces the values of the given query parameters, matched case-insensitively
+fn redact_query(query: &str, redacted_params: &[String]) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if redacted_params.iter().any(|param| param.eq_ignore_ascii_case(name)) => {
+                format!("{}={}", name, REDACTED)
+            }
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Replaces `%{Header}i` placeholders for the given header, matched case-insensitively
+fn replace_header_placeholder(format: &str, header: &str) -> String {
+    let mut result = String::with_capacity(format.len());
+    let mut rest = format;
+    while let Some(start) = rest.find("%{") {
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        match placeholder.find("}i") {
+            Some(end) if placeholder[2..end].eq_ignore_ascii_case(header) => {
+                result.push_str(REDACTED);
+                rest = &placeholder[end + 2..];
+            }
+            _ => {
+                result.push_str("%{");
+                rest = &placeholder[2..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use actix_web::{test, web, App, HttpResponse};
+    use super::*;
+
+    /// Collects what a `fmt` subscriber writes
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn tokens_in_the_query_string_and_headers_are_redacted_from_the_log_line_and_spans() {
+        // The logger writes through `log`, which reaches tracing subscribers through the bridge
+        let _ = tracing_log::LogTracer::init();
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt().with_writer(move || writer.clone()).with_ansi(false).finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let config = LoggingConfig {
+            request_format: r#""%r" %s "%{X-Emby-Token}i""#.into(),
+            ..LoggingConfig::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .wrap(request_logger(&config))
+                .wrap(tracing_actix_web::TracingLogger::<RedactedRootSpan>::new())
+                .route(
+                    "/Items",
+                    web::get().to(|| async {
+                        tracing::info!("listing items");
+                        HttpResponse::Ok().finish()
+                    }),
+                ),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri("/Items?ParentId=1&api_key=query-secret")
+            .insert_header(("X-Emby-Token", "header-secret"))
+            .to_request();
+        test::call_and_read_body(&app, request).await;
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let line = output.lines().find(|line| line.contains("GET /Items")).expect(&output);
+        assert!(line.contains(r#""GET /Items?ParentId=1&api_key=[REDACTED] HTTP/1.1" 200 "[REDACTED]""#), "{}", line);
+        assert!(!line.contains("secret"), "{}", line);
+        // Events logged while handling the request carry the fields of its root span
+        let event = output.lines().find(|line| line.contains("listing items")).expect(&output);
+        assert!(event.contains("http.target=/Items?ParentId=1&api_key=[REDACTED]"), "{}", event);
+        assert!(!output.contains("secret"), "{}", output);
+    }
+}
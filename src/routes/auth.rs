@@ -0,0 +1,77 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, ResponseError};
+use tracing::{trace, warn};
+use crate::config::{self, SharedConfig};
+use crate::error::AppError;
+use crate::services::sessions::{Session, SessionStore};
+
+/// Paths answered without a session token: what a client needs before it has signed in,
+/// and what probes and scrapers request
+const PUBLIC_PATHS: [&str; 6] = ["/", "/System/Info/Public", "/Users/AuthenticateByName", "/metrics", "/health", "/ready"];
+
+fn is_public(path: &str) -> bool {
+    // Players load artwork without sending a token, as they do from Jellyfin
+    PUBLIC_PATHS.contains(&path) || (path.starts_with("/Items/") && path.contains("/Images/"))
+}
+
+/// Extracts the access token a client sent, from the dedicated token headers, the
+/// `Token` field of the Emby authorization header, or the `api_key` query parameter
+pub fn request_token(req: &actix_web::HttpRequest) -> Option<String> {
+    let header = |name: &str| req.headers().get(name).and_then(|h| h.to_str().ok());
+
+    if let Some(token) = header("x-emby-token").or_else(|| header("x-mediabrowser-token")) {
+        return Some(token.to_string());
+    }
+
+    let authorization = header("x-emby-authorization").or_else(|| header("authorization"));
+    if let Some(token) = authorization.and_then(|value| {
+        value.split(',').find_map(|part| {
+            let (key, value) = part.trim().split_once('=')?;
+            (key.trim() == "Token").then(|| value.trim().trim_matches('"').to_string())
+        })
+    }) {
+        return Some(token);
+    }
+
+    web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.get("api_key").cloned())
+}
+
+/// The active session whose token the request carries
+pub fn request_session(req: &actix_web::HttpRequest) -> Option<Session> {
+    let sessions = req.app_data::<web::Data<SessionStore>>()?;
+    sessions.find_by_token(&request_token(req)?)
+}
+
+/// Whether the request carries the configured admin token
+pub fn has_admin_token(req: &actix_web::HttpRequest, config: &config::Config) -> bool {
+    config.admin.token.as_deref().is_some_and(|token| {
+        req.headers()
+            .get("x-admin-token")
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|provided| config::constant_time_eq(provided.as_bytes(), token.as_bytes()))
+    })
+}
+
+/// Rejects requests to protected routes that carry no token, or one no active session
+/// was issued, which includes the tokens of sessions an admin ended. Requests with the
+/// admin token pass, and nothing is checked when `auth.allow_all` keeps the server open.
+pub async fn require_session_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let config = req.app_data::<web::Data<SharedConfig>>().map(|shared| shared.current());
+    let open = config.as_ref().is_some_and(|config| config.auth.allow_all);
+    let admin = config.as_ref().is_some_and(|config| has_admin_token(req.request(), config));
+
+    if !open && !admin && !is_public(req.path()) && request_session(req.request()).is_none() {
+        warn!("Rejecting request to {} without a known session token", req.path());
+        return Ok(req.into_response(AppError::Unauthorized.error_response()));
+    }
+
+    trace!("Request to {} is authorized", req.path());
+    next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+}
@@ -1,74 +1,951 @@
 use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Instant;
 use crate::api::ertflix_client::ErtflixClient;
 use crate::api::jellyfin_server;
-use crate::models::jellyfin;
-use crate::services::media_service::MediaService;
-use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use crate::config::{self, Config, SortSpec};
+use crate::error::AppError;
+use crate::models::{ertflix, jellyfin};
+use crate::models::jellyfin::ItemId;
+use crate::services::idempotency::{Claim, IdempotencyStore, StoredResponse};
+use crate::services::image_proxy::ImageProxy;
+use crate::services::cache::CacheStatus;
+use crate::services::media_service::{ItemKind, MediaService};
+use crate::services::sessions::{Session, SessionStore};
+use crate::services::stream_tracker::StreamTracker;
+use crate::services::supervisor::Supervisor;
+use crate::services::user_data::{UserDataStore, UserItemData};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, Responder, ResponseError, web};
 use tracing::{debug, error, info, trace, warn, instrument};
+use uuid::Uuid;
 use crate::api::jellyfin_server::EmbyAuthorizationHeader;
+use super::{auth, AuthenticationBody, CurrentConfig};
 
-pub async fn handle_get_collections<T: ErtflixClient>(media_service: web::Data<MediaService<T>>) -> impl Responder {
-    info!("Handling request for collections");
+/// Query parameters of the listing and search endpoints. Every field is optional, so an
+/// endpoint reads the ones that apply to it and a new filter is one more field here.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ItemQuery {
+    pub parent_id: Option<String>,
+    pub is_favorite: Option<bool>,
+    pub is_played: Option<bool>,
+    /// Comma separated list of optional fields to include, e.g. `Seasons`
+    pub fields: Option<String>,
+    pub sort_by: Option<String>,
+    pub sort_order: Option<String>,
+    /// Comma separated item types to list, e.g. `Movie,Series`; all types when absent
+    pub include_item_types: Option<String>,
+    pub start_index: Option<usize>,
+    /// Most items in a page, clamped to `library.max_page_size`
+    pub limit: Option<usize>,
+    /// Text to match, as `/Search/Hints` sends it
+    #[serde(alias = "searchTerm")]
+    pub search_term: Option<String>,
+    /// Also list the episodes of the series in scope, not only the series themselves
+    pub recursive: Option<bool>,
+    /// ERTFLIX section to list instead of the configured one, when `admin.section_overrides` allows it
+    #[serde(rename = "section")]
+    pub section: Option<String>,
+}
+
+impl ItemQuery {
+    /// Whether the optional field was requested through `Fields`
+    fn includes_field(&self, field: &str) -> bool {
+        self.fields
+            .as_deref()
+            .is_some_and(|fields| fields.split(',').any(|f| f.trim().eq_ignore_ascii_case(field)))
+    }
+
+    /// Whether items of the Jellyfin type, `Movie` or `Series`, are to be listed
+    fn includes_type(&self, item_type: &str) -> bool {
+        self.include_item_types
+            .as_deref()
+            .is_none_or(|types| types.split(',').any(|t| t.trim().eq_ignore_ascii_case(item_type)))
+    }
+
+    /// The section `?section=` asks for, refused unless section overrides are enabled
+    fn section_override(&self, config: &Config) -> Result<Option<&str>, AppError> {
+        match self.section.as_deref() {
+            Some(_) if !config.admin.section_overrides => {
+                Err(AppError::BadRequest("section overrides are disabled".to_string()))
+            }
+            section => Ok(section),
+        }
+    }
+
+    fn is_recursive(&self) -> bool {
+        self.recursive.unwrap_or(false)
+    }
+
+    /// The page of `items` that `StartIndex` and `Limit` ask for, never holding more than
+    /// `library.max_page_size` items
+    fn page<I>(&self, items: Vec<I>, config: &Config) -> jellyfin::QueryResult<I> {
+        let max_page_size = config.library.max_page_size;
+        if self.limit.is_some_and(|limit| limit > max_page_size) {
+            debug!("Clamping Limit {:?} to {}", self.limit, max_page_size);
+        }
+        let limit = self.limit.unwrap_or(max_page_size).min(max_page_size);
+        jellyfin::QueryResult::paged(items, self.start_index.unwrap_or(0), Some(limit))
+    }
+
+    /// The trimmed `SearchTerm`, empty when absent
+    fn search_term(&self) -> &str {
+        self.search_term.as_deref().unwrap_or_default().trim()
+    }
+
+    /// Series are listed without their seasons unless `Fields=Seasons` asks for them, even
+    /// when the seasons were fetched to list the episodes
+    fn shape_seasons<S>(&self, seasons: &mut Vec<S>) {
+        if !self.includes_field("Seasons") {
+            seasons.clear();
+        }
+    }
+
+    /// The sort the client asked for, or the configured default of the listing
+    fn sort_spec(&self, config: &Config, collection_key: &str) -> SortSpec {
+        match &self.sort_by {
+            Some(sort_by) => SortSpec::new(sort_by, self.sort_order.as_deref().unwrap_or("Ascending")),
+            None => config.sorting.for_collection(collection_key).clone(),
+        }
+    }
+
+    /// Whether an item with the given user state passes the query
+    fn matches(&self, data: &UserItemData) -> bool {
+        self.is_favorite.is_none_or(|is_favorite| data.is_favorite == is_favorite)
+            && self.is_played.is_none_or(|is_played| data.played == is_played)
+    }
+}
+
+pub async fn handle_get_collections<T: ErtflixClient + 'static>(
+    req: HttpRequest,
+    media_service: web::Data<MediaService<T>>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+    query: web::Query<ItemQuery>,
+) -> impl Responder {
+    list_collections(&req, &config::identity().user_id, &media_service, &config, &user_data_store, &query).await
+}
+
+/// `/Users/{user_id}/Views`, the user-scoped form of `/UserViews`
+pub async fn handle_get_user_views<T: ErtflixClient + 'static>(
+    req: HttpRequest,
+    path: web::Path<String>,
+    media_service: web::Data<MediaService<T>>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+    query: web::Query<ItemQuery>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    list_collections(&req, &user_id, &media_service, &config, &user_data_store, &query).await
+}
+
+/// The library's collections, followed by the Continue Watching view of `user_id`, paged
+/// as `query` asks. Answers 304 Not Modified when the client already has the page's
+/// current ETag.
+async fn list_collections<T: ErtflixClient + 'static>(
+    req: &HttpRequest,
+    user_id: &str,
+    media_service: &MediaService<T>,
+    config: &Config,
+    user_data_store: &UserDataStore,
+    query: &ItemQuery,
+) -> HttpResponse {
+    info!("Handling request for collections of user {}", user_id);
     trace!("Starting collections retrieval process");
 
-    match media_service.get_collections().await {
-        Ok(collections_vec) => {
+    match media_service.get_collections_with_status().await {
+        Ok((mut collections_vec, cache_status)) => {
             info!("Successfully retrieved {} collections", collections_vec.len());
+            // Views keep the ERTFLIX order unless the client asks for another
+            if query.sort_by.is_some() {
+                sort_items(&mut collections_vec, &query.sort_spec(config, ""), |collection| SortKeys {
+                    name: collection.name.clone(),
+                    ..Default::default()
+                });
+            }
+            collections_vec.push(jellyfin::Collection::continue_watching(
+                user_data_store.in_progress(user_id),
+            ));
+            let aspect_ratio = config.images.collections_aspect_ratio();
+            for collection in &mut collections_vec {
+                collection.primary_image_aspect_ratio = aspect_ratio;
+                collection.can_download = config.library.can_download;
+                collection.can_delete = config.library.can_delete;
+            }
             debug!("Creating Jellyfin collections response");
-            let response = jellyfin::Collections::new(collections_vec);
+            let response = query.page(collections_vec, config);
+            let etag = response.etag();
+            if if_none_match(req, &etag) {
+                debug!("Collections unchanged since {}, answering 304", etag);
+                return HttpResponse::NotModified()
+                    .insert_header((actix_web::http::header::ETAG, etag))
+                    .finish();
+            }
             trace!("Collections response prepared");
-            HttpResponse::Ok().json(response)
+            let mut ok = HttpResponse::Ok();
+            ok.insert_header(("X-Cache", cache_status.header_value()))
+                .insert_header((actix_web::http::header::ETAG, etag));
+            if config.library.slim_collections {
+                return ok.json(response.map(|collection| collection.slim()));
+            }
+            ok.json(response)
         },
         Err(e) => {
             error!("Failed to retrieve collections: {}", e);
-            warn!("Returning internal server error for collections request");
-            HttpResponse::InternalServerError().finish()
+            warn!("Returning an error response for collections request");
+            AppError::from_upstream(e.as_ref()).error_response()
         },
     }
 }
 
-pub async fn handle_get_tv_shows<T: ErtflixClient>(media_service: web::Data<MediaService<T>>) -> impl Responder {
+/// Whether the request's `If-None-Match` lists `etag`, compared weakly, or is `*`
+fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(actix_web::http::header::IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag)
+        })
+}
+
+pub async fn handle_get_tv_shows<T: ErtflixClient + 'static>(
+    media_service: web::Data<MediaService<T>>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+    query: web::Query<ItemQuery>,
+) -> impl Responder {
     info!("Handling request for TV shows");
     trace!("Starting TV shows retrieval process");
 
-    match media_service.get_tv_shows().await {
-        Ok(tv_shows) => {
+    let tv_shows = match query.section_override(&config) {
+        Ok(Some(section)) => {
+            debug!("Listing section {} as TV shows", section);
+            media_service.get_tv_shows_in_section(section).await.map(|shows| (shows, CacheStatus::Miss))
+        }
+        Ok(None) => media_service.get_tv_shows_with_status().await,
+        Err(e) => return e.error_response(),
+    };
+    match tv_shows {
+        Ok((tv_shows, _)) if tv_shows.is_empty() && config.library.unavailable_when_empty => {
+            error!("ERTFLIX lists no TV shows, reporting the library as unavailable");
+            AppError::Unavailable("library unavailable: ERTFLIX lists no TV shows".into()).error_response()
+        },
+        Ok((tv_shows, cache_status)) => {
+            let aspect_ratio = config.images.tv_shows_aspect_ratio();
+            let (tv_shows, user_data): (Vec<ertflix::TVShow>, Vec<UserItemData>) = tv_shows
+                .into_iter()
+                .filter_map(|show| {
+                    let data = user_data_store.get(&config::identity().user_id, &ItemId::from_ertflix(&show.id));
+                    query.matches(&data).then_some((show, data))
+                })
+                .unzip();
+            // Seasons take a request per series, so they are only fetched when asked for
+            let tv_shows = if query.includes_field("Seasons") { media_service.with_seasons(tv_shows).await } else { tv_shows };
+            let mut tv_shows: Vec<jellyfin::TVShow> = tv_shows
+                .into_iter()
+                .zip(user_data)
+                .map(|(show, data)| {
+                    let item_id = ItemId::from_ertflix(&show.id);
+                    let mut show = media_service.convert_to_jellyfin_tv_show(show);
+                    show.user_data = user_data_response(&item_id, &data);
+                    show.primary_image_aspect_ratio = aspect_ratio;
+                    show.can_download = config.library.can_download;
+                    show.can_delete = config.library.can_delete;
+                    show
+                })
+                .collect();
+            sort_items(&mut tv_shows, &query.sort_spec(&config, "tv_shows"), |show| SortKeys {
+                name: show.title.clone(),
+                ..Default::default()
+            });
             info!("Successfully retrieved {} TV shows", tv_shows.len());
             debug!("Preparing TV shows JSON response");
             trace!("TV shows response ready");
-            HttpResponse::Ok().json(tv_shows)
+            HttpResponse::Ok()
+                .insert_header(("X-Cache", cache_status.header_value()))
+                .json(tv_shows)
         },
         Err(e) => {
             error!("Failed to retrieve TV shows: {}", e);
-            warn!("Returning internal server error for TV shows request");
-            HttpResponse::InternalServerError().finish()
+            warn!("Returning an error response for TV shows request");
+            AppError::from_upstream(e.as_ref()).error_response()
         },
     }
 }
 
-pub async fn handle_get_movies<T: ErtflixClient>(media_service: web::Data<MediaService<T>>) -> impl Responder {
+pub async fn handle_get_movies<T: ErtflixClient + 'static>(
+    media_service: web::Data<MediaService<T>>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+    query: web::Query<ItemQuery>,
+) -> impl Responder {
     info!("Handling request for movies");
     trace!("Starting movies retrieval process");
 
-    match media_service.get_movies().await {
-        Ok(movies) => {
+    let movies = match query.section_override(&config) {
+        Ok(Some(section)) => {
+            debug!("Listing section {} as movies", section);
+            media_service.get_movies_in_section(section).await.map(|movies| (movies, CacheStatus::Miss))
+        }
+        Ok(None) => media_service.get_movies_with_status().await,
+        Err(e) => return e.error_response(),
+    };
+    match movies {
+        Ok((movies, _)) if movies.is_empty() && config.library.unavailable_when_empty => {
+            error!("ERTFLIX lists no movies, reporting the library as unavailable");
+            AppError::Unavailable("library unavailable: ERTFLIX lists no movies".into()).error_response()
+        },
+        Ok((movies, cache_status)) => {
+            let user_id = &config::identity().user_id;
+            let mut movies: Vec<(ertflix::Movie, UserItemData)> = movies
+                .into_iter()
+                .filter_map(|movie| {
+                    let data = user_data_store.get(user_id, &ItemId::from_ertflix(&movie.id));
+                    query.matches(&data).then_some((movie, data))
+                })
+                .collect();
+            sort_items(&mut movies, &query.sort_spec(&config, "movies"), |(movie, _)| SortKeys {
+                name: movie.title.clone(),
+                year: movie.year,
+                ..Default::default()
+            });
             info!("Successfully retrieved {} movies", movies.len());
-            debug!("Preparing movies JSON response");
-            trace!("Movies response ready");
-            HttpResponse::Ok().json(movies)
+            debug!("Streaming movies JSON response");
+
+            let aspect_ratio = config.images.movies_aspect_ratio();
+            let (can_download, can_delete) = (config.library.can_download, config.library.can_delete);
+            let media_service = media_service.clone();
+            // Only the movies of the page are converted, each one as the response reaches it,
+            // so the largest libraries start arriving before the whole page is serialized
+            let chunks = query
+                .page(movies, &config)
+                .into_json_chunks(move |(movie, data)| {
+                    let item_id = ItemId::from_ertflix(&movie.id);
+                    let mut movie = media_service.convert_to_jellyfin_movie(movie);
+                    movie.user_data = user_data_response(&item_id, &data);
+                    movie.primary_image_aspect_ratio = aspect_ratio;
+                    movie.can_download = can_download;
+                    movie.can_delete = can_delete;
+                    movie
+                })
+                .map(|chunk| chunk.map(web::Bytes::from));
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .insert_header(("X-Cache", cache_status.header_value()))
+                .streaming(futures::stream::iter(chunks))
         },
         Err(e) => {
             error!("Failed to retrieve movies: {}", e);
-            warn!("Returning internal server error for movies request");
-            HttpResponse::InternalServerError().finish()
+            warn!("Returning an error response for movies request");
+            AppError::from_upstream(e.as_ref()).error_response()
         },
     }
 }
 
-pub async fn handle_authentication(req: HttpRequest) -> impl Responder {
+/// Path of the item image endpoints; an image index, if present, is ignored
+#[derive(Debug, serde::Deserialize)]
+pub struct ImagePath {
+    item_id: ItemId,
+    image_type: String,
+}
+
+/// `/Items/{itemId}/Images/{imageType}`: the ERTFLIX artwork of that type, proxied
+/// or, when image proxying is off, as a redirect to the CDN
+pub async fn handle_get_item_image<T: ErtflixClient + 'static>(
+    path: web::Path<ImagePath>,
+    media_service: web::Data<MediaService<T>>,
+    image_proxy: web::Data<ImageProxy>,
+    config: CurrentConfig,
+) -> impl Responder {
+    debug!("Handling {} image request for item {}", path.image_type, path.item_id);
+    let url = match media_service.get_image_url(&path.item_id, &path.image_type).await {
+        Ok(url) => url,
+        Err(e) => {
+            warn!("No {} image for item {}: {}", path.image_type, path.item_id, e);
+            return AppError::from_upstream(e.as_ref()).error_response();
+        }
+    };
+
+    if !config.images.proxy {
+        trace!("Redirecting {} image of {} to {}", path.image_type, path.item_id, url);
+        return HttpResponse::Found()
+            .insert_header((actix_web::http::header::LOCATION, url))
+            .finish();
+    }
+
+    match image_proxy.fetch(&url).await {
+        Ok(image) => HttpResponse::Ok()
+            .content_type(image.content_type)
+            .body(image.bytes),
+        Err(e) => {
+            warn!("Failed to fetch {} image of {}: {}", path.image_type, path.item_id, e);
+            AppError::from_upstream(&e).error_response()
+        }
+    }
+}
+
+/// A field listings can be sorted by, as `SortBy` names it
+#[derive(Debug, Clone, Copy)]
+enum SortField {
+    Name,
+    ProductionYear,
+    DateCreated,
+}
+
+impl SortField {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "sortname" | "name" => Some(SortField::Name),
+            "productionyear" | "premieredate" => Some(SortField::ProductionYear),
+            "datecreated" => Some(SortField::DateCreated),
+            _ => None,
+        }
+    }
+}
+
+/// What an item is sorted by; a missing key sorts the item last in either order
+#[derive(Debug, Default)]
+struct SortKeys {
+    name: String,
+    year: Option<u32>,
+    /// ISO 8601, so it orders as a string
+    date_created: Option<String>,
+}
+
+/// Orders items by the `SortBy` fields we know, the first deciding and the next breaking
+/// ties. Names compare case-insensitively. The sort is stable, so ties left over and
+/// unknown fields keep the ERTFLIX order.
+fn sort_items<I>(items: &mut [I], spec: &SortSpec, keys: impl Fn(&I) -> SortKeys) {
+    let fields: Vec<SortField> = spec
+        .sort_by
+        .split(',')
+        .filter_map(|name| {
+            let field = SortField::parse(name);
+            if field.is_none() {
+                trace!("Not sorting by unsupported field {}", name);
+            }
+            field
+        })
+        .collect();
+
+    // Sorting stably by the last field first leaves the first one deciding
+    for field in fields.iter().rev() {
+        match field {
+            SortField::Name => sort_missing_last(items, spec.is_descending(), |item| {
+                Some(keys(item).name.to_lowercase()).filter(|name| !name.is_empty())
+            }),
+            SortField::ProductionYear => sort_missing_last(items, spec.is_descending(), |item| keys(item).year),
+            SortField::DateCreated => sort_missing_last(items, spec.is_descending(), |item| keys(item).date_created),
+        }
+    }
+}
+
+fn sort_missing_last<I, K: Ord>(items: &mut [I], descending: bool, key: impl Fn(&I) -> Option<K>) {
+    if descending {
+        items.sort_by_cached_key(|item| {
+            let key = key(item);
+            (key.is_none(), key.map(std::cmp::Reverse))
+        });
+    } else {
+        items.sort_by_cached_key(|item| {
+            let key = key(item);
+            (key.is_none(), key)
+        });
+    }
+}
+
+/// Builds the Jellyfin `UserData` payload for an item from the stored user state
+fn user_data_response(item_id: &ItemId, data: &UserItemData) -> jellyfin::UserData {
+    let mut user_data = jellyfin::UserData::for_item(item_id);
+    user_data.is_favorite = data.is_favorite;
+    user_data.played = data.played;
+    user_data.play_count = data.play_count;
+    user_data.playback_position_ticks = data.playback_position_ticks;
+    user_data
+}
+
+/// `/Users/{userId}/Items`, which carries the user's favorites and played state, so only
+/// for sessions that may see them
+pub async fn handle_get_user_items<T: ErtflixClient + 'static>(
+    req: HttpRequest,
+    path: web::Path<String>,
+    media_service: web::Data<MediaService<T>>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+    query: web::Query<ItemQuery>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    if !may_access_user_data(&req, &config, &user_id) {
+        warn!("Rejecting items request of user {} without a session of that user", user_id);
+        return AppError::Unauthorized.error_response();
+    }
+    list_items(&user_id, &media_service, &config, &user_data_store, &query).await
+}
+
+/// `/Items`, used by clients that do not scope requests to a user; served as the default user
+pub async fn handle_get_items<T: ErtflixClient + 'static>(
+    media_service: web::Data<MediaService<T>>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+    query: web::Query<ItemQuery>,
+) -> impl Responder {
+    list_items(&config::identity().user_id, &media_service, &config, &user_data_store, &query).await
+}
+
+async fn list_items<T: ErtflixClient + 'static>(
+    user_id: &str,
+    media_service: &MediaService<T>,
+    config: &Config,
+    user_data_store: &UserDataStore,
+    query: &ItemQuery,
+) -> HttpResponse {
+    info!("Handling items request for user {} with {:?}", user_id, query);
+
+    // Items are scoped to the collection named by ParentId, or the Continue Watching view
+    let parent_id = match query.parent_id.as_deref().map(ItemId::from_str).transpose() {
+        Ok(parent_id) => parent_id,
+        Err(e) => return AppError::BadRequest(e).error_response(),
+    };
+    let in_parent: Option<Vec<ItemId>> = match &parent_id {
+        None => None,
+        Some(parent_id) if parent_id.as_str() == jellyfin::CONTINUE_WATCHING_ID => Some(user_data_store.in_progress(user_id)),
+        Some(parent_id) => match media_service.get_collection_tile_ids(parent_id).await {
+            Ok(Some(tile_ids)) => Some(tile_ids.iter().map(|id| ItemId::from_ertflix(id)).collect()),
+            Ok(None) => {
+                debug!("No collection with id {}", parent_id);
+                return AppError::NotFound(parent_id.to_string()).error_response();
+            }
+            Err(e) => {
+                error!("Failed to retrieve collection {}: {}", parent_id, e);
+                return AppError::from_upstream(e.as_ref()).error_response();
+            }
+        },
+    };
+
+    let movies = if query.includes_type("Movie") { media_service.get_movies().await } else { Ok(Vec::new()) };
+    let list_series = query.includes_type("Series");
+    let list_episodes = query.is_recursive() && query.includes_type("Episode");
+    let tv_shows = if list_series || list_episodes { media_service.get_tv_shows().await } else { Ok(Vec::new()) };
+    let (movies, tv_shows) = match (movies, tv_shows) {
+        (Ok(movies), Ok(tv_shows)) => (movies, tv_shows),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to retrieve items: {}", e);
+            warn!("Returning an error response for items request");
+            return AppError::from_upstream(e.as_ref()).error_response();
+        }
+    };
+
+    let in_scope = |item_id: &ItemId| in_parent.as_ref().is_none_or(|ids| ids.contains(item_id));
+    let tv_shows: Vec<ertflix::TVShow> = tv_shows.into_iter().filter(|show| in_scope(&ItemId::from_ertflix(&show.id))).collect();
+    // Seasons take a request per series, so they are only fetched for the episodes or when asked for
+    let tv_shows = if list_episodes || (list_series && query.includes_field("Seasons")) {
+        media_service.with_seasons(tv_shows).await
+    } else {
+        tv_shows
+    };
+    let user_data = |item_id: &ItemId| {
+        let data = user_data_store.get(user_id, item_id);
+        query.matches(&data).then(|| user_data_response(item_id, &data))
+    };
+
+    let mut items: Vec<serde_json::Value> = movies
+        .into_iter()
+        .filter_map(|movie| {
+            let item_id = ItemId::from_ertflix(&movie.id);
+            if !in_scope(&item_id) {
+                return None;
+            }
+            let user_data = user_data(&item_id)?;
+            let mut movie = media_service.convert_to_jellyfin_movie(movie);
+            movie.user_data = user_data;
+            movie.primary_image_aspect_ratio = config.images.movies_aspect_ratio();
+            movie.can_download = config.library.can_download;
+            movie.can_delete = config.library.can_delete;
+            Some(serde_json::json!(movie))
+        })
+        .collect();
+
+    // Episodes are in scope through their series, and filtered on their own user data
+    for show in tv_shows {
+        let item_id = ItemId::from_ertflix(&show.id);
+        let mut show = media_service.convert_to_jellyfin_tv_show(show);
+        if list_episodes {
+            items.extend(show.episode_items().into_iter().filter_map(|mut episode| {
+                let episode_id = ItemId::from_str(&episode.id).ok()?;
+                episode.user_data = user_data(&episode_id)?;
+                Some(serde_json::json!(episode))
+            }));
+        }
+        if list_series && let Some(user_data) = user_data(&item_id) {
+            query.shape_seasons(&mut show.seasons);
+            show.user_data = user_data;
+            show.primary_image_aspect_ratio = config.images.tv_shows_aspect_ratio();
+            show.can_download = config.library.can_download;
+            show.can_delete = config.library.can_delete;
+            items.push(serde_json::json!(show));
+        }
+    }
+
+    let continue_watching = parent_id.as_ref().is_some_and(|id| id.as_str() == jellyfin::CONTINUE_WATCHING_ID);
+    if continue_watching
+        && query.sort_by.is_none()
+        && let Some(in_progress) = &in_parent
+    {
+        // Continue Watching keeps the most recently watched items first unless asked otherwise
+        items.sort_by_cached_key(|item| {
+            in_progress.iter().position(|id| Some(id.as_str()) == item["Id"].as_str()).unwrap_or(usize::MAX)
+        });
+    } else {
+        let sort_spec = query.sort_spec(config, query.parent_id.as_deref().unwrap_or_default());
+        sort_items(&mut items, &sort_spec, |item| SortKeys {
+            name: item["Name"].as_str().unwrap_or_default().to_string(),
+            year: item["ProductionYear"].as_u64().map(|year| year as u32),
+            date_created: item["DateCreated"].as_str().map(str::to_string),
+        });
+    }
+
+    let page = query.page(items, config);
+    info!("Returning {} of {} items for user {}", page.items.len(), page.total_record_count, user_id);
+    HttpResponse::Ok().json(page)
+}
+
+/// Query of the item detail endpoints; `Type` narrows the lookup to movies or series
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ItemDetailQuery {
+    #[serde(rename = "Type")]
+    item_type: Option<String>,
+}
+
+/// `/Items/{item_id}`, the detail of one item for the default user
+pub async fn handle_get_item<T: ErtflixClient + 'static>(
+    path: web::Path<ItemId>,
+    media_service: web::Data<MediaService<T>>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+    query: web::Query<ItemDetailQuery>,
+) -> impl Responder {
+    let item_id = path.into_inner();
+    get_item(&config::identity().user_id, &item_id, &media_service, &config, &user_data_store, &query).await
+}
+
+/// `/Users/{user_id}/Items/{item_id}`
+pub async fn handle_get_user_item<T: ErtflixClient + 'static>(
+    path: web::Path<(String, ItemId)>,
+    media_service: web::Data<MediaService<T>>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+    query: web::Query<ItemDetailQuery>,
+) -> impl Responder {
+    let (user_id, item_id) = path.into_inner();
+    get_item(&user_id, &item_id, &media_service, &config, &user_data_store, &query).await
+}
+
+async fn get_item<T: ErtflixClient + 'static>(
+    user_id: &str,
+    item_id: &ItemId,
+    media_service: &MediaService<T>,
+    config: &Config,
+    user_data_store: &UserDataStore,
+    query: &ItemDetailQuery,
+) -> HttpResponse {
+    info!("Handling detail request for item {} of user {}", item_id, user_id);
+
+    let kind = match query.item_type.as_deref() {
+        None => None,
+        Some("Movie") => Some(ItemKind::Movie),
+        Some("Series") => Some(ItemKind::Series),
+        Some(other) => return AppError::BadRequest(format!("unknown item type: {}", other)).error_response(),
+    };
+    let (kind, ertflix_id) = match media_service.find_item(item_id, kind).await {
+        Ok(found) => found,
+        Err(e) => {
+            warn!("Could not find item {}: {}", item_id, e);
+            return AppError::from_upstream(e.as_ref()).error_response();
+        }
+    };
+
+    let data = user_data_store.get(user_id, item_id);
+    let item = match kind {
+        ItemKind::Movie => media_service.get_movie_by_id(&ertflix_id).await.map(|movie| {
+            let mut movie = media_service.convert_to_jellyfin_movie(movie);
+            movie.user_data = user_data_response(item_id, &data);
+            movie.primary_image_aspect_ratio = config.images.movies_aspect_ratio();
+            movie.can_download = config.library.can_download;
+            movie.can_delete = config.library.can_delete;
+            serde_json::json!(movie)
+        }),
+        ItemKind::Series => media_service.get_show_by_id(&ertflix_id).await.map(|show| {
+            let mut show = media_service.convert_to_jellyfin_tv_show(show);
+            show.user_data = user_data_response(item_id, &data);
+            show.primary_image_aspect_ratio = config.images.tv_shows_aspect_ratio();
+            show.can_download = config.library.can_download;
+            show.can_delete = config.library.can_delete;
+            serde_json::json!(show)
+        }),
+    };
+
+    match item {
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => {
+            error!("Failed to retrieve item {}: {}", item_id, e);
+            AppError::from_upstream(e.as_ref()).error_response()
+        }
+    }
+}
+
+/// `/Items/{item_id}/PlaybackInfo`: the ERTFLIX stream of the item as its only media source
+pub async fn handle_get_playback_info<T: ErtflixClient + 'static>(
+    path: web::Path<ItemId>,
+    media_service: web::Data<MediaService<T>>,
+    stream_tracker: web::Data<StreamTracker>,
+) -> impl Responder {
+    let item_id = path.into_inner();
+    info!("Handling playback info request for item {}", item_id);
+
+    match media_service.get_media_source(&item_id).await {
+        Ok(media_source) => {
+            // Clients report the play session back when playback stops, which ends the stream
+            let play_session_id = Uuid::new_v4().simple().to_string();
+            stream_tracker.start(&play_session_id);
+            HttpResponse::Ok().json(jellyfin::PlaybackInfoResponse { media_sources: vec![media_source], play_session_id })
+        }
+        Err(e) => {
+            error!("Failed to resolve the stream of item {}: {}", item_id, e);
+            AppError::from_upstream(e.as_ref()).error_response()
+        }
+    }
+}
+
+pub async fn handle_mark_favorite(
+    req: HttpRequest,
+    path: web::Path<(String, ItemId)>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+) -> impl Responder {
+    let (user_id, item_id) = path.into_inner();
+    info!("Marking item {} as favorite for user {}", item_id, user_id);
+
+    if !may_access_user_data(&req, &config, &user_id) {
+        warn!("Rejecting marking a favorite of user {} without a session of that user", user_id);
+        return AppError::Unauthorized.error_response();
+    }
+
+    let data = user_data_store.set_favorite(&user_id, &item_id, true);
+    HttpResponse::Ok().json(user_data_response(&item_id, &data))
+}
+
+pub async fn handle_unmark_favorite(
+    req: HttpRequest,
+    path: web::Path<(String, ItemId)>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+) -> impl Responder {
+    let (user_id, item_id) = path.into_inner();
+    info!("Removing item {} from favorites for user {}", item_id, user_id);
+
+    if !may_access_user_data(&req, &config, &user_id) {
+        warn!("Rejecting clearing a favorite of user {} without a session of that user", user_id);
+        return AppError::Unauthorized.error_response();
+    }
+
+    let data = user_data_store.set_favorite(&user_id, &item_id, false);
+    HttpResponse::Ok().json(user_data_response(&item_id, &data))
+}
+
+pub async fn handle_mark_played(
+    req: HttpRequest,
+    path: web::Path<(String, ItemId)>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+) -> impl Responder {
+    let (user_id, item_id) = path.into_inner();
+    info!("Marking item {} as played for user {}", item_id, user_id);
+
+    if !may_access_user_data(&req, &config, &user_id) {
+        warn!("Rejecting marking items played for user {} without a session of that user", user_id);
+        return AppError::Unauthorized.error_response();
+    }
+
+    let data = user_data_store.set_played(&user_id, &item_id, true);
+    HttpResponse::Ok().json(user_data_response(&item_id, &data))
+}
+
+pub async fn handle_mark_unplayed(
+    req: HttpRequest,
+    path: web::Path<(String, ItemId)>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+) -> impl Responder {
+    let (user_id, item_id) = path.into_inner();
+    info!("Marking item {} as unplayed for user {}", item_id, user_id);
+
+    if !may_access_user_data(&req, &config, &user_id) {
+        warn!("Rejecting marking items unplayed for user {} without a session of that user", user_id);
+        return AppError::Unauthorized.error_response();
+    }
+
+    let data = user_data_store.set_played(&user_id, &item_id, false);
+    HttpResponse::Ok().json(user_data_response(&item_id, &data))
+}
+
+/// Records the reported playback position so partially watched items can be resumed, for
+/// the user of the reporting session or the configured user when there is no session
+fn record_playback_position(
+    req: &HttpRequest,
+    progress: &jellyfin_server::PlaybackProgressInfo,
+    user_data_store: &UserDataStore,
+) {
+    if let Some(position_ticks) = progress.position_ticks {
+        let user_id = auth::request_session(req)
+            .map(|session| session.user_id)
+            .unwrap_or_else(|| config::identity().user_id.clone());
+        user_data_store.set_playback_position(&user_id, &progress.item_id, position_ticks);
+    }
+}
+
+pub async fn handle_playback_start(
+    progress: web::Json<jellyfin_server::PlaybackProgressInfo>,
+    stream_tracker: web::Data<StreamTracker>,
+) -> impl Responder {
+    info!("Playback started for item {}", progress.item_id);
+    stream_tracker.start(progress.stream_key());
+    HttpResponse::NoContent().finish()
+}
+
+pub async fn handle_playback_progress(
+    req: HttpRequest,
+    progress: web::Json<jellyfin_server::PlaybackProgressInfo>,
+    user_data_store: web::Data<UserDataStore>,
+    stream_tracker: web::Data<StreamTracker>,
+) -> impl Responder {
+    debug!("Playback progress for item {}: {:?}", progress.item_id, progress.position_ticks);
+    stream_tracker.touch(progress.stream_key());
+    record_playback_position(&req, &progress, &user_data_store);
+    HttpResponse::NoContent().finish()
+}
+
+pub async fn handle_playback_stopped(
+    req: HttpRequest,
+    progress: web::Json<jellyfin_server::PlaybackProgressInfo>,
+    user_data_store: web::Data<UserDataStore>,
+    stream_tracker: web::Data<StreamTracker>,
+) -> impl Responder {
+    info!("Playback stopped for item {} at {:?}", progress.item_id, progress.position_ticks);
+    stream_tracker.stop(progress.stream_key());
+    record_playback_position(&req, &progress, &user_data_store);
+    HttpResponse::NoContent().finish()
+}
+
+/// Prometheus text exposition of the adapter's gauges and counters
+pub async fn handle_get_metrics<T: ErtflixClient + 'static>(
+    stream_tracker: web::Data<StreamTracker>,
+    media_service: web::Data<MediaService<T>>,
+) -> impl Responder {
+    trace!("Rendering metrics");
+    let rate_limits = media_service.upstream_rate_limits();
+    let failures = media_service.upstream_failures();
+    let mut body = format!(
+        "# HELP ertflix_active_streams Number of streams currently playing\n\
+         # TYPE ertflix_active_streams gauge\n\
+         ertflix_active_streams {}\n\
+         # HELP ertflix_upstream_retry_after_seconds Retry-After of the last ERTFLIX response, 0 when it sent none\n\
+         # TYPE ertflix_upstream_retry_after_seconds gauge\n\
+         ertflix_upstream_retry_after_seconds {}\n\
+         # HELP ertflix_upstream_throttled_responses_total ERTFLIX responses that asked to retry later\n\
+         # TYPE ertflix_upstream_throttled_responses_total counter\n\
+         ertflix_upstream_throttled_responses_total {}\n\
+         # HELP ertflix_upstream_timeouts_total ERTFLIX requests that got no answer in time\n\
+         # TYPE ertflix_upstream_timeouts_total counter\n\
+         ertflix_upstream_timeouts_total {}\n\
+         # HELP ertflix_upstream_errors_total ERTFLIX requests that failed without an answer for another reason\n\
+         # TYPE ertflix_upstream_errors_total counter\n\
+         ertflix_upstream_errors_total {}\n",
+        stream_tracker.active(),
+        rate_limits.retry_after_seconds,
+        rate_limits.throttled_responses,
+        failures.timeouts,
+        failures.errors,
+    );
+    // Left out until ERTFLIX sends X-RateLimit-Remaining, rather than reported as 0
+    if let Some(remaining) = rate_limits.remaining {
+        body.push_str(&format!(
+            "# HELP ertflix_upstream_rate_limit_remaining X-RateLimit-Remaining of the last ERTFLIX response that sent it\n\
+             # TYPE ertflix_upstream_rate_limit_remaining gauge\n\
+             ertflix_upstream_rate_limit_remaining {}\n",
+            remaining
+        ));
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+/// When the process started, for the uptime `/health` reports
+pub static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// `/health`: answers as long as the server is up, for load balancers and liveness probes
+pub async fn handle_health() -> impl Responder {
+    trace!("Answering health check");
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok",
+        "uptime_seconds": STARTED.elapsed().as_secs(),
+        "version": env!("CARGO_PKG_VERSION"),
+    }))
+}
+
+/// `/ready`: 503 unless every dependency can be reached, for readiness probes
+pub async fn handle_ready<T: ErtflixClient + 'static>(media_service: web::Data<MediaService<T>>) -> impl Responder {
+    let checks = media_service.readiness().await;
+    let ready = checks.values().all(Result::is_ok);
+    let checks: serde_json::Map<String, serde_json::Value> = checks
+        .into_iter()
+        .map(|(dependency, check)| (dependency.to_string(), check.err().unwrap_or_else(|| "ok".to_string()).into()))
+        .collect();
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    HttpResponse::build(status).json(serde_json::json!({
+        "status": if ready { "ready" } else { "unavailable" },
+        "checks": checks,
+    }))
+}
+
+pub async fn handle_admin_health<T: ErtflixClient + 'static>(
+    req: HttpRequest,
+    config: CurrentConfig,
+    stream_tracker: web::Data<StreamTracker>,
+    media_service: web::Data<MediaService<T>>,
+    supervisor: web::Data<Supervisor>,
+) -> impl Responder {
+    if !is_admin(&req, &config) {
+        warn!("Rejecting admin health request with missing or invalid admin token");
+        return AppError::Unauthorized.error_response();
+    }
+
+    admin_json(&req, StatusCode::OK, &serde_json::json!({
+        "Status": "ok",
+        "ActiveStreams": stream_tracker.active(),
+        "LastRefresh": media_service.last_refresh(),
+        "Tasks": supervisor.health(),
+    }))
+}
+
+pub async fn handle_authentication(
+    req: HttpRequest,
+    body: web::Bytes,
+    config: CurrentConfig,
+    session_store: web::Data<SessionStore>,
+) -> impl Responder {
     info!("Handling authentication request");
 
-    debug!("Headers: {:#?}", req.headers());
+    let language = config.language.negotiate(
+        req.headers()
+            .get(actix_web::http::header::ACCEPT_LANGUAGE)
+            .and_then(|h| h.to_str().ok()),
+    );
+    debug!("Negotiated language: {}", language);
 
     let emby_auth_header = req
         .headers()
@@ -78,14 +955,108 @@ pub async fn handle_authentication(req: HttpRequest) -> impl Responder {
 
     match EmbyAuthorizationHeader::from_str(emby_auth_header) {
         Ok(authorization) => {
-            HttpResponse::Ok().json(jellyfin_server::AuthenticationResponse::default(authorization))
+            let credentials: AuthenticationBody = serde_json::from_slice(&body).unwrap_or_default();
+            let password = if credentials.pw.is_empty() { &credentials.password } else { &credentials.pw };
+            let mut response = jellyfin_server::AuthenticationResponse::with_clock(authorization, session_store.clock()).with_language(&language);
+            if config.auth.allow_guest && !credentials.username.is_empty() && credentials.username != config::identity().username {
+                response = response.with_guest(&credentials.username);
+            } else if !config.auth.allow_all && !config.auth.accepts(&credentials.username, password) {
+                warn!("Rejecting sign in as {:?}: wrong username or password", credentials.username);
+                return AppError::Unauthorized.error_response();
+            }
+            let session_info = response.session_info();
+            session_store.insert(Session {
+                id: session_info.id.clone(),
+                access_token: response.access_token().to_string(),
+                user_id: session_info.user_id.clone(),
+                user_name: session_info.user_name.clone(),
+                client: session_info.client.clone(),
+                device_name: session_info.device_name.clone(),
+                device_id: session_info.device_id.clone(),
+                application_version: session_info.application_version.clone(),
+                created: session_store.clock().now(),
+            });
+            HttpResponse::Ok()
+                .insert_header((actix_web::http::header::CONTENT_LANGUAGE, language.as_str()))
+                .json(response)
         },
         Err(_) => {
-            HttpResponse::BadRequest().body("Invalid X-Emby-Authentication header")
+            AppError::BadRequest("Invalid X-Emby-Authorization header".to_string()).error_response()
+        }
+    }
+}
+
+/// `/Sessions`: the active sessions, without their tokens. Admins see every session,
+/// everyone else only those of their own user.
+pub async fn handle_get_sessions(
+    req: HttpRequest,
+    config: CurrentConfig,
+    session_store: web::Data<SessionStore>,
+) -> impl Responder {
+    let sessions: Vec<serde_json::Value> = session_store
+        .list()
+        .into_iter()
+        .filter(|session| may_access_user_data(&req, &config, &session.user_id))
+        .map(|session| {
+            serde_json::json!({
+                "Id": session.id,
+                "UserId": session.user_id,
+                "UserName": session.user_name,
+                "Client": session.client,
+                "DeviceName": session.device_name,
+                "DeviceId": session.device_id,
+                "ApplicationVersion": session.application_version,
+                "LastActivityDate": session.created.to_rfc3339(),
+                "ServerId": config::identity().server_id,
+            })
+        })
+        .collect();
+    debug!("Listing {} sessions", sessions.len());
+    HttpResponse::Ok().json(sessions)
+}
+
+/// `DELETE /Sessions/{sessionId}`: ends a session and revokes its token
+pub async fn handle_delete_session(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: CurrentConfig,
+    session_store: web::Data<SessionStore>,
+) -> impl Responder {
+    if !is_admin(&req, &config) {
+        warn!("Rejecting session deletion with missing or invalid admin token");
+        return AppError::Unauthorized.error_response();
+    }
+
+    let session_id = path.into_inner();
+    match session_store.revoke(&session_id) {
+        Some(session) => {
+            info!("Admin ended session {} of device {}", session.id, session.device_name);
+            HttpResponse::NoContent().finish()
         }
+        None => AppError::NotFound(session_id).error_response(),
     }
 }
 
+/// `/Plugins`: no plugins are installed, but admin clients expect the list
+pub async fn handle_get_plugins(req: HttpRequest, config: CurrentConfig) -> impl Responder {
+    if !is_admin(&req, &config) {
+        warn!("Rejecting plugins request with missing or invalid admin token");
+        return AppError::Unauthorized.error_response();
+    }
+    debug!("Reporting no installed plugins");
+    HttpResponse::Ok().json(Vec::<serde_json::Value>::new())
+}
+
+/// `/ScheduledTasks`: nothing runs on a Jellyfin-style schedule, so the list is empty
+pub async fn handle_get_scheduled_tasks(req: HttpRequest, config: CurrentConfig) -> impl Responder {
+    if !is_admin(&req, &config) {
+        warn!("Rejecting scheduled tasks request with missing or invalid admin token");
+        return AppError::Unauthorized.error_response();
+    }
+    debug!("Reporting no scheduled tasks");
+    HttpResponse::Ok().json(Vec::<serde_json::Value>::new())
+}
+
 #[instrument(level = "trace")]
 pub async fn handle_get_system_info() -> impl Responder {
     info!("Handling system info request");
@@ -93,3 +1064,409 @@ pub async fn handle_get_system_info() -> impl Responder {
     trace!("System info response prepared");
     HttpResponse::Ok().json(jellyfin_server::SystemInfo::default())
 }
+
+/// `/Search/Hints`, matching the search term against ERTFLIX
+pub async fn handle_search_hints<T: ErtflixClient + 'static>(
+    media_service: web::Data<MediaService<T>>,
+    config: CurrentConfig,
+    query: web::Query<ItemQuery>,
+) -> impl Responder {
+    let search_term = query.search_term();
+    info!("Handling search for {:?}", search_term);
+    if search_term.is_empty() {
+        debug!("Empty search term, answering with no hints");
+        return HttpResponse::Ok().json(jellyfin::SearchHintResult::new(Vec::new()));
+    }
+
+    match media_service.search(search_term).await {
+        Ok(hints) => HttpResponse::Ok().json(jellyfin::SearchHintResult::from(query.page(hints, &config))),
+        Err(e) => {
+            error!("Failed to search for {}: {}", search_term, e);
+            AppError::from_upstream(e.as_ref()).error_response()
+        }
+    }
+}
+
+/// A small status page for people who open the server in a browser
+pub async fn handle_get_landing_page(supervisor: web::Data<Supervisor>) -> impl Responder {
+    trace!("Rendering landing page");
+    // Degraded while any supervised background task is down, e.g. waiting to be restarted
+    let status = if supervisor.health().values().all(|task| task.running) { "ok" } else { "degraded" };
+    let server_name = html_escape(&config::identity().server_name);
+    let body = format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head><meta charset=\"utf-8\"><title>{server_name}</title></head>\n\
+         <body>\n\
+         <h1>{server_name}</h1>\n\
+         <p>ertflix-2-jellyfin {version}</p>\n\
+         <p>Status: {status}</p>\n\
+         <ul>\n\
+         <li><a href=\"/System/Info/Public\">/System/Info/Public</a></li>\n\
+         <li><a href=\"/metrics\">/metrics</a></li>\n\
+         <li><a href=\"/health\">/health</a></li>\n\
+         <li><a href=\"/ready\">/ready</a></li>\n\
+         </ul>\n\
+         </body>\n\
+         </html>\n",
+        version = env!("CARGO_PKG_VERSION"),
+    );
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body)
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+
+/// Whether the request may use the admin endpoints: it carries the configured admin token
+/// or, when none is configured, a session of the configured user, whose policy makes them
+/// an administrator. Guests and requests without a session never may.
+fn is_admin(req: &HttpRequest, config: &Config) -> bool {
+    match &config.admin.token {
+        Some(_) => auth::has_admin_token(req, config),
+        None => auth::request_session(req).is_some_and(|session| session.user_id == config::identity().user_id),
+    }
+}
+
+/// Whether the request may read or change the data of `user_id`: admins may for any user,
+/// everyone else only with a session of that user
+fn may_manage_user(req: &HttpRequest, config: &Config, user_id: &str) -> bool {
+    is_admin(req, config) || auth::request_session(req).is_some_and(|session| session.user_id == user_id)
+}
+
+/// Whether the request may read or change the favorites and played state of `user_id`:
+/// as [`may_manage_user`], or anyone while `auth.allow_all` keeps the server open
+fn may_access_user_data(req: &HttpRequest, config: &Config, user_id: &str) -> bool {
+    config.auth.allow_all || may_manage_user(req, config, user_id)
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct PrettyParam {
+    pretty: bool,
+}
+
+/// Answers an admin request with `body`, indented when the request asks for `?pretty=true`.
+/// Client endpoints always stay compact.
+fn admin_json(req: &HttpRequest, status: StatusCode, body: &serde_json::Value) -> HttpResponse {
+    let pretty = web::Query::<PrettyParam>::from_query(req.query_string()).is_ok_and(|param| param.pretty);
+    let body = if pretty {
+        serde_json::to_string_pretty(body).unwrap_or_else(|_| body.to_string())
+    } else {
+        body.to_string()
+    };
+    HttpResponse::build(status)
+        .content_type("application/json")
+        .body(body)
+}
+
+pub async fn handle_admin_refresh<T: ErtflixClient + 'static>(
+    req: HttpRequest,
+    config: CurrentConfig,
+    idempotency_store: web::Data<IdempotencyStore>,
+    media_service: web::Data<MediaService<T>>,
+) -> impl Responder {
+    info!("Handling admin refresh request");
+
+    if !is_admin(&req, &config) {
+        warn!("Rejecting admin refresh request with missing or invalid admin token");
+        return AppError::Unauthorized.error_response();
+    }
+
+    let idempotency_key = req
+        .headers()
+        .get("idempotency-key")
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string);
+
+    if let Some(key) = &idempotency_key {
+        match idempotency_store.claim(key).await {
+            Claim::New => trace!("Claimed idempotency key {}", key),
+            Claim::Pending => {
+                warn!("Rejecting refresh, one with idempotency key {} is still running", key);
+                return AppError::Conflict(format!("a refresh with idempotency key {} is still running", key))
+                    .error_response();
+            }
+            Claim::Done(stored) => {
+                info!("Replaying stored response for idempotency key {}", key);
+                return HttpResponse::build(StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK))
+                    .content_type("application/json")
+                    .body(stored.body);
+            }
+        }
+    }
+
+    trace!("Refreshing collections, movies and TV shows");
+    let report = media_service.refresh_all().await;
+    let status = if report.is_success() {
+        StatusCode::OK
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    let report = serde_json::json!(report);
+    if let Some(key) = &idempotency_key {
+        debug!("Storing refresh response for idempotency key {}", key);
+        idempotency_store
+            .complete(key, StoredResponse { status: status.as_u16(), body: report.to_string() })
+            .await;
+    }
+
+    admin_json(&req, status, &report)
+}
+
+/// `/admin/export.csv`: the library as CSV, written one row at a time
+pub async fn handle_admin_export_csv<T: ErtflixClient + 'static>(
+    req: HttpRequest,
+    config: CurrentConfig,
+    media_service: web::Data<MediaService<T>>,
+) -> impl Responder {
+    info!("Handling admin library export request");
+
+    if !is_admin(&req, &config) {
+        warn!("Rejecting admin export request with missing or invalid admin token");
+        return AppError::Unauthorized.error_response();
+    }
+
+    let (movies, tv_shows) = match (media_service.get_movies().await, media_service.get_tv_shows().await) {
+        (Ok(movies), Ok(tv_shows)) => (movies, tv_shows),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to retrieve library for export: {}", e);
+            return AppError::from_upstream(e.as_ref()).error_response();
+        }
+    };
+    debug!("Exporting {} movies and {} TV shows", movies.len(), tv_shows.len());
+
+    let header = std::iter::once(csv_row(&["id", "title", "type", "year", "genres"]));
+    let movie_rows = movies.into_iter().map(|movie| {
+        let year = movie.year.map(|year| year.to_string()).unwrap_or_default();
+        csv_row(&[&movie.id, &movie.title, "Movie", &year, &movie.genre.join(";")])
+    });
+    let show_rows = tv_shows
+        .into_iter()
+        .map(|show| csv_row(&[&show.id, &show.title, "Series", "", ""]));
+    let rows = header
+        .chain(movie_rows)
+        .chain(show_rows)
+        .map(|row| Ok::<_, actix_web::Error>(web::Bytes::from(row)));
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((actix_web::http::header::CONTENT_DISPOSITION, "attachment; filename=\"export.csv\""))
+        .streaming(futures::stream::iter(rows))
+}
+
+/// `/admin/mappings`: the ERTFLIX section behind each collection, with its content type and
+/// item count, to debug how the library was organized
+pub async fn handle_admin_mappings<T: ErtflixClient + 'static>(
+    req: HttpRequest,
+    config: CurrentConfig,
+    media_service: web::Data<MediaService<T>>,
+) -> impl Responder {
+    info!("Handling admin collection mappings request");
+
+    if !is_admin(&req, &config) {
+        warn!("Rejecting admin mappings request with missing or invalid admin token");
+        return AppError::Unauthorized.error_response();
+    }
+
+    match media_service.get_collection_mappings(&config.ertflix).await {
+        Ok(mappings) => {
+            debug!("Listing the mappings of {} collections", mappings.len());
+            admin_json(&req, StatusCode::OK, &serde_json::json!(mappings))
+        }
+        Err(e) => {
+            error!("Failed to retrieve collection mappings: {}", e);
+            AppError::from_upstream(e.as_ref()).error_response()
+        }
+    }
+}
+
+/// The favorites of a user, as `/admin/users/{userId}/favorites/export` answers and
+/// `/admin/users/{userId}/favorites/import` expects them
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FavoritesExport {
+    pub item_ids: Vec<ItemId>,
+}
+
+/// `/admin/users/{userId}/favorites/export`: the ids of the user's favorite items, to back
+/// them up or move them to another server. Admins may export any user's, others only their own.
+pub async fn handle_admin_export_favorites(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: CurrentConfig,
+    user_data_store: web::Data<UserDataStore>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    info!("Handling admin favorites export for user {}", user_id);
+
+    if !may_manage_user(&req, &config, &user_id) {
+        warn!("Rejecting favorites export of user {} without an admin token or a session of that user", user_id);
+        return AppError::Unauthorized.error_response();
+    }
+
+    let mut item_ids = user_data_store.favorites(&user_id);
+    item_ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    debug!("Exporting {} favorites of user {}", item_ids.len(), user_id);
+    admin_json(&req, StatusCode::OK, &serde_json::json!(FavoritesExport { item_ids }))
+}
+
+/// `/admin/users/{userId}/favorites/import`: marks every item of an export as a favorite of
+/// the user, keeping the favorites they already have. Ids of items ERTFLIX doesn't list
+/// (anymore) are imported too, and counted as `NotInLibrary`. Admins may import for any
+/// user, others only for themselves.
+pub async fn handle_admin_import_favorites<T: ErtflixClient + 'static>(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: CurrentConfig,
+    body: web::Bytes,
+    media_service: web::Data<MediaService<T>>,
+    user_data_store: web::Data<UserDataStore>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    info!("Handling admin favorites import for user {}", user_id);
+
+    if !may_manage_user(&req, &config, &user_id) {
+        warn!("Rejecting favorites import for user {} without an admin token or a session of that user", user_id);
+        return AppError::Unauthorized.error_response();
+    }
+
+    let favorites: FavoritesExport = match serde_json::from_slice(&body) {
+        Ok(favorites) => favorites,
+        Err(e) => return AppError::BadRequest(format!("Invalid favorites export: {}", e)).error_response(),
+    };
+
+    let library = match media_service.library_item_ids().await {
+        Ok(library) => library,
+        Err(e) => {
+            error!("Failed to retrieve the library to check imported favorites against: {}", e);
+            return AppError::from_upstream(e.as_ref()).error_response();
+        }
+    };
+    let mut not_in_library = 0;
+    for item_id in &favorites.item_ids {
+        if !library.contains(item_id) {
+            debug!("Imported favorite {} is not in the library", item_id);
+            not_in_library += 1;
+        }
+        user_data_store.set_favorite(&user_id, item_id, true);
+    }
+    info!("Imported {} favorites of user {}, {} not in the library", favorites.item_ids.len(), user_id, not_in_library);
+
+    admin_json(&req, StatusCode::OK, &serde_json::json!({
+        "Imported": favorites.item_ids.len(),
+        "NotInLibrary": not_in_library,
+    }))
+}
+
+/// Formats a CSV row, quoting fields that contain commas, quotes or line breaks
+fn csv_row(fields: &[&str]) -> String {
+    let mut row = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    row.push_str("\r\n");
+    row
+}
+
+/// `/admin/selftest`: fetches the configured known-good tile and checks that it
+/// converts into a usable Jellyfin item, as a canary for the whole pipeline
+pub async fn handle_admin_selftest<T: ErtflixClient + 'static>(
+    req: HttpRequest,
+    config: CurrentConfig,
+    media_service: web::Data<MediaService<T>>,
+) -> impl Responder {
+    info!("Handling admin self-test request");
+
+    if !is_admin(&req, &config) {
+        warn!("Rejecting admin self-test request with missing or invalid admin token");
+        return AppError::Unauthorized.error_response();
+    }
+
+    let Some(tile_id) = config.admin.selftest_tile_id.as_deref() else {
+        warn!("Self-test requested but admin.selftest_tile_id is not configured");
+        return admin_json(&req, StatusCode::SERVICE_UNAVAILABLE, &serde_json::json!({
+            "Passed": false,
+            "Checks": [{ "Name": "configuration", "Passed": false, "Detail": "admin.selftest_tile_id is not set" }],
+        }));
+    };
+
+    // Straight from ERTFLIX, so every run exercises the fetch and not a cached answer
+    let checks: Vec<(&str, Result<String, String>)> = match media_service.fetch_movie(tile_id).await {
+        Ok(movie) => {
+            let fetched = format!("fetched tile {}", movie.id);
+            let item = media_service.convert_to_jellyfin_movie(movie);
+            vec![
+                ("fetch", Ok(fetched)),
+                (
+                    "title",
+                    if item.title.trim().is_empty() {
+                        Err("converted item has an empty title".to_string())
+                    } else {
+                        Ok(item.title.clone())
+                    },
+                ),
+                (
+                    "id",
+                    match item.id.parse::<ItemId>() {
+                        Ok(item_id) if item_id.as_str() == item.id => Ok(item.id.clone()),
+                        _ => Err(format!("converted item has an invalid id: {:?}", item.id)),
+                    },
+                ),
+            ]
+        }
+        Err(e) => vec![("fetch", Err(e.to_string()))],
+    };
+
+    let passed = checks.iter().all(|(_, outcome)| outcome.is_ok());
+    if passed {
+        info!("Self-test passed for tile {}", tile_id);
+    } else {
+        error!("Self-test failed for tile {}", tile_id);
+    }
+
+    let checks: Vec<serde_json::Value> = checks
+        .into_iter()
+        .map(|(name, outcome)| {
+            let (passed, detail) = match outcome {
+                Ok(detail) => (true, detail),
+                Err(detail) => (false, detail),
+            };
+            serde_json::json!({ "Name": name, "Passed": passed, "Detail": detail })
+        })
+        .collect();
+
+    let status = if passed { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    admin_json(&req, status, &serde_json::json!({
+        "Passed": passed,
+        "TileId": tile_id,
+        "Checks": checks,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_with_commas_quotes_or_line_breaks_are_quoted() {
+        assert_eq!(
+            csv_row(&["id-1", "Ο Θίασος, μέρος 1", "Το \"Νησί\"", "Γραμμή\nδύο", "2020"]),
+            "id-1,\"Ο Θίασος, μέρος 1\",\"Το \"\"Νησί\"\"\",\"Γραμμή\nδύο\",2020\r\n"
+        );
+    }
+}
@@ -1,19 +1,32 @@
-use actix_web::web;
+use std::future::{ready, Ready};
+use std::ops::Deref;
+use std::sync::Arc;
+use actix_web::{dev, web, FromRequest, HttpRequest};
 use crate::api::ertflix_client::ErtflixClient;
-use tracing::{debug, info, trace};
+use crate::config::{Config, SharedConfig};
+use crate::error::AppError;
+use tracing::{debug, error, info, trace};
 
+pub mod auth;
 pub mod handlers;
 
 pub fn init_routes<T: ErtflixClient + 'static>(cfg: &mut web::ServiceConfig) {
     info!("Initializing application routes");
     debug!("Configuring route handlers for ErtflixClient type");
     
+    // Malformed paths and queries are answered with the same JSON errors as the handlers'
+    cfg.app_data(web::PathConfig::default().error_handler(|e, _| AppError::BadRequest(e.to_string()).into()));
+    cfg.app_data(web::QueryConfig::default().error_handler(|e, _| AppError::BadRequest(e.to_string()).into()));
+    
     trace!("Registering /tv route for TV shows endpoint");
     cfg.route("/tv", web::get().to(handlers::handle_get_tv_shows::<T>));
     
     trace!("Registering /movies route for movies endpoint");
     cfg.route("/movies", web::get().to(handlers::handle_get_movies::<T>));
     
+    trace!("Registering / route for landing page");
+    cfg.route("/", web::get().to(handlers::handle_get_landing_page));
+    
     // Infuse's first request to a Jellyfin server is to this endpoint, to provide a health check
     trace!("Registering /System/Info/Public route for system info endpoint");
     cfg.route(
@@ -29,18 +42,182 @@ pub fn init_routes<T: ErtflixClient + 'static>(cfg: &mut web::ServiceConfig) {
     );
     
     // Infuse requests collections from this endpoint
-    trace!("Registering /UserViews route for collections endpoint");
+    trace!("Registering /UserViews and /Users/{{userId}}/Views routes for collections endpoints");
     cfg.route(
         "/UserViews",
         web::get().to(handlers::handle_get_collections::<T>),
     );
+    cfg.route(
+        "/Users/{user_id}/Views",
+        web::get().to(handlers::handle_get_user_views::<T>),
+    );
+    
+    // Item listings, with and without a user segment depending on the client
+    trace!("Registering /Items and /Users/{{userId}}/Items routes for items endpoints");
+    cfg.route("/Items", web::get().to(handlers::handle_get_items::<T>));
+    cfg.route(
+        "/Users/{user_id}/Items",
+        web::get().to(handlers::handle_get_user_items::<T>),
+    );
+    
+    trace!("Registering /Search/Hints route for search endpoint");
+    cfg.route("/Search/Hints", web::get().to(handlers::handle_search_hints::<T>));
+    
+    // Detail of a single item, requested when a user opens it
+    trace!("Registering /Items/{{itemId}} and /Users/{{userId}}/Items/{{itemId}} routes for item detail endpoints");
+    cfg.route("/Items/{item_id}", web::get().to(handlers::handle_get_item::<T>));
+    cfg.route(
+        "/Users/{user_id}/Items/{item_id}",
+        web::get().to(handlers::handle_get_user_item::<T>),
+    );
+    
+    // Clients ask for the media sources of an item right before playing it
+    trace!("Registering /Items/{{itemId}}/PlaybackInfo routes for playback info endpoint");
+    cfg.route(
+        "/Items/{item_id}/PlaybackInfo",
+        web::get().to(handlers::handle_get_playback_info::<T>),
+    );
+    cfg.route(
+        "/Items/{item_id}/PlaybackInfo",
+        web::post().to(handlers::handle_get_playback_info::<T>),
+    );
+    
+    // Favorites are toggled per user and reflected in each item's UserData
+    trace!("Registering /Users/{{userId}}/FavoriteItems/{{itemId}} routes for favorites endpoints");
+    cfg.route(
+        "/Users/{user_id}/FavoriteItems/{item_id}",
+        web::post().to(handlers::handle_mark_favorite),
+    );
+    cfg.route(
+        "/Users/{user_id}/FavoriteItems/{item_id}",
+        web::delete().to(handlers::handle_unmark_favorite),
+    );
+    
+    // Played state backs "hide watched" in clients
+    trace!("Registering /Users/{{userId}}/PlayedItems/{{itemId}} routes for played state endpoints");
+    cfg.route(
+        "/Users/{user_id}/PlayedItems/{item_id}",
+        web::post().to(handlers::handle_mark_played),
+    );
+    cfg.route(
+        "/Users/{user_id}/PlayedItems/{item_id}",
+        web::delete().to(handlers::handle_mark_unplayed),
+    );
+    
+    // Playback reports keep track of the resume position behind "Continue Watching"
+    trace!("Registering /Sessions/Playing routes for playback reporting endpoints");
+    cfg.route(
+        "/Sessions/Playing",
+        web::post().to(handlers::handle_playback_start),
+    );
+    cfg.route(
+        "/Sessions/Playing/Progress",
+        web::post().to(handlers::handle_playback_progress),
+    );
+    cfg.route(
+        "/Sessions/Playing/Stopped",
+        web::post().to(handlers::handle_playback_stopped),
+    );
+    
+    // Artwork from the ERTFLIX CDN
+    trace!("Registering /Items/{{item_id}}/Images routes for item images");
+    cfg.route(
+        "/Items/{item_id}/Images/{image_type}",
+        web::get().to(handlers::handle_get_item_image::<T>),
+    );
+    cfg.route(
+        "/Items/{item_id}/Images/{image_type}/{image_index}",
+        web::get().to(handlers::handle_get_item_image::<T>),
+    );
+    
+    // Probes for load balancers and orchestrators, outside of Jellyfin's API
+    trace!("Registering /health and /ready routes for probes");
+    cfg.route("/health", web::get().to(handlers::handle_health));
+    cfg.route("/ready", web::get().to(handlers::handle_ready::<T>));
+
+    trace!("Registering /metrics route for metrics endpoint");
+    cfg.route("/metrics", web::get().to(handlers::handle_get_metrics::<T>));
+    
+    trace!("Registering /Sessions routes for session management endpoints");
+    cfg.route("/Sessions", web::get().to(handlers::handle_get_sessions));
+    cfg.route(
+        "/Sessions/{session_id}",
+        web::delete().to(handlers::handle_delete_session),
+    );
+    
+    // Dashboard probes; answered with empty lists so admin clients don't show errors
+    trace!("Registering /Plugins and /ScheduledTasks routes for dashboard compatibility");
+    cfg.route("/Plugins", web::get().to(handlers::handle_get_plugins));
+    cfg.route("/ScheduledTasks", web::get().to(handlers::handle_get_scheduled_tasks));
+    
+    // Admin endpoints, guarded by the configured admin token
+    trace!("Registering /admin/refresh route for library refresh endpoint");
+    cfg.route(
+        "/admin/refresh",
+        web::post().to(handlers::handle_admin_refresh::<T>),
+    );
+    cfg.route(
+        "/admin/health",
+        web::get().to(handlers::handle_admin_health::<T>),
+    );
+    trace!("Registering /admin/export.csv route for library export endpoint");
+    cfg.route(
+        "/admin/export.csv",
+        web::get().to(handlers::handle_admin_export_csv::<T>),
+    );
+    trace!("Registering /admin/mappings route for collection mappings endpoint");
+    cfg.route(
+        "/admin/mappings",
+        web::get().to(handlers::handle_admin_mappings::<T>),
+    );
+    trace!("Registering /admin/users/{{userId}}/favorites routes for favorites export and import endpoints");
+    cfg.route(
+        "/admin/users/{user_id}/favorites/export",
+        web::get().to(handlers::handle_admin_export_favorites),
+    );
+    cfg.route(
+        "/admin/users/{user_id}/favorites/import",
+        web::post().to(handlers::handle_admin_import_favorites::<T>),
+    );
+    trace!("Registering /admin/selftest route for pipeline self-test endpoint");
+    cfg.route(
+        "/admin/selftest",
+        web::get().to(handlers::handle_admin_selftest::<T>),
+    );
     
     info!("All routes successfully registered");
     debug!("Route initialization completed");
 }
 
-#[derive(serde::Deserialize)]
-#[serde(rename_all = "PascalCase")]
+/// The configuration current when the request arrived. A reload while the request is
+/// handled doesn't change it halfway through.
+pub struct CurrentConfig(Arc<Config>);
+
+impl Deref for CurrentConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &self.0
+    }
+}
+
+impl FromRequest for CurrentConfig {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+        ready(match req.app_data::<web::Data<SharedConfig>>() {
+            Some(shared) => Ok(CurrentConfig(shared.current())),
+            None => {
+                error!("No configuration registered for {}", req.path());
+                Err(actix_web::error::ErrorInternalServerError("configuration is not set up"))
+            }
+        })
+    }
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
 struct AuthenticationBody {
     pw: String,
     username: String,
@@ -0,0 +1,325 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, trace, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use redis::RedisResult;
+use crate::services::redis_store::RedisStore;
+
+/// Result of a cache lookup
+pub enum Lookup<V> {
+    /// The entry is within its TTL
+    Fresh(V),
+    /// The entry expired but is still within the grace window and may be served while it is refreshed
+    Stale(V),
+    Miss,
+}
+
+/// How a response was produced, reported to clients in the `X-Cache` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Revalidating,
+}
+
+impl CacheStatus {
+    pub fn header_value(&self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Miss => "MISS",
+            CacheStatus::Revalidating => "REVALIDATING",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CacheEntry {
+    value: String,
+    expires_at: Instant,
+}
+
+/// Entry as stored in Redis. Expiry is wall-clock time since it is shared across restarts.
+#[derive(Serialize, Deserialize)]
+struct RedisEntry {
+    value: String,
+    expires_at_ms: u64,
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Serializes `value` as stored in Redis, expiring after `ttl`
+fn redis_entry(value: String, ttl: Duration) -> String {
+    let entry = RedisEntry { value, expires_at_ms: unix_millis(SystemTime::now() + ttl) };
+    serde_json::to_string(&entry).expect("a Redis entry always serializes")
+}
+
+static NEXT_CACHE_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Each worker thread's copies of recently read fresh entries, keyed by cache id and key
+    static LOCAL_ENTRIES: RefCell<HashMap<(u64, String), CacheEntry>> = RefCell::new(HashMap::new());
+}
+
+/// In-memory cache of JSON serialized values with per-entry TTLs and a shared
+/// grace window during which expired entries are still served as stale.
+///
+/// Fresh reads can be kept for a very short time in a worker-local layer, so hot keys
+/// are served without touching the shared entries. `set_with_ttl` and `clear` only drop
+/// the calling worker's copies; other workers catch up once their local TTL runs out.
+///
+/// With a Redis store attached the shared entries live in Redis instead. Redis failures
+/// are logged and treated as misses, so callers fall back to fetching live.
+pub struct Cache {
+    id: u64,
+    grace: Duration,
+    local_ttl: Duration,
+    entries: RwLock<HashMap<String, CacheEntry>>,
+    /// Lookups that reached `entries`, i.e. were not answered by the worker-local layer
+    shared_reads: AtomicU64,
+    revalidating: Mutex<HashSet<String>>,
+    redis: Option<RedisStore>,
+}
+
+impl Cache {
+    pub fn new(grace: Duration) -> Self {
+        debug!("Creating cache with a stale grace window of {}s", grace.as_secs());
+        Self {
+            id: NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed),
+            grace,
+            local_ttl: Duration::ZERO,
+            entries: RwLock::new(HashMap::new()),
+            shared_reads: AtomicU64::new(0),
+            revalidating: Mutex::new(HashSet::new()),
+            redis: None,
+        }
+    }
+
+    /// Keeps fresh reads in a worker-local layer for `ttl`; zero disables the layer
+    pub fn with_local_ttl(mut self, ttl: Duration) -> Self {
+        debug!("Keeping worker-local cache copies for {}ms", ttl.as_millis());
+        self.local_ttl = ttl;
+        self
+    }
+
+    /// Keeps the shared entries in Redis rather than in memory
+    pub fn with_redis(mut self, store: RedisStore) -> Self {
+        debug!("Keeping shared cache entries in Redis");
+        self.redis = Some(store);
+        self
+    }
+
+    pub async fn get<V: DeserializeOwned>(&self, key: &str) -> Lookup<V> {
+        if let Some(value) = self.get_local(key) {
+            return value;
+        }
+
+        self.shared_reads.fetch_add(1, Ordering::Relaxed);
+        let entry = match &self.redis {
+            Some(redis) => self.get_redis(redis, key).await,
+            None => self.entries.read().unwrap().get(key).cloned(),
+        };
+        let Some(entry) = entry else {
+            trace!("Cache miss for {}", key);
+            return Lookup::Miss;
+        };
+
+        let now = Instant::now();
+        if now >= entry.expires_at + self.grace {
+            trace!("Cache entry for {} is past its grace window", key);
+            return Lookup::Miss;
+        }
+
+        match serde_json::from_str(&entry.value) {
+            Ok(value) if now < entry.expires_at => {
+                trace!("Cache hit for {}", key);
+                self.set_local(key, &entry);
+                Lookup::Fresh(value)
+            }
+            Ok(value) => {
+                trace!("Stale cache hit for {}", key);
+                Lookup::Stale(value)
+            }
+            Err(e) => {
+                warn!("Discarding unreadable cache entry for {}: {}", key, e);
+                Lookup::Miss
+            }
+        }
+    }
+
+    /// Reads an entry from Redis, translating its wall-clock expiry to an `Instant`
+    async fn get_redis(&self, redis: &RedisStore, key: &str) -> Option<CacheEntry> {
+        let stored = match redis.get(key).await {
+            Ok(stored) => stored?,
+            Err(e) => {
+                warn!("Redis read of {} failed, fetching live: {}", key, e);
+                return None;
+            }
+        };
+        let entry: RedisEntry = serde_json::from_str(&stored)
+            .map_err(|e| warn!("Discarding unreadable Redis entry for {}: {}", key, e))
+            .ok()?;
+
+        let now = Instant::now();
+        let now_ms = unix_millis(SystemTime::now());
+        let expires_at = if entry.expires_at_ms >= now_ms {
+            now + Duration::from_millis(entry.expires_at_ms - now_ms)
+        } else {
+            now.checked_sub(Duration::from_millis(now_ms - entry.expires_at_ms)).unwrap_or(now)
+        };
+        Some(CacheEntry { value: entry.value, expires_at })
+    }
+
+    fn get_local<V: DeserializeOwned>(&self, key: &str) -> Option<Lookup<V>> {
+        if self.local_ttl.is_zero() {
+            return None;
+        }
+        LOCAL_ENTRIES.with_borrow_mut(|local| {
+            let local_key = (self.id, key.to_string());
+            let entry = local.get(&local_key)?;
+            if Instant::now() >= entry.expires_at {
+                local.remove(&local_key);
+                return None;
+            }
+            let value = serde_json::from_str(&entry.value).ok()?;
+            trace!("Worker-local cache hit for {}", key);
+            Some(Lookup::Fresh(value))
+        })
+    }
+
+    /// Copies a fresh shared entry into this worker's layer, never past the entry's own expiry
+    fn set_local(&self, key: &str, entry: &CacheEntry) {
+        if self.local_ttl.is_zero() {
+            return;
+        }
+        let expires_at = entry.expires_at.min(Instant::now() + self.local_ttl);
+        LOCAL_ENTRIES.with_borrow_mut(|local| {
+            local.retain(|_, entry| entry.expires_at > Instant::now());
+            local.insert((self.id, key.to_string()), CacheEntry { value: entry.value.clone(), expires_at });
+        });
+    }
+
+    /// Number of lookups that went past the worker-local layer to the shared entries
+    #[cfg(test)]
+    fn shared_reads(&self) -> u64 {
+        self.shared_reads.load(Ordering::Relaxed)
+    }
+
+    pub async fn set_with_ttl<V: Serialize>(&self, key: &str, value: &V, ttl: Duration) {
+        let value = match serde_json::to_string(value) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to serialize {} for caching: {}", key, e);
+                return;
+            }
+        };
+        trace!("Caching {} for {}s", key, ttl.as_secs());
+        LOCAL_ENTRIES.with_borrow_mut(|local| local.remove(&(self.id, key.to_string())));
+
+        let Some(redis) = &self.redis else {
+            self.entries.write().unwrap().insert(
+                key.to_string(),
+                CacheEntry { value, expires_at: Instant::now() + ttl },
+            );
+            return;
+        };
+        // Redis keeps the entry through the grace window so it can still be served stale
+        if let Err(e) = redis.set(key, redis_entry(value, ttl), ttl + self.grace).await {
+            warn!("Failed to cache {} in Redis: {}", key, e);
+        }
+    }
+
+    /// Caches `value` like `set_with_ttl`, but only when `key` has no entry, even a stale
+    /// one, in a single step. Returns whether it was stored. When Redis fails the value
+    /// counts as stored, as if the key were absent.
+    pub async fn insert_if_absent<V: Serialize>(&self, key: &str, value: &V, ttl: Duration) -> bool {
+        let value = match serde_json::to_string(value) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to serialize {} for caching: {}", key, e);
+                return false;
+            }
+        };
+
+        let Some(redis) = &self.redis else {
+            let mut entries = self.entries.write().unwrap();
+            let now = Instant::now();
+            entries.retain(|_, entry| now < entry.expires_at + self.grace);
+            if entries.contains_key(key) {
+                trace!("Not caching {}, it is already present", key);
+                return false;
+            }
+            entries.insert(key.to_string(), CacheEntry { value, expires_at: now + ttl });
+            return true;
+        };
+        match redis.set_if_absent(key, redis_entry(value, ttl), ttl + self.grace).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!("Failed to cache {} in Redis: {}", key, e);
+                true
+            }
+        }
+    }
+
+    pub async fn clear(&self) {
+        debug!("Clearing the cache");
+        self.entries.write().unwrap().clear();
+        LOCAL_ENTRIES.with_borrow_mut(|local| local.retain(|(cache_id, _), _| *cache_id != self.id));
+        if let Some(redis) = &self.redis
+            && let Err(e) = redis.clear().await
+        {
+            warn!("Failed to clear the Redis cache: {}", e);
+        }
+    }
+
+    /// Pings the Redis store, if one is attached
+    pub async fn ping_redis(&self) -> Option<RedisResult<String>> {
+        match &self.redis {
+            Some(redis) => Some(redis.ping().await),
+            None => None,
+        }
+    }
+
+    /// Claims the background refresh of `key`, returning false if one is already running
+    pub fn begin_revalidation(&self, key: &str) -> bool {
+        self.revalidating.lock().unwrap().insert(key.to_string())
+    }
+
+    pub fn end_revalidation(&self, key: &str) {
+        self.revalidating.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn local_layer_serves_repeated_reads_without_the_shared_entries() {
+        let cache = Cache::new(Duration::from_secs(60)).with_local_ttl(Duration::from_secs(5));
+        cache.set_with_ttl("movies", &vec!["movie-1"], Duration::from_secs(60)).await;
+
+        assert!(matches!(cache.get::<Vec<String>>("movies").await, Lookup::Fresh(_)));
+        assert_eq!(cache.shared_reads(), 1);
+        match cache.get::<Vec<String>>("movies").await {
+            Lookup::Fresh(movies) => assert_eq!(movies, vec!["movie-1"]),
+            _ => panic!("expected a fresh worker-local hit"),
+        }
+        assert_eq!(cache.shared_reads(), 1);
+    }
+
+    #[actix_web::test]
+    async fn without_local_ttl_every_read_goes_to_the_shared_entries() {
+        let cache = Cache::new(Duration::from_secs(60));
+        cache.set_with_ttl("movies", &vec!["movie-1"], Duration::from_secs(60)).await;
+
+        cache.get::<Vec<String>>("movies").await;
+        cache.get::<Vec<String>>("movies").await;
+        assert_eq!(cache.shared_reads(), 2);
+    }
+}
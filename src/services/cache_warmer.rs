@@ -0,0 +1,33 @@
+use std::time::Duration;
+use actix_web::rt::time;
+use tracing::{error, info, warn};
+use crate::api::ertflix_client::ErtflixClient;
+use crate::services::media_service::MediaService;
+
+/// Fetches collections, movies and TV shows into the cache forever on the given interval.
+/// A failing fetch is logged and retried on the next tick; the entries it would have
+/// replaced stay cached until they expire.
+pub async fn run<T: ErtflixClient + 'static>(media_service: &MediaService<T>, interval: Duration, shortest_ttl: Duration) {
+    info!("Warming the cache every {}s", interval.as_secs());
+    if interval >= shortest_ttl {
+        warn!(
+            "Cache warming interval of {}s is not shorter than the {}s TTL, entries may expire between runs",
+            interval.as_secs(),
+            shortest_ttl.as_secs()
+        );
+    }
+    let mut ticker = time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+        let report = media_service.warm_cache().await;
+        if report.is_success() {
+            info!(
+                "Cache warmed in {}ms: {:?} collections, {:?} movies, {:?} TV shows",
+                report.duration_ms, report.collections, report.movies, report.tv_shows
+            );
+        } else {
+            error!("Cache warming failed for {:?}", report.errors);
+        }
+    }
+}
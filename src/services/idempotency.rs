@@ -0,0 +1,117 @@
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace, warn};
+use crate::config::Config;
+use crate::services::cache::{Cache, Lookup};
+use crate::services::redis_store::RedisStore;
+
+/// A response remembered for an `Idempotency-Key`, replayed verbatim on retries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// What is stored under a key: a marker while the first request executes, then its response
+#[derive(Serialize, Deserialize)]
+enum Entry {
+    Pending,
+    Done(StoredResponse),
+}
+
+/// Outcome of `IdempotencyStore::claim`
+#[derive(Debug, PartialEq)]
+pub enum Claim {
+    /// The key is new within the window: execute the request, then `complete` the key
+    New,
+    /// A request with the key is still executing
+    Pending,
+    /// A request with the key was answered with this response
+    Done(StoredResponse),
+}
+
+/// Remembers the first response produced for each `Idempotency-Key` so that
+/// retried admin POSTs within the window are answered without re-executing.
+///
+/// Keys are claimed before the request executes, in one step of the cache backend, so
+/// concurrent retries don't both execute. With a Redis backed cache the keys are shared
+/// by every instance.
+pub struct IdempotencyStore {
+    window: Duration,
+    cache: Cache,
+}
+
+impl IdempotencyStore {
+    /// Remembers responses for `admin.idempotency_window_seconds`, in Redis when it is enabled
+    pub fn from_config(config: &Config) -> Self {
+        let mut cache = Cache::new(Duration::ZERO);
+        if config.redis.enabled {
+            match RedisStore::new(&config.redis) {
+                Ok(store) => cache = cache.with_redis(store.with_namespace("idempotency")),
+                Err(e) => warn!("Invalid Redis URL {}, keeping idempotency keys in memory instead: {}", config.redis.url, e),
+            }
+        }
+        Self::with_cache(Duration::from_secs(config.admin.idempotency_window_seconds), cache)
+    }
+
+    /// Keeps the keys in `cache`
+    pub fn with_cache(window: Duration, cache: Cache) -> Self {
+        debug!("Creating idempotency store with a window of {}s", window.as_secs());
+        Self { window, cache }
+    }
+
+    /// Claims `key` for a request about to execute, unless an earlier request did within the window
+    pub async fn claim(&self, key: &str) -> Claim {
+        // A key expiring between the two steps is claimed again; more misses mean the backend is failing
+        for _ in 0..2 {
+            if self.cache.insert_if_absent(key, &Entry::Pending, self.window).await {
+                trace!("Claimed idempotency key {}", key);
+                return Claim::New;
+            }
+            match self.cache.get::<Entry>(key).await {
+                Lookup::Fresh(Entry::Done(response)) | Lookup::Stale(Entry::Done(response)) => return Claim::Done(response),
+                Lookup::Fresh(Entry::Pending) | Lookup::Stale(Entry::Pending) => return Claim::Pending,
+                Lookup::Miss => trace!("Idempotency key {} expired while it was claimed", key),
+            }
+        }
+        warn!("Idempotency key {} could neither be claimed nor read, executing the request", key);
+        Claim::New
+    }
+
+    /// Records the response of the request that claimed `key`
+    pub async fn complete(&self, key: &str, response: StoredResponse) {
+        self.cache.set_with_ttl(key, &Entry::Done(response), self.window).await;
+        trace!("Stored response for idempotency key {}", key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(window: Duration) -> IdempotencyStore {
+        IdempotencyStore::with_cache(window, Cache::new(Duration::ZERO))
+    }
+
+    #[actix_web::test]
+    async fn a_claimed_key_is_pending_until_completed_then_replayed() {
+        let store = store(Duration::from_secs(60));
+        let response = StoredResponse { status: 200, body: "{}".to_string() };
+
+        assert_eq!(store.claim("key-1").await, Claim::New);
+        assert_eq!(store.claim("key-1").await, Claim::Pending);
+        assert_eq!(store.claim("key-2").await, Claim::New);
+
+        store.complete("key-1", response.clone()).await;
+        assert_eq!(store.claim("key-1").await, Claim::Done(response));
+    }
+
+    #[actix_web::test]
+    async fn keys_can_be_claimed_again_after_the_window() {
+        let store = store(Duration::from_millis(20));
+
+        assert_eq!(store.claim("key").await, Claim::New);
+        actix_web::rt::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(store.claim("key").await, Claim::New);
+    }
+}
@@ -0,0 +1,127 @@
+use std::future::Future;
+use std::time::Duration;
+use actix_web::web::Bytes;
+use tracing::{debug, error, trace};
+use reqwest::{header, Client};
+use tokio::sync::Semaphore;
+use crate::api::ertflix_client::Error;
+use crate::config;
+
+/// Artwork fetched from the ERTFLIX CDN
+pub struct Image {
+    pub content_type: String,
+    pub bytes: Bytes,
+}
+
+/// Fetches artwork for the image endpoints.
+///
+/// Has its own bound on concurrent fetches, separate from the ERTFLIX API limit, so a
+/// client loading a large grid can't saturate the network. Fetches beyond the bound
+/// wait for a free slot.
+pub struct ImageProxy {
+    client: Client,
+    permits: Semaphore,
+}
+
+impl ImageProxy {
+    pub fn new(max_concurrent_fetches: usize) -> Self {
+        debug!("Creating image proxy fetching at most {} images at once", max_concurrent_fetches);
+        Self {
+            client: Client::new(),
+            permits: Semaphore::new(max_concurrent_fetches.max(1)),
+        }
+    }
+
+    /// Downloads the image, failing with `Error::NotFound` when the CDN doesn't have it
+    pub async fn fetch(&self, url: &str) -> Result<Image, Error> {
+        self.limited(self.download(url)).await
+    }
+
+    /// Runs `fetch` once one of the fetch slots is free
+    async fn limited<Fut: Future>(&self, fetch: Fut) -> Fut::Output {
+        if self.permits.available_permits() == 0 {
+            trace!("All image fetch slots are busy, queueing");
+        }
+        let _permit = self.permits.acquire().await.expect("the image fetch semaphore is never closed");
+        fetch.await
+    }
+
+    async fn download(&self, url: &str) -> Result<Image, Error> {
+        trace!("Fetching image {}", url);
+        let response = self
+            .client
+            .get(url)
+            .timeout(Duration::from_secs(config::TIMEOUT_SECONDS))
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Image request failed for {}: {}", url, e);
+                Error::from_request(e)
+            })?;
+
+        if !response.status().is_success() {
+            debug!("Image {} answered {}", url, response.status());
+            return Err(Error::NotFound(url.to_string()));
+        }
+
+        let declared_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let bytes = response.bytes().await.map_err(|e| {
+            error!("Failed to read image {}: {}", url, e);
+            Error::from_request(e)
+        })?;
+        // Clients only render artwork sent with an image type, which storage buckets
+        // behind the CDN don't always set
+        let content_type = match declared_type {
+            Some(declared) if declared.starts_with("image/") => declared,
+            declared => {
+                let sniffed = sniff_image_type(&bytes).unwrap_or("image/jpeg");
+                debug!("Image {} was sent as {:?}, serving it as {}", url, declared, sniffed);
+                sniffed.to_string()
+            }
+        };
+        trace!("Fetched {} bytes of {} from {}", bytes.len(), content_type, url);
+
+        Ok(Image { content_type, bytes })
+    }
+}
+
+/// Recognizes the common image formats by their magic bytes
+fn sniff_image_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use futures::future::join_all;
+
+    #[actix_web::test]
+    async fn concurrent_fetches_are_bounded() {
+        let proxy = ImageProxy::new(2);
+        let in_flight = AtomicUsize::new(0);
+        let most_in_flight = AtomicUsize::new(0);
+
+        let fetches = (0..6).map(|_| {
+            proxy.limited(async {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most_in_flight.fetch_max(now, Ordering::SeqCst);
+                actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            })
+        });
+        join_all(fetches).await;
+
+        assert_eq!(most_in_flight.load(Ordering::SeqCst), 2);
+    }
+}
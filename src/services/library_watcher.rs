@@ -0,0 +1,95 @@
+use std::error;
+use std::time::Duration;
+use actix_web::rt::time;
+use tracing::{debug, error, info};
+use crate::api::ertflix_client::ErtflixClient;
+use crate::services::media_service::MediaService;
+
+/// Polls the Ertflix page content and only refreshes the library when its
+/// fingerprint differs from the last one seen. The last fingerprint is kept in the
+/// media service's cache, so a restarted watcher doesn't refresh an unchanged library.
+#[derive(Default)]
+pub struct LibraryWatcher;
+
+impl LibraryWatcher {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Checks for changes, returning whether a full refresh was performed
+    pub async fn poll<T: ErtflixClient + 'static>(&self, media_service: &MediaService<T>) -> Result<bool, Box<dyn error::Error>> {
+        let fingerprint = media_service.library_fingerprint().await?;
+
+        if media_service.last_library_fingerprint().await.as_ref() == Some(&fingerprint) {
+            debug!("Library fingerprint unchanged, skipping refresh");
+            return Ok(false);
+        }
+
+        info!("Library fingerprint changed, refreshing the library");
+        let report = media_service.refresh_all().await;
+        if !report.is_success() {
+            return Err(format!("refresh failed: {:?}", report.errors).into());
+        }
+
+        media_service.remember_library_fingerprint(&fingerprint).await;
+        Ok(true)
+    }
+
+    /// Polls forever on the given interval, logging failures without giving up
+    pub async fn run<T: ErtflixClient + 'static>(&self, media_service: &MediaService<T>, interval: Duration) {
+        info!("Polling Ertflix for library changes every {}s", interval.as_secs());
+        let mut ticker = time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.poll(media_service).await {
+                error!("Library change detection failed: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock_ertflix_client::MockErtflixClient;
+    use crate::config::Config;
+
+    #[actix_web::test]
+    async fn an_unchanged_fingerprint_skips_the_refresh() {
+        let media_service = MediaService::<MockErtflixClient>::from_config(&Config::default()).await.unwrap();
+        let watcher = LibraryWatcher::new();
+
+        assert!(watcher.poll(&media_service).await.unwrap());
+        let refreshed = media_service.last_refresh().unwrap().finished_at;
+
+        assert!(!watcher.poll(&media_service).await.unwrap());
+        assert_eq!(media_service.last_refresh().unwrap().finished_at, refreshed);
+    }
+
+    #[actix_web::test]
+    async fn a_changed_fingerprint_refreshes_the_library() {
+        let media_service = MediaService::<MockErtflixClient>::from_config(&Config::default()).await.unwrap();
+        let watcher = LibraryWatcher::new();
+        media_service.remember_library_fingerprint("an older library").await;
+
+        assert!(watcher.poll(&media_service).await.unwrap());
+        assert!(media_service.last_refresh().is_some());
+    }
+
+    #[actix_web::test]
+    async fn a_restarted_watcher_remembers_the_last_fingerprint() {
+        let media_service = MediaService::<MockErtflixClient>::from_config(&Config::default()).await.unwrap();
+        assert!(LibraryWatcher::new().poll(&media_service).await.unwrap());
+
+        assert!(!LibraryWatcher::new().poll(&media_service).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn the_fingerprint_of_a_library_never_changes() {
+        let media_service = MediaService::<MockErtflixClient>::from_config(&Config::default()).await.unwrap();
+
+        // Pinned, as fingerprints kept in Redis are compared across releases
+        assert_eq!(media_service.library_fingerprint().await.unwrap(), "bb8c81eaafd657218b18685bf6f8b108");
+    }
+}
@@ -1,8 +1,19 @@
+use std::collections::{BTreeMap, HashSet};
 use std::error;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use futures::stream::{self, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::clock::{Clock, SystemClock};
 use crate::models::ertflix;
 use crate::models::jellyfin;
-use crate::api::ertflix_client::ErtflixClient;
-use log::{debug, error, info, trace, warn};
+use crate::api::ertflix_client::{self, ErtflixClient, SectionContents, Tile};
+use crate::api::rate_limit::RateLimitSnapshot;
+use crate::config;
+use crate::services::cache::{Cache, CacheStatus, Lookup};
+use crate::services::redis_store::RedisStore;
+use tracing::{debug, error, field, info, instrument, trace, warn, Span};
 
 /// # MediaService
 ///
@@ -100,38 +111,265 @@ use log::{debug, error, info, trace, warn};
 /// The `MediaService` is designed to be used in concurrent environments and can safely
 /// handle multiple simultaneous requests for content translation operations.
 pub struct MediaService<T: ErtflixClient> {
-    client: T,
+    client: Arc<T>,
+    cache: Arc<Cache>,
+    /// Swapped when the configuration is reloaded, so TTLs can be tuned while serving
+    cache_config: RwLock<config::CacheConfig>,
+    full_episodes: config::FullEpisodesConfig,
+    stream_url_ttl: Duration,
+    min_year: Option<u32>,
+    merge_movie_parts: bool,
+    remote_trailers: bool,
+    max_collection_children: usize,
+    max_concurrent_requests: usize,
+    last_refresh: Mutex<Option<RefreshReport>>,
+    /// Dates refresh reports
+    clock: Box<dyn Clock>,
 }
 
-impl<DefaultErtflixClient: ErtflixClient> MediaService<DefaultErtflixClient> {
-    /// Creates a new MediaService
-    ///
-    /// # Arguments
-    ///
-    /// * `base_url` - ERTFLIX API base URL
-    pub async fn new(base_url: &str) -> Result<Self, Box<dyn error::Error>> {
-        info!("Creating new MediaService with base URL: {}", base_url);
-        debug!("Initializing ERTFLIX client");
+/// Outcome of `MediaService::refresh_all` and `MediaService::warm_cache`. Every type is
+/// refreshed even when another fails, so a report can show counts for some types and
+/// errors for others.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RefreshReport {
+    pub collections: Option<usize>,
+    pub movies: Option<usize>,
+    pub tv_shows: Option<usize>,
+    /// Error message of each type that failed, keyed like the counts
+    pub errors: BTreeMap<String, String>,
+    pub duration_ms: u64,
+    /// RFC 3339 timestamp of when the refresh completed
+    pub finished_at: String,
+}
+
+impl RefreshReport {
+    fn new(
+        started: Instant,
+        clock: &dyn Clock,
+        collections: Result<usize, Box<dyn error::Error>>,
+        movies: Result<usize, Box<dyn error::Error>>,
+        tv_shows: Result<usize, Box<dyn error::Error>>,
+    ) -> Self {
+        let mut errors = BTreeMap::new();
+        let mut count = |name: &str, result: Result<usize, Box<dyn error::Error>>| match result {
+            Ok(count) => Some(count),
+            Err(e) => {
+                errors.insert(name.to_string(), e.to_string());
+                None
+            }
+        };
+        Self {
+            collections: count("Collections", collections),
+            movies: count("Movies", movies),
+            tv_shows: count("TvShows", tv_shows),
+            errors,
+            duration_ms: started.elapsed().as_millis() as u64,
+            finished_at: clock.now().to_rfc3339(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// How a collection was derived from an ERTFLIX section, as `/admin/mappings` lists it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CollectionMapping {
+    /// The collection's Jellyfin id, sent by clients as `ParentId`
+    pub collection_id: String,
+    pub name: String,
+    pub section_codename: Option<String>,
+    /// `movies` or `tvshows` when the section is one of the configured movie or TV show
+    /// sections, `mixed` for any other
+    pub content_type: &'static str,
+    pub item_count: usize,
+}
 
-        let client = DefaultErtflixClient::new(base_url);
+const MOVIES_CACHE_KEY: &str = "movies";
+const TV_SHOWS_CACHE_KEY: &str = "tv_shows";
+const COLLECTIONS_CACHE_KEY: &str = "collections";
+const LIBRARY_FINGERPRINT_CACHE_KEY: &str = "library_fingerprint";
+
+/// Records how many items a listing returned and how it was served on the current span,
+/// and logs them once as the listing's summary
+fn record_listing(what: &str, item_count: usize, status: CacheStatus) {
+    let span = Span::current();
+    span.record("item_count", item_count);
+    span.record("cache", status.header_value());
+    info!(item_count, cache = status.header_value(), "Retrieved {}", what);
+}
+
+/// The kinds of items the library lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemKind {
+    Movie,
+    Series,
+}
+
+impl<DefaultErtflixClient: ErtflixClient + 'static> MediaService<DefaultErtflixClient> {
+    /// Creates a new MediaService from the configuration, including any
+    /// fallback base URLs and the cache TTLs
+    pub async fn from_config(config: &config::Config) -> Result<Self, Box<dyn error::Error>> {
+        debug!("Initializing ERTFLIX client from configuration");
+        Self::with_client(config, DefaultErtflixClient::from_config(&config.ertflix)).await
+    }
+
+    /// Creates a new MediaService from the configuration, listing the library through
+    /// `client` rather than one built from the configuration
+    pub async fn with_client(config: &config::Config, client: DefaultErtflixClient) -> Result<Self, Box<dyn error::Error>> {
+        info!("Creating new MediaService with base URL: {}", config.ertflix.base_url);
+
+        let mut cache = Cache::new(Duration::from_secs(config.cache.stale_grace_seconds))
+            .with_local_ttl(Duration::from_millis(config.cache.local_ttl_millis));
+        if config.redis.enabled {
+            match RedisStore::new(&config.redis) {
+                Ok(store) => cache = cache.with_redis(store.with_namespace("cache")),
+                Err(e) => warn!("Invalid Redis URL {}, caching in memory instead: {}", config.redis.url, e),
+            }
+        }
+        let cache = Arc::new(cache);
+        let client = client.with_tile_cache(cache.clone(), Duration::from_secs(config.cache.tiles_ttl_seconds));
 
         info!("MediaService successfully created");
-        trace!("MediaService initialization complete");
+        Ok(MediaService {
+            client: Arc::new(client),
+            cache,
+            cache_config: RwLock::new(config.cache.clone()),
+            full_episodes: config.full_episodes.clone(),
+            stream_url_ttl: Duration::from_secs(config.streams.url_ttl_seconds),
+            min_year: config.library.min_year,
+            merge_movie_parts: config.library.merge_movie_parts,
+            remote_trailers: config.library.remote_trailers,
+            max_collection_children: config.library.max_collection_children,
+            max_concurrent_requests: config.ertflix.max_concurrent_requests.max(1),
+            last_refresh: Mutex::new(None),
+            clock: Box::new(SystemClock),
+        })
+    }
+
+    /// The service with its refresh reports dated by `clock`
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Applies the settings of a reloaded configuration that can change while serving:
+    /// the cache TTLs and the sections listed. Cached responses are dropped when the
+    /// sections change, so the next requests list the new ones.
+    pub async fn apply_config(&self, config: &config::Config) {
+        *self.cache_config.write().unwrap() = config.cache.clone();
+        if self.client.reload(&config.ertflix) {
+            self.clear_cache().await;
+        }
+    }
+
+    /// Drops every cached response so the next requests go to ERTFLIX
+    pub async fn clear_cache(&self) {
+        info!("Clearing cached ERTFLIX responses");
+        self.cache.clear().await;
+    }
+
+    /// Fetches collections, movies and TV shows from ERTFLIX again, all at once, and replaces
+    /// the cached ones that were fetched. A failing type doesn't stop the others; its cached
+    /// entry keeps being served and its error is recorded in the report.
+    pub async fn refresh_all(&self) -> RefreshReport {
+        info!("Refreshing the whole library");
+        let report = self.warm_cache().await;
+
+        if report.is_success() {
+            info!("Library refreshed in {}ms: {:?} collections, {:?} movies, {:?} TV shows",
+                  report.duration_ms, report.collections, report.movies, report.tv_shows);
+        } else {
+            error!("Library refresh failed for {:?}", report.errors);
+        }
+        *self.last_refresh.lock().unwrap() = Some(report.clone());
+        report
+    }
+
+    /// Fetches collections, movies and TV shows from ERTFLIX and caches them, even when
+    /// the cached ones are still fresh. Requests keep being served from the cache while
+    /// the fetches run.
+    pub async fn warm_cache(&self) -> RefreshReport {
+        let started = Instant::now();
+        let (sections, movies, tv_shows) =
+            futures::join!(self.load_sections(true), self.load_movies(true), self.load_tv_shows(true));
+        RefreshReport::new(
+            started,
+            self.clock.as_ref(),
+            sections.map(|(sections, _)| sections.len()),
+            movies.map(|(movies, _)| movies.len()),
+            tv_shows.map(|(shows, _)| shows.len()),
+        )
+    }
+
+    /// Checks the dependencies serving requests needs: ERTFLIX and, when the cache is kept
+    /// there, Redis. Maps each dependency to the reason it is down, if it is.
+    pub async fn readiness(&self) -> BTreeMap<&'static str, Result<(), String>> {
+        let mut checks = BTreeMap::new();
+        checks.insert("ertflix", self.client.ping().await.map_err(|e| e.to_string()));
+        if let Some(ping) = self.cache.ping_redis().await {
+            checks.insert("redis", ping.map(|_| ()).map_err(|e| e.to_string()));
+        }
+        for (dependency, check) in &checks {
+            if let Err(e) = check {
+                warn!("Dependency {} is not ready: {}", dependency, e);
+            }
+        }
+        checks
+    }
+
+    /// Rate limits ERTFLIX announced to the client so far
+    pub fn upstream_rate_limits(&self) -> RateLimitSnapshot {
+        self.client.rate_limits()
+    }
 
-        Ok(MediaService { client })
+    /// ERTFLIX requests that timed out or failed without an answer so far
+    pub fn upstream_failures(&self) -> ertflix_client::UpstreamFailures {
+        self.client.upstream_failures()
+    }
+
+    /// The report of the most recent `refresh_all`, if any ran
+    pub fn last_refresh(&self) -> Option<RefreshReport> {
+        self.last_refresh.lock().unwrap().clone()
     }
 
     /// Retrieves TV shows
     pub async fn get_tv_shows(&self) -> Result<Vec<ertflix::TVShow>, Box<dyn error::Error>> {
+        self.get_tv_shows_with_status().await.map(|(shows, _)| shows)
+    }
+
+    /// Retrieves TV shows along with whether they were served from the cache
+    pub async fn get_tv_shows_with_status(&self) -> Result<(Vec<ertflix::TVShow>, CacheStatus), Box<dyn error::Error>> {
+        self.load_tv_shows(false).await
+    }
+
+    /// Retrieves TV shows without their seasons, from ERTFLIX even when they are cached if
+    /// `refresh` is set. `with_seasons` fills in the seasons of the shows that need them.
+    #[instrument(name = "tv_shows", skip(self), fields(item_count = field::Empty, cache = field::Empty, upstream_ms = field::Empty))]
+    async fn load_tv_shows(&self, refresh: bool) -> Result<(Vec<ertflix::TVShow>, CacheStatus), Box<dyn error::Error>> {
         info!("Starting TV shows retrieval");
         trace!("Delegating to ERTFLIX client for TV shows");
 
-        match self.client.get_tv_shows().await {
-            Ok(shows) => {
-                info!("Successfully retrieved {} TV shows", shows.len());
-                debug!("TV shows retrieval completed successfully");
-                trace!("Returning TV shows to caller");
+        let full_episodes = self.full_episodes.clone();
+        match self
+            .cached(TV_SHOWS_CACHE_KEY, self.ttl_seconds(|cache| cache.tv_shows_ttl_seconds), refresh, move |client| async move {
+                let mut shows = client.get_tv_shows().await?;
+                if full_episodes.enabled {
+                    let total = shows.len();
+                    shows.retain(|show| !full_episodes.is_clip_title(&show.title));
+                    debug!("Dropped {} clip tiles from TV shows", total - shows.len());
+                }
                 Ok(shows)
+            })
+            .await
+        {
+            Ok((shows, status)) => {
+                record_listing("TV shows", shows.len(), status);
+                Ok((shows, status))
             }
             Err(e) => {
                 error!("Failed to retrieve TV shows: {}", e);
@@ -143,15 +381,40 @@ impl<DefaultErtflixClient: ErtflixClient> MediaService<DefaultErtflixClient> {
 
     /// Retrieves movies
     pub async fn get_movies(&self) -> Result<Vec<ertflix::Movie>, Box<dyn error::Error>> {
+        self.get_movies_with_status().await.map(|(movies, _)| movies)
+    }
+
+    /// Retrieves movies along with whether they were served from the cache
+    pub async fn get_movies_with_status(&self) -> Result<(Vec<ertflix::Movie>, CacheStatus), Box<dyn error::Error>> {
+        self.load_movies(false).await
+    }
+
+    /// Retrieves movies, from ERTFLIX even when they are cached if `refresh` is set
+    #[instrument(name = "movies", skip(self), fields(item_count = field::Empty, cache = field::Empty, upstream_ms = field::Empty))]
+    async fn load_movies(&self, refresh: bool) -> Result<(Vec<ertflix::Movie>, CacheStatus), Box<dyn error::Error>> {
         info!("Starting movies retrieval");
         trace!("Delegating to ERTFLIX client for movies");
 
-        match self.client.get_movies().await {
-            Ok(movies) => {
-                info!("Successfully retrieved {} movies", movies.len());
-                debug!("Movies retrieval completed successfully");
-                trace!("Returning movies to caller");
+        let min_year = self.min_year;
+        let merge_movie_parts = self.merge_movie_parts;
+        match self
+            .cached(MOVIES_CACHE_KEY, self.ttl_seconds(|cache| cache.movies_ttl_seconds), refresh, move |client| async move {
+                let mut movies = client.get_movies().await?;
+                if let Some(min_year) = min_year {
+                    let total = movies.len();
+                    movies.retain(|movie| movie.year.is_none_or(|year| year >= min_year));
+                    debug!("Dropped {} movies released before {}", total - movies.len(), min_year);
+                }
+                if merge_movie_parts {
+                    movies = ertflix::merge_movie_parts(movies);
+                }
                 Ok(movies)
+            })
+            .await
+        {
+            Ok((movies, status)) => {
+                record_listing("movies", movies.len(), status);
+                Ok((movies, status))
             }
             Err(e) => {
                 error!("Failed to retrieve movies: {}", e);
@@ -160,29 +423,152 @@ impl<DefaultErtflixClient: ErtflixClient> MediaService<DefaultErtflixClient> {
             }
         }
     }
-    
+
+    /// The sections behind the collections, each with its full (capped) tile list
+    async fn get_sections(&self) -> Result<(Vec<SectionContents>, CacheStatus), Box<dyn error::Error>> {
+        self.load_sections(false).await
+    }
+
+    /// The sections behind the collections, from ERTFLIX even when they are cached if `refresh` is set
+    async fn load_sections(&self, refresh: bool) -> Result<(Vec<SectionContents>, CacheStatus), Box<dyn error::Error>> {
+        let max_concurrent_requests = self.max_concurrent_requests;
+        let max_children = self.max_collection_children;
+        self.cached(COLLECTIONS_CACHE_KEY, self.ttl_seconds(|cache| cache.collections_ttl_seconds), refresh, move |client| async move {
+            let sections = client.get_collections(|section_contents| section_contents).await?;
+            Ok(with_full_tiles(&*client, sections, max_concurrent_requests, max_children).await)
+        })
+        .await
+    }
+
+    /// Fetches the movies of any ERTFLIX section, bypassing the cache. Used to debug
+    /// clients against sections other than the movies one.
+    pub async fn get_movies_in_section(&self, section_codename: &str) -> Result<Vec<ertflix::Movie>, Box<dyn error::Error>> {
+        self.get_section_items(section_codename).await
+    }
+
+    /// Fetches the TV shows of any ERTFLIX section, without their seasons, bypassing the cache
+    pub async fn get_tv_shows_in_section(&self, section_codename: &str) -> Result<Vec<ertflix::TVShow>, Box<dyn error::Error>> {
+        self.get_section_items(section_codename).await
+    }
+
+    /// Fills in the seasons of each show, fetching at most `max_concurrent_requests` shows
+    /// at once. Shows whose seasons cannot be fetched are kept without seasons.
+    pub async fn with_seasons(&self, shows: Vec<ertflix::TVShow>) -> Vec<ertflix::TVShow> {
+        stream::iter(shows)
+            .map(|show| self.fill_seasons(show))
+            .buffered(self.max_concurrent_requests)
+            .collect()
+            .await
+    }
+
+    /// Fills in the seasons of a show from their own cache entry or ERTFLIX, dropping clips
+    /// when only full episodes are kept
+    async fn fill_seasons(&self, mut show: ertflix::TVShow) -> ertflix::TVShow {
+        let key = format!("seasons:{}", show.id);
+        if let Lookup::Fresh(seasons) | Lookup::Stale(seasons) = self.cache.get::<Vec<ertflix::Season>>(&key).await {
+            trace!("Cache hit for {}", key);
+            show.seasons = seasons;
+        } else {
+            match self.client.get_show_seasons(show.id.clone()).await {
+                Ok(seasons) => {
+                    let ttl = Duration::from_secs(self.ttl_seconds(|cache| cache.tv_shows_ttl_seconds));
+                    self.cache.set_with_ttl(&key, &seasons, ttl).await;
+                    show.seasons = seasons;
+                }
+                Err(e) => warn!("Failed to fetch the seasons of TV show {}: {}", show.id, e),
+            }
+        }
+        if self.full_episodes.enabled {
+            show.drop_clips(&self.full_episodes);
+        }
+        show.normalize_numbering();
+        show
+    }
+
+    async fn get_section_items<V: From<Tile>>(&self, section_codename: &str) -> Result<Vec<V>, Box<dyn error::Error>> {
+        info!("Fetching the items of section {}", section_codename);
+        let sections = self.client.get_section_content(section_codename.to_string()).await?;
+        let Some(tiles) = sections.into_iter().next().and_then(|section| section.tiles_ids) else {
+            warn!("Section {} lists no tiles", section_codename);
+            return Err(Box::new(ertflix_client::Error::NotFound(section_codename.to_string())));
+        };
+        self.client.get_tiles(tiles).await
+    }
+
+    /// The ERTFLIX ids of the tiles listed in the collection with the given Jellyfin id,
+    /// or `None` when no collection has that id
+    pub async fn get_collection_tile_ids(&self, collection_id: &jellyfin::ItemId) -> Result<Option<Vec<String>>, Box<dyn error::Error>> {
+        let (sections, _) = self.get_sections().await?;
+        let tile_ids = sections
+            .into_iter()
+            .find(|section| jellyfin::ItemId::from_ertflix(&section.section_id.to_string()) == *collection_id)
+            .map(|section| section.tiles_ids.into_iter().flatten().map(|tile| tile.id).collect());
+        trace!("Collection {} lists {:?}", collection_id, tile_ids);
+        Ok(tile_ids)
+    }
+
+    /// Which section each collection was made from and what it is taken to list
+    pub async fn get_collection_mappings(
+        &self,
+        ertflix_config: &config::ErtflixConfig,
+    ) -> Result<Vec<CollectionMapping>, Box<dyn error::Error>> {
+        let (sections, _) = self.get_sections().await?;
+        let mappings = sections
+            .into_iter()
+            .map(|section| {
+                let codename = section.toplist_codename.unwrap_or_default();
+                let section_codename = (!codename.is_empty() && section.section_id != ertflix_client::UNCATEGORIZED_SECTION_ID)
+                    .then(|| codename.clone());
+                let content_type = match &section_codename {
+                    Some(codename) if ertflix_config.movies_section_codenames.contains(codename) => "movies",
+                    Some(codename) if ertflix_config.tv_shows_section_codenames.contains(codename) => "tvshows",
+                    _ => "mixed",
+                };
+                CollectionMapping {
+                    collection_id: jellyfin::ItemId::from_ertflix(&section.section_id.to_string()).to_string(),
+                    name: codename,
+                    section_codename,
+                    content_type,
+                    item_count: section.tiles_ids.map_or(0, |tiles| tiles.len()),
+                }
+            })
+            .collect();
+        Ok(mappings)
+    }
+
+    #[cfg(test)]
     pub async fn get_collections(
         &self,
     ) -> Result<Vec<jellyfin::Collection>, Box<dyn error::Error>> {
+        self.get_collections_with_status().await.map(|(collections, _)| collections)
+    }
+
+    /// Retrieves collections along with whether they were served from the cache
+    #[instrument(name = "collections", skip(self), fields(item_count = field::Empty, cache = field::Empty, upstream_ms = field::Empty))]
+    pub async fn get_collections_with_status(
+        &self,
+    ) -> Result<(Vec<jellyfin::Collection>, CacheStatus), Box<dyn error::Error>> {
         info!("Starting collections retrieval and conversion");
         trace!("Delegating to ERTFLIX client for collections");
 
-        match self
-            .client
-            .get_collections(|section_contents| section_contents)
-            .await
-        {
-            Ok(section_contents) => {
-                debug!("Retrieved {} section contents from ERTFLIX", section_contents.len());
+        match self.get_sections().await {
+            Ok((section_contents, status)) => {
+                debug!("Retrieved {} section contents from ERTFLIX ({:?})", section_contents.len(), status);
                 trace!("Starting conversion from ERTFLIX collections to Jellyfin format");
 
                 let collections: Vec<jellyfin::Collection> = section_contents
                     .into_iter()
-                    .map(|section| {
+                    .map(|section: SectionContents| {
                         trace!("Converting section {} to collection", section.section_id);
                         let ertflix_collection = ertflix::Collection {
                             name: section.toplist_codename.clone().unwrap_or_default(),
                             id: section.section_id.to_string(),
+                            tile_ids: section
+                                .tiles_ids
+                                .iter()
+                                .flatten()
+                                .map(|tile| tile.id.clone())
+                                .collect(),
                         };
                         debug!("Created ERTFLIX collection: {} (ID: {})",
                                ertflix_collection.name, ertflix_collection.id);
@@ -190,10 +576,8 @@ impl<DefaultErtflixClient: ErtflixClient> MediaService<DefaultErtflixClient> {
                     })
                     .collect();
 
-                info!("Successfully converted {} collections to Jellyfin format", collections.len());
-                debug!("Collections conversion completed successfully");
-                trace!("Returning converted collections to caller");
-                Ok(collections)
+                record_listing("collections", collections.len(), status);
+                Ok((collections, status))
             }
             Err(e) => {
                 error!("Failed to retrieve collections: {}", e);
@@ -203,19 +587,391 @@ impl<DefaultErtflixClient: ErtflixClient> MediaService<DefaultErtflixClient> {
         }
     }
 
-    fn convert_to_jellyfin_tv_show(&self, _tv_show: ertflix::TVShow) -> jellyfin::TVShow {
-        // Logic to convert ERTFLIX TV Show to Jellyfin format
-        // This is a placeholder for actual implementation
-        warn!("convert_to_jellyfin_tv_show is not implemented yet");
-        debug!("Placeholder method called for TV show conversion");
-        unimplemented!()
+    /// Searches ERTFLIX and keeps the matches that are movies or TV shows of the library,
+    /// so every hint leads to an item clients can open
+    pub async fn search(&self, query: &str) -> Result<Vec<jellyfin::SearchHint>, Box<dyn error::Error>> {
+        let tiles = self.client.search(query.to_string()).await?;
+        if tiles.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (movies, tv_shows) = futures::try_join!(self.get_movies(), self.get_tv_shows())?;
+
+        let hints: Vec<jellyfin::SearchHint> = tiles
+            .into_iter()
+            .filter_map(|tile| {
+                let (name, item_type) = if let Some(movie) = movies.iter().find(|movie| movie.id == tile.id) {
+                    (movie.title.clone(), "Movie")
+                } else if let Some(show) = tv_shows.iter().find(|show| show.id == tile.id) {
+                    (show.title.clone(), "Series")
+                } else {
+                    trace!("Search match {} is not in the library, skipping it", tile.id);
+                    return None;
+                };
+                Some(jellyfin::SearchHint {
+                    id: jellyfin::ItemId::from_ertflix(&tile.id).into(),
+                    name,
+                    item_type: item_type.into(),
+                })
+            })
+            .collect();
+        debug!("Search for {} gave {} hints", query, hints.len());
+        Ok(hints)
+    }
+
+    /// Retrieves a single movie, from its own cache entry, the cached movie listing,
+    /// or a single-tile fetch, in that order
+    pub async fn get_movie_by_id(&self, id: &str) -> Result<ertflix::Movie, Box<dyn error::Error>> {
+        self.get_item_by_id(id, MOVIES_CACHE_KEY, self.ttl_seconds(|cache| cache.movies_ttl_seconds), |movie: &ertflix::Movie| &movie.id)
+            .await
+    }
+
+    /// Fetches a single movie from ERTFLIX, bypassing every cache, so the answer reflects
+    /// what ERTFLIX serves right now
+    pub async fn fetch_movie(&self, id: &str) -> Result<ertflix::Movie, Box<dyn error::Error>> {
+        self.client.fetch_tile(id.to_string()).await
+    }
+
+    /// Retrieves a single TV show with its seasons. The show comes from its own cache entry,
+    /// the cached TV show listing, or a single-tile fetch, in that order.
+    pub async fn get_show_by_id(&self, id: &str) -> Result<ertflix::TVShow, Box<dyn error::Error>> {
+        let show: ertflix::TVShow = self
+            .get_item_by_id(id, TV_SHOWS_CACHE_KEY, self.ttl_seconds(|cache| cache.tv_shows_ttl_seconds), |show: &ertflix::TVShow| &show.id)
+            .await?;
+        Ok(self.fill_seasons(show).await)
+    }
+
+    async fn get_item_by_id<V>(
+        &self,
+        id: &str,
+        listing_key: &str,
+        ttl_seconds: u64,
+        id_of: fn(&V) -> &str,
+    ) -> Result<V, Box<dyn error::Error>>
+    where
+        V: Serialize + DeserializeOwned + From<Tile>,
+    {
+        let key = format!("{}:{}", listing_key, id);
+        if let Lookup::Fresh(item) | Lookup::Stale(item) = self.cache.get::<V>(&key).await {
+            trace!("Cache hit for {}", key);
+            return Ok(item);
+        }
+
+        if let Lookup::Fresh(items) | Lookup::Stale(items) = self.cache.get::<Vec<V>>(listing_key).await
+            && let Some(item) = items.into_iter().find(|item| id_of(item) == id)
+        {
+            trace!("Found {} in the cached {} listing", id, listing_key);
+            return Ok(item);
+        }
+
+        debug!("{} is not cached, fetching its tile", key);
+        let item: V = self.client.get_tile(id.to_string()).await?;
+        self.cache.set_with_ttl(&key, &item, Duration::from_secs(ttl_seconds)).await;
+        Ok(item)
+    }
+
+    /// Resolves the playback manifest URL of a movie or TV show, reusing a previously
+    /// resolved URL until it expires. Expired URLs are never served, even within the grace window.
+    pub async fn get_stream_url(&self, item_id: &jellyfin::ItemId) -> Result<String, Box<dyn error::Error>> {
+        let key = format!("stream_url:{}", item_id);
+        if let Lookup::Fresh(url) = self.cache.get::<String>(&key).await {
+            trace!("Using cached stream URL for {}", item_id);
+            return Ok(url);
+        }
+
+        let codename = self.find_listed(item_id, |movie| movie.codename, |show| show.codename).await?;
+        let url = self.client.get_stream_url(codename).await?;
+        self.cache.set_with_ttl(&key, &url, self.stream_url_ttl).await;
+        Ok(url)
+    }
+
+    /// The media source clients play an item from, pointing at its ERTFLIX stream
+    pub async fn get_media_source(&self, item_id: &jellyfin::ItemId) -> Result<jellyfin::MediaSource, Box<dyn error::Error>> {
+        let title = self.find_listed(item_id, |movie| movie.title, |show| show.title).await?;
+        let url = self.get_stream_url(item_id).await?;
+        debug!("Media source of {} streams from {}", item_id, url);
+        Ok(jellyfin::MediaSource::stream(item_id, title, url))
+    }
+
+    /// Resolves the URL of an item's artwork of the given Jellyfin image type, failing
+    /// with `Error::NotFound` when the item is unknown or has no such image
+    pub async fn get_image_url(&self, item_id: &jellyfin::ItemId, image_type: &str) -> Result<String, Box<dyn error::Error>> {
+        let images = self.find_listed(item_id, |movie| movie.images, |show| show.images).await?;
+        match images.get(image_type) {
+            Some(url) => Ok(url.to_string()),
+            None => {
+                debug!("Item {} has no {} image", item_id, image_type);
+                Err(Box::new(ertflix_client::Error::NotFound(format!("{}/{}", item_id, image_type))))
+            }
+        }
+    }
+
+    /// Finds the kind and ERTFLIX id of a listed item by its Jellyfin id, looking only
+    /// among items of `kind` when it is given
+    pub async fn find_item(
+        &self,
+        item_id: &jellyfin::ItemId,
+        kind: Option<ItemKind>,
+    ) -> Result<(ItemKind, String), Box<dyn error::Error>> {
+        if kind != Some(ItemKind::Series)
+            && let Some(movie) = self.get_movies().await?.into_iter().find(|movie| jellyfin::ItemId::from_ertflix(&movie.id) == *item_id)
+        {
+            return Ok((ItemKind::Movie, movie.id));
+        }
+        if kind != Some(ItemKind::Movie)
+            && let Some(show) = self.get_tv_shows().await?.into_iter().find(|show| jellyfin::ItemId::from_ertflix(&show.id) == *item_id)
+        {
+            return Ok((ItemKind::Series, show.id));
+        }
+        debug!("No {:?} item with id {}", kind, item_id);
+        Err(Box::new(ertflix_client::Error::NotFound(item_id.to_string())))
     }
 
-    fn convert_to_jellyfin_movie(&self, _movie: ertflix::Movie) -> jellyfin::Movie {
-        // Logic to convert ERTFLIX Movie to Jellyfin format
-        // This is a placeholder for actual implementation
-        warn!("convert_to_jellyfin_movie is not implemented yet");
-        debug!("Placeholder method called for movie conversion");
-        unimplemented!()
+    /// Jellyfin ids of every listed movie and TV show
+    pub async fn library_item_ids(&self) -> Result<HashSet<jellyfin::ItemId>, Box<dyn error::Error>> {
+        let movies = self.get_movies().await?;
+        let tv_shows = self.get_tv_shows().await?;
+        Ok(movies
+            .iter()
+            .map(|movie| &movie.id)
+            .chain(tv_shows.iter().map(|show| &show.id))
+            .map(|id| jellyfin::ItemId::from_ertflix(id))
+            .collect())
     }
+
+    /// Finds a listed movie or TV show by its Jellyfin id and takes what the caller needs from it
+    async fn find_listed<R>(
+        &self,
+        item_id: &jellyfin::ItemId,
+        from_movie: impl FnOnce(ertflix::Movie) -> R,
+        from_show: impl FnOnce(ertflix::TVShow) -> R,
+    ) -> Result<R, Box<dyn error::Error>> {
+        let movies = self.get_movies().await?;
+        if let Some(movie) = movies.into_iter().find(|movie| jellyfin::ItemId::from_ertflix(&movie.id) == *item_id) {
+            return Ok(from_movie(movie));
+        }
+        self.get_tv_shows()
+            .await?
+            .into_iter()
+            .find(|show| jellyfin::ItemId::from_ertflix(&show.id) == *item_id)
+            .map(from_show)
+            .ok_or_else(|| Box::new(ertflix_client::Error::NotFound(item_id.to_string())) as Box<dyn error::Error>)
+    }
+
+    /// A TTL of the current cache configuration. The lock is released before returning,
+    /// so it is never held across an `await`.
+    fn ttl_seconds(&self, ttl: fn(&config::CacheConfig) -> u64) -> u64 {
+        ttl(&self.cache_config.read().unwrap())
+    }
+
+    /// Serves `key` from the cache when fresh, or when stale but within the grace window
+    /// while `fetch` refreshes it in the background. Otherwise, or always when `refresh`
+    /// is set, fetches and caches it.
+    async fn cached<V, F, Fut>(
+        &self,
+        key: &'static str,
+        ttl_seconds: u64,
+        refresh: bool,
+        fetch: F,
+    ) -> Result<(V, CacheStatus), Box<dyn error::Error>>
+    where
+        V: Serialize + DeserializeOwned + 'static,
+        F: FnOnce(Arc<DefaultErtflixClient>) -> Fut + 'static,
+        Fut: Future<Output = Result<V, Box<dyn error::Error>>> + 'static,
+    {
+        let ttl = Duration::from_secs(ttl_seconds);
+
+        let lookup = if refresh { Lookup::Miss } else { self.cache.get::<V>(key).await };
+        match lookup {
+            Lookup::Fresh(value) => return Ok((value, CacheStatus::Hit)),
+            Lookup::Stale(value) => {
+                self.revalidate(key, ttl, fetch);
+                return Ok((value, CacheStatus::Revalidating));
+            }
+            Lookup::Miss => {}
+        }
+
+        let started = Instant::now();
+        let value = fetch(self.client.clone()).await?;
+        Span::current().record("upstream_ms", started.elapsed().as_millis() as u64);
+        self.cache.set_with_ttl(key, &value, ttl).await;
+        Ok((value, CacheStatus::Miss))
+    }
+
+    /// Refreshes a stale entry in the background, unless a refresh is already running
+    fn revalidate<V, F, Fut>(&self, key: &'static str, ttl: Duration, fetch: F)
+    where
+        V: Serialize + 'static,
+        F: FnOnce(Arc<DefaultErtflixClient>) -> Fut + 'static,
+        Fut: Future<Output = Result<V, Box<dyn error::Error>>> + 'static,
+    {
+        if !self.cache.begin_revalidation(key) {
+            trace!("Revalidation of {} already in progress", key);
+            return;
+        }
+
+        debug!("Serving stale {} while revalidating in the background", key);
+        let client = self.client.clone();
+        let cache = self.cache.clone();
+        actix_web::rt::spawn(async move {
+            match fetch(client).await {
+                Ok(value) => cache.set_with_ttl(key, &value, ttl).await,
+                Err(e) => warn!("Background revalidation of {} failed: {}", key, e),
+            }
+            cache.end_revalidation(key);
+        });
+    }
+
+    /// Computes a cheap fingerprint of the library from the page content alone, hashing
+    /// each section's id and tile ids without fetching tile details. The hash is stable
+    /// across restarts and builds, so a fingerprint kept in Redis stays comparable.
+    pub async fn library_fingerprint(&self) -> Result<String, Box<dyn error::Error>> {
+        trace!("Computing library fingerprint from page content");
+        let sections = self
+            .client
+            .get_collections(|section_contents| section_contents)
+            .await?;
+
+        // Every variable-length part is length-prefixed, so no two libraries encode alike
+        let mut encoded = Vec::new();
+        for section in &sections {
+            let tiles: Vec<&Tile> = section.tiles_ids.iter().flatten().collect();
+            encoded.extend(section.section_id.to_le_bytes());
+            encoded.extend((tiles.len() as u64).to_le_bytes());
+            for tile in tiles {
+                encoded.extend((tile.id.len() as u64).to_le_bytes());
+                encoded.extend(tile.id.as_bytes());
+            }
+        }
+        let fingerprint = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, &encoded).simple().to_string();
+        debug!("Library fingerprint over {} sections: {}", sections.len(), fingerprint);
+        Ok(fingerprint)
+    }
+
+    /// The library fingerprint the last refresh was made for, kept in the cache so it
+    /// outlives restarts of the watcher and, with Redis, of the process
+    pub async fn last_library_fingerprint(&self) -> Option<String> {
+        match self.cache.get::<String>(LIBRARY_FINGERPRINT_CACHE_KEY).await {
+            Lookup::Fresh(fingerprint) | Lookup::Stale(fingerprint) => Some(fingerprint),
+            Lookup::Miss => None,
+        }
+    }
+
+    /// Records the fingerprint of the library a refresh was made for. It is kept as long as
+    /// the longest-lived listing, as it describes what the cache holds.
+    pub async fn remember_library_fingerprint(&self, fingerprint: &str) {
+        let ttl_seconds = self.ttl_seconds(|cache| {
+            cache.collections_ttl_seconds.max(cache.movies_ttl_seconds).max(cache.tv_shows_ttl_seconds)
+        });
+        self.cache
+            .set_with_ttl(LIBRARY_FINGERPRINT_CACHE_KEY, &fingerprint, Duration::from_secs(ttl_seconds))
+            .await;
+    }
+
+    /// Converts an ERTFLIX TV show to a Jellyfin series with default user data. Shows
+    /// without seasons convert to a series with an empty `Seasons` list.
+    pub fn convert_to_jellyfin_tv_show(&self, tv_show: ertflix::TVShow) -> jellyfin::TVShow {
+        trace!("Converting TV show {} with {} seasons to Jellyfin format", tv_show.id, tv_show.seasons.len());
+        let item_id = jellyfin::ItemId::from_ertflix(&tv_show.id);
+        let seasons: Vec<jellyfin::Season> = tv_show
+            .seasons
+            .into_iter()
+            .map(|season| {
+                let mut season = jellyfin::Season::from(season);
+                season.id = jellyfin::item_guid(&format!("{}:season:{}", tv_show.id, season.season_number));
+                season
+            })
+            .collect();
+        jellyfin::TVShow {
+            user_data: jellyfin::UserData::for_item(&item_id),
+            provider_ids: jellyfin::ertflix_provider_ids(&tv_show.id),
+            id: item_id.into(),
+            title: tv_show.title.clone(),
+            server_id: config::identity().server_id.clone(),
+            item_type: "Series".into(),
+            // What Jellyfin reports for folders, which have no media of their own
+            media_type: "Unknown".into(),
+            is_folder: true,
+            child_count: seasons.len() as i32,
+            seasons,
+            overview: tv_show.description,
+            image_tags: jellyfin::ImageTags::from_urls(&tv_show.images),
+            backdrop_image_tags: jellyfin::backdrop_image_tags(&tv_show.images),
+            poster_url: tv_show.images.primary.unwrap_or_default(),
+            tags: tv_show.tags,
+            remote_trailers: self.trailers_of(tv_show.trailer_url, &tv_show.title),
+            ..Default::default()
+        }
+    }
+
+    /// Converts an ERTFLIX movie to the Jellyfin item clients expect, with default user data
+    pub fn convert_to_jellyfin_movie(&self, movie: ertflix::Movie) -> jellyfin::Movie {
+        trace!("Converting movie {} to Jellyfin format", movie.id);
+        let item_id = jellyfin::ItemId::from_ertflix(&movie.id);
+        jellyfin::Movie {
+            user_data: jellyfin::UserData::for_item(&item_id),
+            provider_ids: jellyfin::ertflix_provider_ids(&movie.id),
+            id: item_id.into(),
+            title: movie.title.clone(),
+            server_id: config::identity().server_id.clone(),
+            item_type: "Movie".into(),
+            media_type: "Video".into(),
+            is_folder: false,
+            year: movie.year.map(|year| year as i32),
+            genre_items: movie.genre.iter().map(|genre| jellyfin::NameGuidPair::genre(genre)).collect(),
+            genre: movie.genre,
+            overview: movie.description,
+            image_tags: jellyfin::ImageTags::from_urls(&movie.images),
+            backdrop_image_tags: jellyfin::backdrop_image_tags(&movie.images),
+            poster_url: movie.images.primary.unwrap_or_default(),
+            tags: movie.tags,
+            remote_trailers: self.trailers_of(movie.trailer_url, &movie.title),
+            part_count: (!movie.parts.is_empty()).then_some(movie.parts.len()),
+            media_sources: movie.parts.iter().map(jellyfin::MediaSource::from).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The `RemoteTrailers` of an item, left empty when trailers are turned off
+    fn trailers_of(&self, trailer_url: Option<String>, title: &str) -> Vec<jellyfin::MediaUrl> {
+        if !self.remote_trailers {
+            return Vec::new();
+        }
+        jellyfin::remote_trailers(trailer_url, title)
+    }
+}
+
+/// The page content only lists the first few tiles of each section. Replaces them with the
+/// section's full tile list, so child counts are accurate, fetching at most `limit` sections
+/// at once. Sections that cannot be fetched keep their page content tiles. Each section keeps
+/// at most `max_children` tiles, so very large sections stay cheap to list and cache.
+async fn with_full_tiles<C: ErtflixClient>(
+    client: &C,
+    sections: Vec<SectionContents>,
+    limit: usize,
+    max_children: usize,
+) -> Vec<SectionContents> {
+    stream::iter(sections)
+        .map(|mut section| async move {
+            // The uncategorized collection is named after the configuration, not an ERTFLIX section
+            let Some(codename) = section.toplist_codename.clone().filter(|_| section.section_id != ertflix_client::UNCATEGORIZED_SECTION_ID) else {
+                return section;
+            };
+            match client.get_section_content(codename).await {
+                Ok(full) => {
+                    if let Some(mut tiles) = full.into_iter().next().and_then(|full| full.tiles_ids) {
+                        trace!("Section {} has {} tiles", section.section_id, tiles.len());
+                        if tiles.len() > max_children {
+                            debug!("Capping section {} at {} of its {} tiles", section.section_id, max_children, tiles.len());
+                            tiles.truncate(max_children);
+                        }
+                        section.tiles_ids = Some(tiles);
+                    }
+                }
+                Err(e) => warn!("Failed to fetch the full contents of section {}: {}", section.section_id, e),
+            }
+            section
+        })
+        .buffered(limit)
+        .collect()
+        .await
 }
@@ -1 +1,12 @@
+pub mod cache;
+pub mod cache_warmer;
+pub mod idempotency;
+pub mod image_proxy;
+pub mod library_watcher;
 pub mod media_service;
+pub mod redis_store;
+pub mod sessions;
+pub mod stream_prefetch;
+pub mod stream_tracker;
+pub mod supervisor;
+pub mod user_data;
@@ -0,0 +1,121 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use actix_web::web;
+use tracing::{debug, trace};
+use redis::{Client, Connection, ErrorKind, RedisError, RedisResult};
+use tokio::sync::Semaphore;
+use crate::config::RedisConfig;
+
+/// Prefix of every key the adapter writes, so clearing never touches other data in the database
+const KEY_PREFIX: &str = "ertflix-2-jellyfin:";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Plain string storage in Redis with expiring keys.
+///
+/// Keeps up to `connection_pool_size` connections, opened on first use and reused
+/// afterwards. Commands run on the blocking thread pool so they never stall a worker.
+pub struct RedisStore {
+    client: Client,
+    /// Prepended to every key, `KEY_PREFIX` followed by the namespace if there is one
+    prefix: String,
+    idle: Mutex<Vec<Connection>>,
+    permits: Semaphore,
+}
+
+impl RedisStore {
+    /// Only parses the URL; connections are opened when the first command is sent
+    pub fn new(config: &RedisConfig) -> RedisResult<Self> {
+        debug!("Using Redis at {} with up to {} connections", config.url, config.connection_pool_size);
+        Ok(Self {
+            client: Client::open(config.url.as_str())?,
+            prefix: KEY_PREFIX.to_string(),
+            idle: Mutex::new(Vec::new()),
+            permits: Semaphore::new(config.connection_pool_size.max(1) as usize),
+        })
+    }
+
+    /// Keeps the keys apart from those of stores in other namespaces, which `clear` leaves alone
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.prefix = format!("{KEY_PREFIX}{namespace}:");
+        self
+    }
+
+    pub async fn get(&self, key: &str) -> RedisResult<Option<String>> {
+        let key = format!("{}{key}", self.prefix);
+        self.with_connection(move |connection| redis::cmd("GET").arg(key).query(connection)).await
+    }
+
+    /// Stores `value` under `key`, letting Redis drop it after `ttl`
+    pub async fn set(&self, key: &str, value: String, ttl: Duration) -> RedisResult<()> {
+        let key = format!("{}{key}", self.prefix);
+        let ttl_ms = ttl.as_millis().max(1) as u64;
+        self.with_connection(move |connection| {
+            redis::cmd("SET").arg(key).arg(value).arg("PX").arg(ttl_ms).query(connection)
+        })
+        .await
+    }
+
+    /// Stores `value` under `key` like `set`, unless the key is present. Returns whether it was stored.
+    pub async fn set_if_absent(&self, key: &str, value: String, ttl: Duration) -> RedisResult<bool> {
+        let key = format!("{}{key}", self.prefix);
+        let ttl_ms = ttl.as_millis().max(1) as u64;
+        self.with_connection(move |connection| {
+            let stored: Option<String> = redis::cmd("SET").arg(key).arg(value).arg("PX").arg(ttl_ms).arg("NX").query(connection)?;
+            Ok(stored.is_some())
+        })
+        .await
+    }
+
+    /// Deletes every key the store has stored in its namespace
+    pub async fn clear(&self) -> RedisResult<()> {
+        let pattern = format!("{}*", self.prefix);
+        self.with_connection(move |connection| {
+            let keys: Vec<String> = redis::cmd("KEYS").arg(pattern).query(connection)?;
+            trace!("Deleting {} Redis keys", keys.len());
+            if keys.is_empty() {
+                return Ok(());
+            }
+            redis::cmd("DEL").arg(keys).query(connection)
+        })
+        .await
+    }
+
+    pub async fn ping(&self) -> RedisResult<String> {
+        self.with_connection(|connection| redis::cmd("PING").query(connection)).await
+    }
+
+    /// Runs `command` on an idle connection, or a new one when none is idle. Connections
+    /// that fail a command are dropped rather than returned to the pool.
+    async fn with_connection<R, F>(&self, command: F) -> RedisResult<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Connection) -> RedisResult<R> + Send + 'static,
+    {
+        let _permit = self.permits.acquire().await.expect("the Redis connection semaphore is never closed");
+        let idle = self.idle.lock().unwrap().pop();
+        let client = self.client.clone();
+
+        let (connection, result) = web::block(move || {
+            let mut connection = match idle {
+                Some(connection) => connection,
+                None => {
+                    trace!("Opening a new Redis connection");
+                    let connection = client.get_connection_with_timeout(CONNECT_TIMEOUT)?;
+                    connection.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+                    connection.set_write_timeout(Some(COMMAND_TIMEOUT))?;
+                    connection
+                }
+            };
+            let result = command(&mut connection);
+            Ok::<_, RedisError>((connection, result))
+        })
+        .await
+        .map_err(|e| RedisError::from((ErrorKind::IoError, "Redis command was not run", e.to_string())))??;
+
+        if result.is_ok() {
+            self.idle.lock().unwrap().push(connection);
+        }
+        result
+    }
+}
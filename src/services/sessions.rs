@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use chrono::{DateTime, Utc};
+use tracing::{debug, info};
+use crate::clock::{Clock, SystemClock};
+
+/// A client session created by a successful authentication
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: String,
+    pub access_token: String,
+    pub user_id: String,
+    pub user_name: String,
+    pub client: String,
+    pub device_name: String,
+    pub device_id: String,
+    pub application_version: String,
+    pub created: DateTime<Utc>,
+}
+
+/// In-memory registry of active sessions
+pub struct SessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+    /// Session id of every issued access token
+    tokens: RwLock<HashMap<String, String>>,
+    clock: Box<dyn Clock>,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::with_clock(Box::new(SystemClock))
+    }
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        debug!("Creating session store");
+        Self::default()
+    }
+
+    /// A store whose sessions are dated by `clock`
+    pub fn with_clock(clock: Box<dyn Clock>) -> Self {
+        Self { sessions: RwLock::default(), tokens: RwLock::default(), clock }
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    pub fn insert(&self, session: Session) {
+        info!("Session {} started for {} on {}", session.id, session.client, session.device_name);
+        self.tokens.write().unwrap().insert(session.access_token.clone(), session.id.clone());
+        self.sessions.write().unwrap().insert(session.id.clone(), session);
+    }
+
+    /// The active session an access token was issued to
+    pub fn find_by_token(&self, token: &str) -> Option<Session> {
+        let session_id = self.tokens.read().unwrap().get(token).cloned()?;
+        self.sessions.read().unwrap().get(&session_id).cloned()
+    }
+
+    /// Active sessions, oldest first
+    pub fn list(&self) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self.sessions.read().unwrap().values().cloned().collect();
+        sessions.sort_by_key(|session| session.created);
+        sessions
+    }
+
+    /// Ends a session and forgets its token, so requests carrying it are refused from
+    /// then on. Returns the session if it existed.
+    pub fn revoke(&self, session_id: &str) -> Option<Session> {
+        let session = self.sessions.write().unwrap().remove(session_id)?;
+        info!("Session {} revoked", session_id);
+        self.tokens.write().unwrap().remove(&session.access_token);
+        Some(session)
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+use actix_web::rt::time;
+use futures::stream::{self, StreamExt};
+use tracing::{debug, info, warn};
+use crate::api::ertflix_client::ErtflixClient;
+use crate::services::media_service::MediaService;
+use crate::services::user_data::UserDataStore;
+
+/// Resolves the stream URLs of the user's favorites so playback can start without
+/// waiting on ERTFLIX, resolving at most `concurrency` URLs at a time.
+/// Returns how many URLs were resolved.
+pub async fn prefetch_favorites<T: ErtflixClient + 'static>(
+    media_service: &MediaService<T>,
+    user_data_store: &UserDataStore,
+    user_id: &str,
+    concurrency: usize,
+) -> usize {
+    let favorites = user_data_store.favorites(user_id);
+    debug!("Prefetching stream URLs for {} favorites of user {}", favorites.len(), user_id);
+
+    stream::iter(favorites)
+        .map(|item_id| async move {
+            match media_service.get_stream_url(&item_id).await {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("Failed to prefetch stream URL for {}: {}", item_id, e);
+                    false
+                }
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .filter(|resolved| std::future::ready(*resolved))
+        .count()
+        .await
+}
+
+/// Prefetches the user's favorites forever on the given interval
+pub async fn run<T: ErtflixClient + 'static>(
+    media_service: &MediaService<T>,
+    user_data_store: &UserDataStore,
+    user_id: &str,
+    concurrency: usize,
+    interval: Duration,
+) {
+    info!("Prefetching favorite stream URLs every {}s", interval.as_secs());
+    let mut ticker = time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+        let resolved = prefetch_favorites(media_service, user_data_store, user_id, concurrency).await;
+        debug!("Prefetched {} favorite stream URLs", resolved);
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, trace};
+
+/// Counts active playback streams.
+///
+/// Streams are keyed by play session (or item when the client sends no session id)
+/// and expire when nothing is heard from them within the timeout, since clients
+/// do not always report that playback stopped.
+pub struct StreamTracker {
+    timeout: Duration,
+    streams: Mutex<HashMap<String, Instant>>,
+}
+
+impl StreamTracker {
+    pub fn new(timeout: Duration) -> Self {
+        debug!("Creating stream tracker with a timeout of {}s", timeout.as_secs());
+        Self {
+            timeout,
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a started stream, or refreshes it if it is already active
+    pub fn start(&self, key: &str) {
+        let mut streams = self.streams.lock().unwrap();
+        if streams.insert(key.to_string(), Instant::now()).is_none() {
+            info!("Stream {} started, {} active", key, streams.len());
+        }
+    }
+
+    /// Keeps a stream alive, starting it if it was not known
+    pub fn touch(&self, key: &str) {
+        trace!("Stream {} is still playing", key);
+        self.start(key);
+    }
+
+    pub fn stop(&self, key: &str) {
+        let mut streams = self.streams.lock().unwrap();
+        if streams.remove(key).is_some() {
+            info!("Stream {} stopped, {} active", key, streams.len());
+        }
+    }
+
+    /// Drops streams that have not reported within the timeout, returning how many were removed
+    pub fn sweep(&self) -> usize {
+        let mut streams = self.streams.lock().unwrap();
+        let before = streams.len();
+        streams.retain(|_, last_seen| last_seen.elapsed() < self.timeout);
+        let removed = before - streams.len();
+        if removed > 0 {
+            info!("Expired {} streams that stopped reporting, {} active", removed, streams.len());
+        }
+        removed
+    }
+
+    pub fn active(&self) -> usize {
+        self.streams.lock().unwrap().len()
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
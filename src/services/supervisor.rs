@@ -0,0 +1,138 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use futures::FutureExt;
+use tracing::{debug, error, info};
+use serde::Serialize;
+
+/// What `/admin/health` reports about a supervised background task
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TaskHealth {
+    pub running: bool,
+    pub restarts: u32,
+    pub last_panic: Option<String>,
+}
+
+/// Keeps background tasks alive. A task that panics is logged and started again after a
+/// backoff that doubles with each consecutive panic, up to `max_backoff`. The backoff
+/// starts over once a restarted task has run for longer than `max_backoff`.
+pub struct Supervisor {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    tasks: Arc<Mutex<BTreeMap<String, TaskHealth>>>,
+}
+
+impl Supervisor {
+    pub fn new(initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            initial_backoff,
+            max_backoff,
+            tasks: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Spawns the task made by `task` under supervision, making a fresh one for every restart
+    pub fn spawn<F, Fut>(&self, name: &str, task: F)
+    where
+        F: FnMut() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        actix_web::rt::spawn(self.supervise(name, task));
+    }
+
+    /// Runs the task until it returns, restarting it whenever it panics
+    fn supervise<F, Fut>(&self, name: &str, mut task: F) -> impl Future<Output = ()> + 'static
+    where
+        F: FnMut() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let name = name.to_string();
+        let tasks = self.tasks.clone();
+        let (initial_backoff, max_backoff) = (self.initial_backoff, self.max_backoff);
+
+        async move {
+            let update = |change: &dyn Fn(&mut TaskHealth)| change(tasks.lock().unwrap().entry(name.clone()).or_default());
+            let mut backoff = initial_backoff;
+            loop {
+                debug!("Starting background task {}", name);
+                update(&|health| health.running = true);
+                let started = Instant::now();
+
+                let Err(panic) = AssertUnwindSafe(task()).catch_unwind().await else {
+                    info!("Background task {} finished", name);
+                    update(&|health| health.running = false);
+                    return;
+                };
+
+                let message = panic_message(panic.as_ref());
+                if started.elapsed() > max_backoff {
+                    backoff = initial_backoff;
+                }
+                error!("Background task {} panicked: {}; restarting in {}ms", name, message, backoff.as_millis());
+                update(&|health| {
+                    health.running = false;
+                    health.restarts += 1;
+                    health.last_panic = Some(message.clone());
+                });
+
+                actix_web::rt::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+            }
+        }
+    }
+
+    /// Health of every task spawned so far, by name
+    pub fn health(&self) -> BTreeMap<String, TaskHealth> {
+        self.tasks.lock().unwrap().clone()
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[actix_web::test]
+    async fn a_task_that_panics_once_is_restarted() {
+        let supervisor = Supervisor::new(Duration::from_millis(1), Duration::from_millis(10));
+        let runs = Rc::new(Cell::new(0));
+
+        let counted = runs.clone();
+        supervisor
+            .supervise("flaky", move || {
+                let runs = counted.clone();
+                async move {
+                    runs.set(runs.get() + 1);
+                    if runs.get() == 1 {
+                        panic!("first run fails");
+                    }
+                }
+            })
+            .await;
+
+        assert_eq!(runs.get(), 2);
+        let health = &supervisor.health()["flaky"];
+        assert_eq!(health.restarts, 1);
+        assert_eq!(health.last_panic.as_deref(), Some("first run fails"));
+        assert!(!health.running);
+    }
+}
@@ -0,0 +1,148 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use chrono::{DateTime, Utc};
+use tracing::{debug, trace};
+use crate::clock::{Clock, SystemClock};
+use crate::models::jellyfin::ItemId;
+
+/// Per-user state for a single item, as toggled by the client
+#[derive(Debug, Clone, Default)]
+pub struct UserItemData {
+    pub is_favorite: bool,
+    pub played: bool,
+    pub play_count: i32,
+    pub playback_position_ticks: i64,
+    pub last_played_date: Option<DateTime<Utc>>,
+}
+
+/// In-memory store of per-user item state, keyed by user id and then item id
+pub struct UserDataStore {
+    users: RwLock<HashMap<String, HashMap<ItemId, UserItemData>>>,
+    clock: Box<dyn Clock>,
+}
+
+impl Default for UserDataStore {
+    fn default() -> Self {
+        Self::with_clock(Box::new(SystemClock))
+    }
+}
+
+impl UserDataStore {
+    pub fn new() -> Self {
+        debug!("Creating user data store");
+        Self::default()
+    }
+
+    /// A store that dates plays by `clock`
+    pub fn with_clock(clock: Box<dyn Clock>) -> Self {
+        Self { users: RwLock::default(), clock }
+    }
+
+    /// Returns the stored state for an item, or the default state if nothing was recorded
+    pub fn get(&self, user_id: &str, item_id: &ItemId) -> UserItemData {
+        let users = self.users.read().unwrap();
+        users
+            .get(user_id)
+            .and_then(|items| items.get(item_id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Applies `update` to the item's state and returns the new state
+    pub fn update<F>(&self, user_id: &str, item_id: &ItemId, update: F) -> UserItemData
+    where
+        F: FnOnce(&mut UserItemData),
+    {
+        let mut users = self.users.write().unwrap();
+        let data = users
+            .entry(user_id.to_string())
+            .or_default()
+            .entry(item_id.clone())
+            .or_default();
+        update(data);
+        trace!("Updated user data for user {} item {}: {:?}", user_id, item_id, data);
+        data.clone()
+    }
+
+    /// Ids of the items the user marked as favorite
+    pub fn favorites(&self, user_id: &str) -> Vec<ItemId> {
+        let users = self.users.read().unwrap();
+        users
+            .get(user_id)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter(|(_, data)| data.is_favorite)
+                    .map(|(item_id, _)| item_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Marks or unmarks an item as a favorite for the user
+    pub fn set_favorite(&self, user_id: &str, item_id: &ItemId, is_favorite: bool) -> UserItemData {
+        debug!("Setting favorite={} for user {} item {}", is_favorite, user_id, item_id);
+        self.update(user_id, item_id, |data| data.is_favorite = is_favorite)
+    }
+
+    /// Marks an item as played, bumping its play count, or resets it to unplayed
+    pub fn set_played(&self, user_id: &str, item_id: &ItemId, played: bool) -> UserItemData {
+        debug!("Setting played={} for user {} item {}", played, user_id, item_id);
+        let now = self.clock.now();
+        self.update(user_id, item_id, |data| {
+            data.playback_position_ticks = 0;
+            if played {
+                data.played = true;
+                data.play_count += 1;
+                data.last_played_date = Some(now);
+            } else {
+                data.played = false;
+                data.play_count = 0;
+            }
+        })
+    }
+
+    /// Records how far into an item the user has watched
+    pub fn set_playback_position(&self, user_id: &str, item_id: &ItemId, position_ticks: i64) -> UserItemData {
+        debug!("Setting playback position {} for user {} item {}", position_ticks, user_id, item_id);
+        let now = self.clock.now();
+        self.update(user_id, item_id, |data| {
+            data.playback_position_ticks = position_ticks;
+            data.last_played_date = Some(now);
+        })
+    }
+
+    /// Ids of items the user has started but not finished, most recently watched first
+    pub fn in_progress(&self, user_id: &str) -> Vec<ItemId> {
+        let users = self.users.read().unwrap();
+        let mut items: Vec<(&ItemId, &UserItemData)> = users
+            .get(user_id)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter(|(_, data)| data.playback_position_ticks > 0 && !data.played)
+                    .collect()
+            })
+            .unwrap_or_default();
+        items.sort_by_key(|(_, data)| Reverse(data.last_played_date));
+        items.into_iter().map(|(item_id, _)| item_id.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    #[test]
+    fn plays_are_dated_by_the_clock() {
+        let clock = FixedClock::at("2024-05-01T12:30:00Z");
+        let played_at = clock.now();
+        let store = UserDataStore::with_clock(Box::new(clock));
+        let item = ItemId::from_ertflix("movie");
+
+        assert_eq!(store.set_playback_position("user", &item, 10).last_played_date, Some(played_at));
+        assert_eq!(store.set_played("user", &item, true).last_played_date, Some(played_at));
+    }
+}